@@ -1,12 +1,15 @@
 //! FUSE application binary interface for `polyfuse`.
 //!
-//! The binding is compatible with ABI 7.31 (in libfuse 3.10.1).
+//! The binding is compatible with ABI 7.38 (in Linux 6.5).
+//! In addition, the definitions for the passthrough mode introduced
+//! in ABI 7.40 (Linux 6.9) are also provided.
 
 #![allow(nonstandard_style, clippy::identity_op)]
 
 use std::convert::TryFrom;
 use std::error;
 use std::fmt;
+use std::mem;
 use zerocopy::AsBytes;
 use zerocopy::FromBytes;
 
@@ -14,7 +17,7 @@ use zerocopy::FromBytes;
 pub const FUSE_KERNEL_VERSION: u32 = 7;
 
 /// The minor version number of FUSE protocol.
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 38;
 
 /// The minimum length of read buffer.
 pub const FUSE_MIN_READ_BUFFER: u32 = 8192;
@@ -34,6 +37,7 @@ pub const FATTR_ATIME_NOW: u32 = 1 << 7;
 pub const FATTR_MTIME_NOW: u32 = 1 << 8;
 pub const FATTR_LOCKOWNER: u32 = 1 << 9;
 pub const FATTR_CTIME: u32 = 1 << 10;
+pub const FATTR_KILL_SUIDGID: u32 = 1 << 11;
 
 // Flags returned by the OPEN request.
 pub const FOPEN_DIRECT_IO: u32 = 1 << 0;
@@ -41,6 +45,9 @@ pub const FOPEN_KEEP_CACHE: u32 = 1 << 1;
 pub const FOPEN_NONSEEKABLE: u32 = 1 << 2;
 pub const FOPEN_CACHE_DIR: u32 = 1 << 3;
 pub const FOPEN_STREAM: u32 = 1 << 4;
+pub const FOPEN_NOFLUSH: u32 = 1 << 5;
+pub const FOPEN_PARALLEL_DIRECT_WRITES: u32 = 1 << 6;
+pub const FOPEN_PASSTHROUGH: u32 = 1 << 7;
 
 // INIT request/reply flags.
 pub const FUSE_ASYNC_READ: u32 = 1;
@@ -69,6 +76,21 @@ pub const FUSE_MAX_PAGES: u32 = 1 << 22;
 pub const FUSE_CACHE_SYMLINKS: u32 = 1 << 23;
 pub const FUSE_NO_OPENDIR_SUPPORT: u32 = 1 << 24;
 pub const FUSE_EXPLICIT_INVAL_DATA: u32 = 1 << 25;
+pub const FUSE_MAP_ALIGNMENT: u32 = 1 << 26;
+pub const FUSE_SUBMOUNTS: u32 = 1 << 27;
+pub const FUSE_HANDLE_KILLPRIV_V2: u32 = 1 << 28;
+pub const FUSE_SETXATTR_EXT: u32 = 1 << 29;
+pub const FUSE_INIT_EXT: u32 = 1 << 30;
+pub const FUSE_INIT_RESERVED: u32 = 1 << 31;
+
+// INIT request/reply flags stored in the `flags2` field.
+//
+// These are only transferred when `FUSE_INIT_EXT` is set in `flags`.
+pub const FUSE_SECURITY_CTX: u64 = 1 << 32;
+pub const FUSE_HAS_INODE_DAX: u64 = 1 << 33;
+pub const FUSE_CREATE_SUPP_GROUP: u64 = 1 << 34;
+pub const FUSE_HAS_EXPIRE_ONLY: u64 = 1 << 35;
+pub const FUSE_PASSTHROUGH: u64 = 1 << 37;
 
 // CUSE INIT request/reply flags.
 pub const CUSE_UNRESTRICTED_IOCTL: u32 = 1 << 0;
@@ -86,7 +108,8 @@ pub const FUSE_LK_FLOCK: u32 = 1 << 0;
 // WRITE flags.
 pub const FUSE_WRITE_CACHE: u32 = 1 << 0;
 pub const FUSE_WRITE_LOCKOWNER: u32 = 1 << 1;
-pub const FUSE_WRITE_KILL_PRIV: u32 = 1 << 2;
+pub const FUSE_WRITE_KILL_SUIDGID: u32 = 1 << 2;
+pub const FUSE_WRITE_KILL_PRIV: u32 = FUSE_WRITE_KILL_SUIDGID;
 
 // Read flags.
 pub const FUSE_READ_LOCKOWNER: u32 = 1 << 1;
@@ -105,6 +128,23 @@ pub const FUSE_POLL_SCHEDULE_NOTIFY: u32 = 1 << 0;
 // Fsync flags.
 pub const FUSE_FSYNC_FDATASYNC: u32 = 1 << 0;
 
+// fuse_attr flags.
+pub const FUSE_ATTR_SUBMOUNT: u32 = 1 << 0;
+pub const FUSE_ATTR_DAX: u32 = 1 << 1;
+
+// Open flags.
+pub const FUSE_OPEN_KILL_SUIDGID: u32 = 1 << 0;
+
+// Setxattr flags.
+pub const FUSE_SETXATTR_ACL_KILL_SGID: u32 = 1 << 0;
+
+// Notify inval entry flags.
+pub const FUSE_EXPIRE_ONLY: u32 = 1 << 0;
+
+// Extension types.
+pub const FUSE_MAX_NR_SECCTX: u32 = 31;
+pub const FUSE_EXT_GROUPS: u32 = 32;
+
 // misc
 pub const FUSE_COMPAT_ENTRY_OUT_SIZE: usize = 120;
 pub const FUSE_COMPAT_ATTR_OUT_SIZE: usize = 96;
@@ -113,8 +153,75 @@ pub const FUSE_COMPAT_WRITE_IN_SIZE: usize = 24;
 pub const FUSE_COMPAT_STATFS_SIZE: usize = 48;
 pub const FUSE_COMPAT_INIT_OUT_SIZE: usize = 8;
 pub const FUSE_COMPAT_22_INIT_OUT_SIZE: usize = 24;
+pub const FUSE_COMPAT_SETXATTR_IN_SIZE: usize = 8;
 pub const CUSE_INIT_INFO_MAX: u32 = 4096;
 
+// Device ioctls.
+//
+// The request codes are encoded as `_IOC` of Linux, whose layout depends on the architecture.
+pub const FUSE_DEV_IOC_MAGIC: u32 = 229;
+/// `_IOR(FUSE_DEV_IOC_MAGIC, 0, uint32_t)`
+#[cfg(target_os = "linux")]
+pub const FUSE_DEV_IOC_CLONE: u32 = ioc::ior(FUSE_DEV_IOC_MAGIC, 0, mem::size_of::<u32>());
+/// `_IOW(FUSE_DEV_IOC_MAGIC, 1, struct fuse_backing_map)`
+#[cfg(target_os = "linux")]
+pub const FUSE_DEV_IOC_BACKING_OPEN: u32 =
+    ioc::iow(FUSE_DEV_IOC_MAGIC, 1, mem::size_of::<fuse_backing_map>());
+/// `_IOW(FUSE_DEV_IOC_MAGIC, 2, uint32_t)`
+#[cfg(target_os = "linux")]
+pub const FUSE_DEV_IOC_BACKING_CLOSE: u32 = ioc::iow(FUSE_DEV_IOC_MAGIC, 2, mem::size_of::<u32>());
+
+#[cfg(target_os = "linux")]
+mod ioc {
+    #[cfg(any(
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "sparc",
+        target_arch = "sparc64"
+    ))]
+    mod arch {
+        pub const SIZEBITS: u32 = 13;
+        pub const WRITE: u32 = 4;
+        pub const READ: u32 = 2;
+    }
+
+    #[cfg(not(any(
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "sparc",
+        target_arch = "sparc64"
+    )))]
+    mod arch {
+        pub const SIZEBITS: u32 = 14;
+        pub const WRITE: u32 = 1;
+        pub const READ: u32 = 2;
+    }
+
+    const fn ioc(dir: u32, ty: u32, nr: u32, size: usize) -> u32 {
+        (dir << (16 + arch::SIZEBITS)) | ((size as u32) << 16) | (ty << 8) | nr
+    }
+
+    pub(crate) const fn ior(ty: u32, nr: u32, size: usize) -> u32 {
+        ioc(arch::READ, ty, nr, size)
+    }
+
+    pub(crate) const fn iow(ty: u32, nr: u32, size: usize) -> u32 {
+        ioc(arch::WRITE, ty, nr, size)
+    }
+}
+
+#[derive(Clone, Copy, Default, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct fuse_backing_map {
+    pub fd: i32,
+    pub flags: u32,
+    pub padding: u64,
+}
+
 #[derive(Clone, Copy, Default, FromBytes, AsBytes)]
 #[repr(C)]
 pub struct fuse_attr {
@@ -133,7 +240,7 @@ pub struct fuse_attr {
     pub gid: u32,
     pub rdev: u32,
     pub blksize: u32,
-    pub padding: u32,
+    pub flags: u32,
 }
 
 #[derive(Clone, Copy, Default, FromBytes, AsBytes)]
@@ -259,6 +366,10 @@ define_opcode! {
     FUSE_RENAME2 = 45,
     FUSE_LSEEK = 46,
     FUSE_COPY_FILE_RANGE = 47,
+    FUSE_SETUPMAPPING = 48,
+    FUSE_REMOVEMAPPING = 49,
+    FUSE_SYNCFS = 50,
+    FUSE_TMPFILE = 51,
 
     CUSE_INIT = 4096,
 }
@@ -285,7 +396,8 @@ pub struct fuse_in_header {
     pub uid: u32,
     pub gid: u32,
     pub pid: u32,
-    pub padding: u32,
+    pub total_extlen: u16,
+    pub padding: u16,
 }
 
 #[derive(Clone, Copy, Default, FromBytes, AsBytes)]
@@ -295,6 +407,8 @@ pub struct fuse_init_in {
     pub minor: u32,
     pub max_readahead: u32,
     pub flags: u32,
+    pub flags2: u32,
+    pub unused: [u32; 11],
 }
 
 #[derive(Clone, Copy, Default, FromBytes, AsBytes)]
@@ -364,7 +478,7 @@ pub struct fuse_link_in {
 #[repr(C)]
 pub struct fuse_open_in {
     pub flags: u32,
-    pub unused: u32,
+    pub open_flags: u32,
 }
 
 #[derive(Clone, Copy, Default, FromBytes, AsBytes)]
@@ -429,6 +543,8 @@ pub struct fuse_getxattr_in {
 pub struct fuse_setxattr_in {
     pub size: u32,
     pub flags: u32,
+    pub setxattr_flags: u32,
+    pub padding: u32,
 }
 
 #[derive(Clone, Copy, Default, FromBytes, AsBytes)]
@@ -454,7 +570,7 @@ pub struct fuse_create_in {
     pub flags: u32,
     pub mode: u32,
     pub umask: u32,
-    pub padding: u32,
+    pub open_flags: u32,
 }
 
 #[derive(Clone, Copy, Default, FromBytes, AsBytes)]
@@ -506,8 +622,10 @@ pub struct fuse_init_out {
     pub max_write: u32,
     pub time_gran: u32,
     pub max_pages: u16,
-    pub padding: u16,
-    pub unused: [u32; 8],
+    pub map_alignment: u16,
+    pub flags2: u32,
+    pub max_stack_depth: u32,
+    pub unused: [u32; 6],
 }
 
 impl Default for fuse_init_out {
@@ -522,8 +640,10 @@ impl Default for fuse_init_out {
             max_write: 0,
             time_gran: 0,
             max_pages: 0,
-            padding: 0,
-            unused: [0; 8],
+            map_alignment: 0,
+            flags2: 0,
+            max_stack_depth: 0,
+            unused: [0; 6],
         }
     }
 }
@@ -540,7 +660,7 @@ pub struct fuse_getxattr_out {
 pub struct fuse_open_out {
     pub fh: u64,
     pub open_flags: u32,
-    pub padding: u32,
+    pub backing_id: i32,
 }
 
 #[derive(Clone, Copy, Default, FromBytes, AsBytes)]
@@ -725,7 +845,7 @@ pub struct fuse_notify_inval_inode_out {
 pub struct fuse_notify_inval_entry_out {
     pub parent: u64,
     pub namelen: u32,
-    pub padding: u32,
+    pub flags: u32,
 }
 
 #[derive(Clone, Copy, Default, FromBytes, AsBytes)]
@@ -767,6 +887,40 @@ pub struct fuse_notify_retrieve_in {
     pub dummy4: u64,
 }
 
+#[derive(Clone, Copy, Default, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct fuse_syncfs_in {
+    pub padding: u64,
+}
+
+#[derive(Clone, Copy, Default, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct fuse_secctx {
+    pub size: u32,
+    pub padding: u32,
+}
+
+#[derive(Clone, Copy, Default, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct fuse_secctx_header {
+    pub size: u32,
+    pub nr_secctx: u32,
+}
+
+#[derive(Clone, Copy, Default, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct fuse_ext_header {
+    pub size: u32,
+    pub typ: u32,
+}
+
+#[derive(Clone, Copy, Default, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct fuse_supp_groups {
+    pub nr_groups: u32,
+    pub groups: [u32; 0],
+}
+
 #[derive(Clone, Copy, Default, FromBytes, AsBytes)]
 #[repr(C)]
 pub struct cuse_init_in {
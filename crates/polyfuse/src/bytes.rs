@@ -9,6 +9,10 @@ use std::os::unix::prelude::*;
 /// This difference is due to the requirement of FUSE kernel driver that all data in
 /// a reply message must be passed in a single `write(2)` syscall.
 ///
+/// This trait is implemented for the common containers of bytes, including
+/// the borrowed and shared ones such as `Cow<[u8]>`, `Arc<[u8]>` and `Rc<[u8]>`,
+/// so that the cached contents can be replied without copying.
+///
 /// [`bytes`]: https://docs.rs/bytes/0.6/bytes
 /// [`Buf`]: https://docs.rs/bytes/0.6/bytes/trait.Buf.html
 pub trait Bytes {
@@ -24,6 +28,9 @@ pub trait Bytes {
     /// the number of byte chunks is acquired from `Bytes::count` and the implementation
     /// needs to add all chunks in `dst`.
     ///
+    /// The chunks must be exactly `count` in number and `size` in total length.
+    /// Otherwise, the reply is not sent and fails with `io::ErrorKind::InvalidData`.
+    ///
     /// [bytes_vectored]: https://docs.rs/bytes/0.6/bytes/trait.Buf.html#method.bytes_vectored
     fn fill_bytes<'a>(&'a self, dst: &mut dyn FillBytes<'a>);
 }
@@ -34,6 +41,28 @@ pub trait FillBytes<'a> {
     fn put(&mut self, chunk: &'a [u8]);
 }
 
+/// Encode the bytes into a contiguous buffer.
+///
+/// The reply header is generated on each call of `Request::reply`,
+/// so the encoded payload can be cached (e.g. as `Arc<[u8]>`) and replied
+/// to the subsequent requests as is, with only the unique ID differing.
+pub fn encode<T>(bytes: &T) -> Vec<u8>
+where
+    T: Bytes + ?Sized,
+{
+    struct Encode(Vec<u8>);
+
+    impl FillBytes<'_> for Encode {
+        fn put(&mut self, chunk: &[u8]) {
+            self.0.extend_from_slice(chunk);
+        }
+    }
+
+    let mut encode = Encode(Vec::with_capacity(bytes.size()));
+    bytes.fill_bytes(&mut encode);
+    encode.0
+}
+
 // ==== pointer types ====
 
 macro_rules! impl_reply_body_for_pointers {
@@ -315,6 +344,25 @@ mod impl_scattered_bytes_for_cont {
     }
 }
 
+/// The gathered segments are passed to the kernel without concatenating them,
+/// e.g. `&[IoSlice<'_>]` as the payload of `Notifier::store`.
+impl Bytes for std::io::IoSlice<'_> {
+    #[inline]
+    fn size(&self) -> usize {
+        Bytes::size(&**self)
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        Bytes::count(&**self)
+    }
+
+    #[inline]
+    fn fill_bytes<'a>(&'a self, dst: &mut dyn FillBytes<'a>) {
+        Bytes::fill_bytes(&**self, dst)
+    }
+}
+
 impl Bytes for std::ffi::OsStr {
     #[inline]
     fn size(&self) -> usize {
@@ -348,3 +396,58 @@ impl Bytes for std::ffi::OsString {
         Bytes::fill_bytes(self.as_bytes(), dst)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{borrow::Cow, io::IoSlice, rc::Rc, sync::Arc};
+
+    struct Collect<'a>(Vec<&'a [u8]>);
+
+    impl<'a> FillBytes<'a> for Collect<'a> {
+        fn put(&mut self, chunk: &'a [u8]) {
+            self.0.push(chunk);
+        }
+    }
+
+    fn to_vec<T: Bytes + ?Sized>(bytes: &T) -> Vec<u8> {
+        let mut collect = Collect(vec![]);
+        bytes.fill_bytes(&mut collect);
+        assert_eq!(collect.0.len(), bytes.count());
+        let data = collect.0.concat();
+        assert_eq!(data.len(), bytes.size());
+        data
+    }
+
+    #[test]
+    fn shared_and_borrowed_bytes() {
+        let content: &[u8] = b"hello";
+
+        assert_eq!(to_vec(&Cow::Borrowed(content)), content);
+        assert_eq!(to_vec(&Cow::<[u8]>::Owned(content.to_vec())), content);
+        assert_eq!(to_vec(&Arc::<[u8]>::from(content)), content);
+        assert_eq!(to_vec(&Rc::<[u8]>::from(content)), content);
+
+        let empty = Arc::<[u8]>::from(&[][..]);
+        assert_eq!(empty.count(), 0);
+        assert!(to_vec(&empty).is_empty());
+    }
+
+    #[test]
+    fn encode_scattered() {
+        let data = (&b"foo"[..], vec![&b"bar"[..], &b"baz"[..]]);
+        assert_eq!(encode(&data), b"foobarbaz");
+        assert_eq!(encode(&()), b"");
+    }
+
+    #[test]
+    fn io_slices() {
+        let slices = [
+            IoSlice::new(b"hello"),
+            IoSlice::new(b""),
+            IoSlice::new(b", world"),
+        ];
+        assert_eq!(to_vec(&slices[..]), b"hello, world");
+        assert_eq!(slices[..].count(), 2, "empty segments are skipped");
+    }
+}
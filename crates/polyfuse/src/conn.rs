@@ -1,17 +1,35 @@
 use libc::{c_int, c_void, iovec};
 use std::{
     cmp,
-    ffi::{OsStr, OsString},
+    ffi::{CString, OsString},
     io,
-    mem::{self, MaybeUninit},
     os::unix::{net::UnixStream, prelude::*},
     path::{Path, PathBuf},
-    process::{Command, ExitStatus},
+    process::ExitStatus,
+};
+
+#[cfg(not(target_os = "freebsd"))]
+use std::{
+    ffi::OsStr,
+    mem::{self, MaybeUninit},
+    process::Command,
     ptr,
 };
 
-const FUSERMOUNT_PROG: &str = "/usr/bin/fusermount";
+// The names of the mount helper, in the order of preference.
+#[cfg(not(target_os = "freebsd"))]
+const FUSERMOUNT_PROGS: &[&str] = &["fusermount3", "fusermount"];
+#[cfg(not(target_os = "freebsd"))]
+const FUSERMOUNT_PATH_ENV: &str = "POLYFUSE_FUSERMOUNT";
+#[cfg(not(target_os = "freebsd"))]
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+#[cfg(not(target_os = "freebsd"))]
 const FUSE_COMMFD_ENV: &str = "_FUSE_COMMFD";
+const FUSECTL_DIR: &str = "/sys/fs/fuse/connections";
+
+const FUSE_DEV_PATH: &str = "/dev/fuse";
+
+const CUSE_DEV_PATH: &str = "/dev/cuse";
 
 macro_rules! syscall {
     ($fn:ident ( $($arg:expr),* $(,)* ) ) => {{
@@ -29,7 +47,7 @@ macro_rules! syscall {
 pub struct Connection {
     fd: RawFd,
     child: Option<Fusermount>,
-    mountpoint: PathBuf,
+    mountpoint: Option<PathBuf>,
     mountopts: MountOptions,
 }
 
@@ -41,16 +59,247 @@ impl Drop for Connection {
 
 impl Connection {
     /// Establish a connection with the FUSE kernel driver.
-    pub(crate) fn open(mountpoint: PathBuf, mountopts: MountOptions) -> io::Result<Self> {
+    pub(crate) fn open(mountpoint: PathBuf, mut mountopts: MountOptions) -> io::Result<Self> {
+        #[cfg(not(target_os = "freebsd"))]
+        {
+            if mountopts.direct_mount {
+                match mount_direct(&mountpoint, &mountopts) {
+                    Ok(fd) => {
+                        return Ok(Self {
+                            fd,
+                            child: None,
+                            mountpoint: Some(mountpoint),
+                            mountopts,
+                        })
+                    }
+                    // Lacking CAP_SYS_ADMIN, fall back to the mount helper.
+                    Err(err) if err.raw_os_error() == Some(libc::EPERM) => {
+                        mountopts.direct_mount = false;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
         let (fd, child) = mount(&mountpoint, &mountopts)?;
         Ok(Self {
             fd,
             child,
-            mountpoint,
+            mountpoint: Some(mountpoint),
             mountopts,
         })
     }
 
+    /// Create a new connection attached to the same FUSE session.
+    ///
+    /// The cloned connection has its own request processing queue in the kernel,
+    /// and the replies must be sent through the connection the corresponding
+    /// request was read from.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn try_clone_device(&self) -> io::Result<Self> {
+        let path = CString::new(FUSE_DEV_PATH).unwrap();
+        let fd = syscall! { open(path.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) };
+
+        let mut master = self.fd as u32;
+        let res = unsafe {
+            libc::ioctl(
+                fd,
+                polyfuse_kernel::FUSE_DEV_IOC_CLONE as _,
+                &mut master as *mut u32,
+            )
+        };
+        if res == -1 {
+            let err = io::Error::last_os_error();
+            unsafe {
+                libc::close(fd);
+            }
+            return Err(err);
+        }
+
+        Ok(Self {
+            fd,
+            child: None,
+            mountpoint: None,
+            mountopts: MountOptions::default(),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn try_clone_device(&self) -> io::Result<Self> {
+        Err(io::Error::from_raw_os_error(libc::ENOTSUP))
+    }
+
+    /// Register a file as the backing file of the passthrough mode,
+    /// and return its backing ID.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn open_backing(&self, fd: RawFd) -> io::Result<u32> {
+        let map = polyfuse_kernel::fuse_backing_map {
+            fd,
+            flags: 0,
+            padding: 0,
+        };
+        let id = syscall! {
+            ioctl(
+                self.fd,
+                polyfuse_kernel::FUSE_DEV_IOC_BACKING_OPEN as _,
+                &map as *const polyfuse_kernel::fuse_backing_map,
+            )
+        };
+        Ok(id as u32)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn open_backing(&self, _: RawFd) -> io::Result<u32> {
+        Err(io::Error::from_raw_os_error(libc::ENOTSUP))
+    }
+
+    /// Unregister the backing file associated with the specified ID.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn close_backing(&self, backing_id: u32) -> io::Result<()> {
+        syscall! {
+            ioctl(
+                self.fd,
+                polyfuse_kernel::FUSE_DEV_IOC_BACKING_CLOSE as _,
+                &backing_id as *const u32,
+            )
+        };
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn close_backing(&self, _: u32) -> io::Result<()> {
+        Err(io::Error::from_raw_os_error(libc::ENOTSUP))
+    }
+
+    /// Return whether the filesystem is mounted with `default_permissions`.
+    pub(crate) fn default_permissions(&self) -> bool {
+        self.mountopts.default_permissions()
+    }
+
+    /// Return whether the filesystem is mounted read-only.
+    pub(crate) fn read_only(&self) -> bool {
+        self.mountopts.read_only()
+    }
+
+    /// Abort the connection via the FUSE control filesystem.
+    pub(crate) fn abort(&self) -> io::Result<()> {
+        std::fs::write(self.fusectl_path("abort")?, b"1")
+    }
+
+    /// Read the attribute of the connection in the FUSE control filesystem, e.g. `max_background`.
+    pub(crate) fn read_fusectl(&self, name: &str) -> io::Result<String> {
+        let content = std::fs::read_to_string(self.fusectl_path(name)?)?;
+        Ok(content.trim_end().to_owned())
+    }
+
+    /// Return whether the filesystem is still mounted at the mountpoint.
+    pub(crate) fn is_mounted(&self) -> io::Result<bool> {
+        match self.connection_id() {
+            Ok(..) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Return the path of the specified file in the directory of the FUSE control
+    /// filesystem associated with this connection.
+    fn fusectl_path(&self, name: &str) -> io::Result<PathBuf> {
+        let id = self.connection_id()?;
+        Ok(Path::new(FUSECTL_DIR).join(id.to_string()).join(name))
+    }
+
+    /// Return the ID of this connection, which names its directory in the FUSE
+    /// control filesystem.
+    ///
+    /// The connection is located by the device number of the mounted filesystem
+    /// found in `/proc/self/mountinfo`, rather than by `stat(2)` on the mountpoint,
+    /// which would issue a request to the (possibly wedged) filesystem itself.
+    #[cfg(not(target_os = "freebsd"))]
+    fn connection_id(&self) -> io::Result<u64> {
+        let mountpoint = self.mountpoint.as_ref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "the connection is not mounted")
+        })?;
+        let mountpoint = if mountpoint.is_absolute() {
+            mountpoint.clone()
+        } else {
+            std::env::current_dir()?.join(mountpoint)
+        };
+
+        let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+        find_connection_id(&mountinfo, &mountpoint).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "the FUSE connection is not found in /proc/self/mountinfo",
+            )
+        })
+    }
+
+    #[cfg(target_os = "freebsd")]
+    fn connection_id(&self) -> io::Result<u64> {
+        Err(io::Error::from_raw_os_error(libc::ENOTSUP))
+    }
+
+    /// Establish a connection with the CUSE kernel driver.
+    ///
+    /// Unlike FUSE, no mount operation is required since the kernel creates
+    /// the character device after the initialization handshake.
+    pub(crate) fn open_cuse() -> io::Result<Self> {
+        let path = CString::new(CUSE_DEV_PATH).unwrap();
+        let fd = syscall! { open(path.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) };
+        Ok(Self {
+            fd,
+            child: None,
+            mountpoint: None,
+            mountopts: MountOptions::default(),
+        })
+    }
+
+    /// Create a connection from a file descriptor of the FUSE device that has
+    /// already been mounted, or an arbitrary one emulating it in the tests.
+    ///
+    /// The connection takes the ownership of the file descriptor, and never
+    /// unmounts the filesystem.
+    pub(crate) fn from_raw_fd(fd: RawFd) -> Self {
+        Self {
+            fd,
+            child: None,
+            mountpoint: None,
+            mountopts: MountOptions::default(),
+        }
+    }
+
+    /// Switch the device file descriptor into or out of the non-blocking mode.
+    pub(crate) fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        let flags = syscall! { fcntl(self.fd, libc::F_GETFL) };
+        let flags = if nonblocking {
+            flags | libc::O_NONBLOCK
+        } else {
+            flags & !libc::O_NONBLOCK
+        };
+        syscall! { fcntl(self.fd, libc::F_SETFL, flags) };
+        Ok(())
+    }
+
+    /// Return whether the file descriptor is a stream socket.
+    ///
+    /// Unlike the FUSE device, which delivers a whole message by a single `read`,
+    /// a stream transport (e.g. a forwarded connection passed to `Session::from_raw_fd`)
+    /// may split a message into pieces.
+    pub(crate) fn is_stream(&self) -> bool {
+        let mut ty: c_int = 0;
+        let mut len = std::mem::size_of::<c_int>() as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockopt(
+                self.fd,
+                libc::SOL_SOCKET,
+                libc::SO_TYPE,
+                &mut ty as *mut c_int as *mut c_void,
+                &mut len,
+            )
+        };
+        res == 0 && ty == libc::SOCK_STREAM
+    }
+
     fn read(&self, dst: &mut [u8]) -> io::Result<usize> {
         let len = syscall! {
             read(
@@ -104,7 +353,9 @@ impl Connection {
             let _ = child.wait();
         }
 
-        unmount(&self.mountpoint);
+        if let Some(mountpoint) = &self.mountpoint {
+            unmount(mountpoint, &self.mountopts);
+        }
     }
 }
 
@@ -174,12 +425,30 @@ impl io::Write for &Connection {
 
 // ==== mount ====
 
-#[derive(Debug)]
+impl MountOptions {
+    pub(crate) fn default_permissions(&self) -> bool {
+        self.options.iter().any(|opt| opt == "default_permissions")
+    }
+
+    pub(crate) fn read_only(&self) -> bool {
+        // The last one of `ro` and `rw` takes effect.
+        let last = self
+            .options
+            .iter()
+            .rev()
+            .find(|opt| *opt == "ro" || *opt == "rw");
+        matches!(last.map(String::as_str), Some("ro"))
+    }
+}
+
+#[derive(Debug, Clone)]
 pub(crate) struct MountOptions {
     pub(crate) options: Vec<String>,
     pub(crate) auto_unmount: bool,
     pub(crate) fusermount_path: Option<PathBuf>,
+    pub(crate) direct_mount: bool,
     pub(crate) fuse_comm_fd: Option<OsString>,
+    pub(crate) blkdev: Option<PathBuf>,
 }
 
 impl Default for MountOptions {
@@ -188,17 +457,21 @@ impl Default for MountOptions {
             options: vec![],
             auto_unmount: true,
             fusermount_path: None,
+            direct_mount: false,
             fuse_comm_fd: None,
+            blkdev: None,
         }
     }
 }
 
 #[derive(Debug)]
+#[cfg_attr(target_os = "freebsd", allow(dead_code))]
 struct Fusermount {
     pid: c_int,
     input: UnixStream,
 }
 
+#[cfg_attr(target_os = "freebsd", allow(dead_code))]
 impl Fusermount {
     fn wait(self) -> io::Result<ExitStatus> {
         drop(self.input);
@@ -208,32 +481,13 @@ impl Fusermount {
     }
 }
 
+#[cfg(not(target_os = "freebsd"))]
 fn mount(mountpoint: &Path, mountopts: &MountOptions) -> io::Result<(RawFd, Option<Fusermount>)> {
     let (input, output) = UnixStream::pair()?;
 
-    let mut fusermount = Command::new(
-        mountopts
-            .fusermount_path
-            .as_deref()
-            .unwrap_or_else(|| Path::new(FUSERMOUNT_PROG)),
-    );
+    let mut fusermount = Command::new(fusermount_prog(mountopts));
 
-    let opts = mountopts
-        .options
-        .iter()
-        .map(|opt| opt.as_str())
-        .chain(if mountopts.auto_unmount {
-            Some("auto_unmount")
-        } else {
-            None
-        })
-        .fold(String::new(), |mut opts, opt| {
-            if !opts.is_empty() {
-                opts.push(',');
-            }
-            opts.push_str(&opt);
-            opts
-        });
+    let opts = fusermount_options(mountopts)?;
     if !opts.is_empty() {
         fusermount.arg("-o").arg(opts);
     }
@@ -291,13 +545,272 @@ fn mount(mountpoint: &Path, mountopts: &MountOptions) -> io::Result<(RawFd, Opti
     }
 }
 
-fn unmount(mountpoint: &Path) {
-    let _ = Command::new(FUSERMOUNT_PROG)
+/// Build the value of the `-o` option passed to `fusermount`.
+#[cfg(not(target_os = "freebsd"))]
+fn fusermount_options(mountopts: &MountOptions) -> io::Result<String> {
+    let blkdev = match mountopts.blkdev {
+        Some(ref device) => {
+            let device = device.to_str().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the path to the block device must be valid UTF-8",
+                )
+            })?;
+            // `fusermount` uses `fsname` as the source of the block device mount.
+            let device = device.replace('\\', "\\\\").replace(',', "\\,");
+            vec!["blkdev".to_owned(), format!("fsname={}", device)]
+        }
+        None => vec![],
+    };
+
+    Ok(mountopts
+        .options
+        .iter()
+        .chain(&blkdev)
+        .map(|opt| opt.as_str())
+        .chain(if mountopts.auto_unmount {
+            Some("auto_unmount")
+        } else {
+            None
+        })
+        .fold(String::new(), |mut opts, opt| {
+            if !opts.is_empty() {
+                opts.push(',');
+            }
+            opts.push_str(&opt);
+            opts
+        }))
+}
+
+/// Find the ID of the FUSE connection mounted at the specified path from the content
+/// of `/proc/self/mountinfo`.
+///
+/// The ID is the device number of the mounted filesystem in the kernel's internal
+/// encoding, which names the directory under `/sys/fs/fuse/connections`.
+#[cfg(not(target_os = "freebsd"))]
+fn find_connection_id(mountinfo: &str, mountpoint: &Path) -> Option<u64> {
+    // The last entry is the topmost one if multiple filesystems are stacked.
+    mountinfo.lines().rev().find_map(|line| {
+        // <id> <parent> <major>:<minor> <root> <mountpoint> <options> [<optional>...] - <fstype> ...
+        let mut fields = line.split(' ');
+        let devno = fields.nth(2)?;
+        let path = fields.nth(1)?;
+        let fstype = fields.skip_while(|&f| f != "-").nth(1)?;
+
+        let is_fuse = ["fuse", "fuseblk"]
+            .iter()
+            .any(|t| fstype == *t || fstype.starts_with(&format!("{}.", t)));
+        if !is_fuse || Path::new(OsStr::from_bytes(&unescape_mountinfo(path))) != mountpoint {
+            return None;
+        }
+
+        let mut devno = devno.splitn(2, ':');
+        let major: u64 = devno.next()?.parse().ok()?;
+        let minor: u64 = devno.next()?.parse().ok()?;
+        Some((major << 20) | minor)
+    })
+}
+
+/// Decode the octal escapes (e.g. `\040` for a space) in a path of `/proc/self/mountinfo`.
+#[cfg(not(target_os = "freebsd"))]
+fn unescape_mountinfo(s: &str) -> Vec<u8> {
+    let s = s.as_bytes();
+    let mut unescaped = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        let escaped = s.get(i + 1..i + 4).and_then(|oct| {
+            let oct = std::str::from_utf8(oct).ok()?;
+            u8::from_str_radix(oct, 8).ok()
+        });
+        match escaped {
+            Some(b) if s[i] == b'\\' => {
+                unescaped.push(b);
+                i += 4;
+            }
+            _ => {
+                unescaped.push(s[i]);
+                i += 1;
+            }
+        }
+    }
+    unescaped
+}
+
+/// Determine the path of the mount helper.
+///
+/// The path specified by `KernelConfig::fusermount_path` or the environment
+/// variable `POLYFUSE_FUSERMOUNT` takes precedence.  Otherwise, `fusermount3`
+/// (shipped with libfuse 3.x) and then `fusermount` are searched in `PATH`.
+#[cfg(not(target_os = "freebsd"))]
+fn fusermount_prog(mountopts: &MountOptions) -> PathBuf {
+    if let Some(path) = &mountopts.fusermount_path {
+        return path.clone();
+    }
+    find_fusermount(
+        std::env::var_os(FUSERMOUNT_PATH_ENV),
+        std::env::var_os("PATH"),
+        |path| path.is_file(),
+    )
+}
+
+#[cfg(not(target_os = "freebsd"))]
+fn find_fusermount(
+    explicit: Option<OsString>,
+    path: Option<OsString>,
+    exists: impl Fn(&Path) -> bool,
+) -> PathBuf {
+    if let Some(explicit) = explicit.filter(|p| !p.is_empty()) {
+        return explicit.into();
+    }
+    let path = path.unwrap_or_else(|| DEFAULT_PATH.into());
+    for prog in FUSERMOUNT_PROGS {
+        for dir in std::env::split_paths(&path) {
+            let candidate = dir.join(prog);
+            if exists(&candidate) {
+                return candidate;
+            }
+        }
+    }
+    // Leave the failure to exec(2), which reports ENOENT.
+    PathBuf::from(FUSERMOUNT_PROGS[FUSERMOUNT_PROGS.len() - 1])
+}
+
+/// Mount the filesystem by calling `mount(2)` directly, without the mount helper.
+///
+/// This requires `CAP_SYS_ADMIN` in the user namespace owning the mount namespace,
+/// and the kernel must allow FUSE mounts in the user namespaces (Linux 4.18 or later).
+#[cfg(not(target_os = "freebsd"))]
+fn mount_direct(mountpoint: &Path, mountopts: &MountOptions) -> io::Result<RawFd> {
+    if mountopts.blkdev.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the block device mounts require the mount helper",
+        ));
+    }
+
+    let mode = std::fs::metadata(mountpoint)?.mode() & libc::S_IFMT;
+    let args = DirectMountArgs::new(mountopts);
+
+    let target = cstr(mountpoint.as_os_str().as_bytes())?;
+    let source = cstr(args.source)?;
+    let fstype = cstr(args.fstype)?;
+
+    let dev = cstr(FUSE_DEV_PATH)?;
+    let fd = syscall! { open(dev.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) };
+
+    let mut data = format!(
+        "fd={},rootmode={:o},user_id={},group_id={}",
+        fd,
+        mode,
+        unsafe { libc::getuid() },
+        unsafe { libc::getgid() },
+    );
+    for opt in &args.options {
+        data.push(',');
+        data.push_str(opt);
+    }
+    let data = cstr(data)?;
+
+    let res = unsafe {
+        libc::mount(
+            source.as_ptr(),
+            target.as_ptr(),
+            fstype.as_ptr(),
+            args.flags,
+            data.as_ptr() as *const c_void,
+        )
+    };
+    if res == -1 {
+        let err = io::Error::last_os_error();
+        unsafe {
+            libc::close(fd);
+        }
+        return Err(err);
+    }
+
+    Ok(fd)
+}
+
+/// The arguments of `mount(2)` translated from the mount options,
+/// in the same way as `fusermount`.
+#[cfg(not(target_os = "freebsd"))]
+#[derive(Debug)]
+struct DirectMountArgs {
+    source: String,
+    fstype: String,
+    flags: libc::c_ulong,
+    options: Vec<String>,
+}
+
+#[cfg(not(target_os = "freebsd"))]
+impl DirectMountArgs {
+    fn new(mountopts: &MountOptions) -> Self {
+        let mut source = None;
+        let mut subtype = None;
+        let mut flags = libc::MS_NOSUID | libc::MS_NODEV;
+        let mut options = vec![];
+        for opt in &mountopts.options {
+            let (set, clear) = match opt.as_str() {
+                "ro" => (libc::MS_RDONLY, 0),
+                "rw" => (0, libc::MS_RDONLY),
+                "nosuid" => (libc::MS_NOSUID, 0),
+                "suid" => (0, libc::MS_NOSUID),
+                "nodev" => (libc::MS_NODEV, 0),
+                "dev" => (0, libc::MS_NODEV),
+                "noexec" => (libc::MS_NOEXEC, 0),
+                "exec" => (0, libc::MS_NOEXEC),
+                "sync" => (libc::MS_SYNCHRONOUS, 0),
+                "async" => (0, libc::MS_SYNCHRONOUS),
+                "dirsync" => (libc::MS_DIRSYNC, 0),
+                "noatime" => (libc::MS_NOATIME, 0),
+                "atime" => (0, libc::MS_NOATIME),
+                "nodiratime" => (libc::MS_NODIRATIME, 0),
+                "diratime" => (0, libc::MS_NODIRATIME),
+                opt => {
+                    if let Some(name) = opt.strip_prefix("fsname=") {
+                        source = Some(name.to_owned());
+                    } else if let Some(name) = opt.strip_prefix("subtype=") {
+                        subtype = Some(name.to_owned());
+                    } else {
+                        options.push(opt.to_owned());
+                    }
+                    continue;
+                }
+            };
+            flags = (flags | set) & !clear;
+        }
+
+        let fstype = match &subtype {
+            Some(subtype) => format!("fuse.{}", subtype),
+            None => "fuse".to_owned(),
+        };
+        Self {
+            source: source.or(subtype).unwrap_or_else(|| "polyfuse".to_owned()),
+            fstype,
+            flags,
+            options,
+        }
+    }
+}
+
+#[cfg(not(target_os = "freebsd"))]
+fn unmount(mountpoint: &Path, mountopts: &MountOptions) {
+    if mountopts.direct_mount {
+        if let Ok(mountpoint) = cstr(mountpoint.as_os_str().as_bytes()) {
+            unsafe {
+                libc::umount2(mountpoint.as_ptr(), libc::MNT_DETACH);
+            }
+        }
+        return;
+    }
+
+    let _ = Command::new(fusermount_prog(mountopts))
         .args(&["-u", "-q", "-z", "--"])
         .arg(&mountpoint)
         .status();
 }
 
+#[cfg(not(target_os = "freebsd"))]
 fn receive_fd(reader: &UnixStream) -> io::Result<RawFd> {
     let mut buf = [0u8; 1];
     let mut iov = libc::iovec {
@@ -345,13 +858,93 @@ fn receive_fd(reader: &UnixStream) -> io::Result<RawFd> {
     Ok(fd)
 }
 
+/// Mount the filesystem on FreeBSD.
+///
+/// Unlike Linux, FreeBSD does not require the setuid helper and the device
+/// `/dev/fuse` is opened by the process itself and then passed to `nmount(2)`
+/// with the `fd` option. The options provided by `KernelConfig::mount_option`
+/// (e.g. `subtype=hello`, `allow_other`, `default_permissions`) are passed as-is.
+///
+/// The `auto_unmount`, `fusermount_path` and `fuse_comm_fd` settings have no effect,
+/// and the block device mounts are not supported.
+#[cfg(target_os = "freebsd")]
+fn mount(mountpoint: &Path, mountopts: &MountOptions) -> io::Result<(RawFd, Option<Fusermount>)> {
+    if mountopts.blkdev.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the block device mounts are not supported on FreeBSD",
+        ));
+    }
+
+    let dev = cstr(FUSE_DEV_PATH)?;
+    let fspath = cstr(mountpoint.as_os_str().as_bytes())?;
+
+    let mut opts: Vec<(CString, Option<CString>)> = vec![];
+    for opt in &mountopts.options {
+        let mut kv = opt.splitn(2, '=');
+        let key = kv.next().unwrap_or("");
+        let value = kv.next().map(cstr).transpose()?;
+        opts.push((cstr(key)?, value));
+    }
+
+    let fd = syscall! { open(dev.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) };
+    opts.push((cstr("fstype")?, Some(cstr("fusefs")?)));
+    opts.push((cstr("fspath")?, Some(fspath)));
+    opts.push((cstr("from")?, Some(dev)));
+    opts.push((cstr("fd")?, Some(cstr(fd.to_string())?)));
+
+    let mut iov = Vec::with_capacity(opts.len() * 2);
+    for (key, value) in &opts {
+        iov.push(libc::iovec {
+            iov_base: key.as_ptr() as *mut c_void,
+            iov_len: key.as_bytes_with_nul().len(),
+        });
+        iov.push(match value {
+            Some(value) => libc::iovec {
+                iov_base: value.as_ptr() as *mut c_void,
+                iov_len: value.as_bytes_with_nul().len(),
+            },
+            None => libc::iovec {
+                iov_base: std::ptr::null_mut(),
+                iov_len: 0,
+            },
+        });
+    }
+
+    let res = unsafe { libc::nmount(iov.as_mut_ptr(), iov.len() as libc::c_uint, 0) };
+    if res == -1 {
+        let err = io::Error::last_os_error();
+        unsafe {
+            libc::close(fd);
+        }
+        return Err(err);
+    }
+
+    Ok((fd, None))
+}
+
+#[cfg(target_os = "freebsd")]
+fn unmount(mountpoint: &Path, _: &MountOptions) {
+    if let Ok(mountpoint) = CString::new(mountpoint.as_os_str().as_bytes()) {
+        unsafe {
+            libc::unmount(mountpoint.as_ptr(), 0);
+        }
+    }
+}
+
+fn cstr(s: impl Into<Vec<u8>>) -> io::Result<CString> {
+    CString::new(s).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
 // ==== util ====
 
+#[cfg(not(target_os = "freebsd"))]
 enum ForkResult {
     Parent { child_pid: c_int },
     Child,
 }
 
+#[cfg(not(target_os = "freebsd"))]
 unsafe fn fork() -> io::Result<ForkResult> {
     let pid = syscall! { fork() };
     match pid {
@@ -360,3 +953,117 @@ unsafe fn fork() -> io::Result<ForkResult> {
         pid => Ok(ForkResult::Parent { child_pid: pid }),
     }
 }
+
+#[cfg(all(test, not(target_os = "freebsd")))]
+mod tests {
+    use super::*;
+
+    #[cfg(not(target_os = "freebsd"))]
+    #[test]
+    fn direct_mount_args() {
+        let mountopts = MountOptions {
+            options: ["subtype=hello", "ro", "dev", "allow_other", "max_read=4096"]
+                .iter()
+                .map(|opt| opt.to_string())
+                .collect(),
+            direct_mount: true,
+            ..Default::default()
+        };
+        let args = DirectMountArgs::new(&mountopts);
+        assert_eq!(args.source, "hello");
+        assert_eq!(args.fstype, "fuse.hello");
+        assert_eq!(args.flags, libc::MS_RDONLY | libc::MS_NOSUID);
+        assert_eq!(args.options, ["allow_other", "max_read=4096"]);
+
+        let args = DirectMountArgs::new(&MountOptions {
+            options: vec!["fsname=/dev/foo".into()],
+            ..Default::default()
+        });
+        assert_eq!(args.source, "/dev/foo");
+        assert_eq!(args.fstype, "fuse");
+        assert_eq!(args.flags, libc::MS_NOSUID | libc::MS_NODEV);
+        assert!(args.options.is_empty());
+    }
+
+    #[cfg(not(target_os = "freebsd"))]
+    #[test]
+    fn fusermount_fallback() {
+        let attempted = std::cell::RefCell::new(vec![]);
+        let find = |installed: &[&str]| {
+            attempted.borrow_mut().clear();
+            find_fusermount(None, Some("/opt/bin:/usr/bin".into()), |path| {
+                attempted.borrow_mut().push(path.to_owned());
+                installed.iter().any(|p| path == Path::new(p))
+            })
+        };
+
+        assert_eq!(
+            find(&["/usr/bin/fusermount", "/usr/bin/fusermount3"]),
+            Path::new("/usr/bin/fusermount3")
+        );
+        assert_eq!(
+            find(&["/opt/bin/fusermount"]),
+            Path::new("/opt/bin/fusermount")
+        );
+        assert_eq!(
+            *attempted.borrow(),
+            [
+                "/opt/bin/fusermount3",
+                "/usr/bin/fusermount3",
+                "/opt/bin/fusermount",
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        );
+        assert_eq!(find(&[]), Path::new("fusermount"));
+
+        // The explicit path is used as is.
+        assert_eq!(
+            find_fusermount(Some("/sbin/fusermount".into()), None, |_| false),
+            Path::new("/sbin/fusermount")
+        );
+    }
+
+    #[test]
+    fn connection_id_from_mountinfo() {
+        let mountinfo = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+45 22 0:42 / /mnt/fuse rw,nosuid,nodev,relatime shared:30 - fuse.hello hello rw,user_id=1000,group_id=1000
+46 22 0:43 / /mnt/with\\040space rw,relatime - fuse /dev/fuse rw,user_id=0,group_id=0
+47 22 7:1 / /mnt/blk rw,relatime - fuseblk /dev/loop1 rw,user_id=0,group_id=0
+48 22 0:44 / /mnt/tmp rw - tmpfs tmpfs rw
+49 45 0:50 / /mnt/fuse rw,relatime shared:31 - fuse.stacked stacked rw,user_id=1000,group_id=1000
+";
+        let find = |path: &str| find_connection_id(mountinfo, Path::new(path));
+        assert_eq!(find("/mnt/fuse"), Some(50));
+        assert_eq!(find("/mnt/with space"), Some(43));
+        assert_eq!(find("/mnt/blk"), Some((7 << 20) | 1));
+        assert_eq!(find("/mnt/tmp"), None);
+        assert_eq!(find("/mnt/none"), None);
+    }
+
+    #[test]
+    fn blkdev_options() {
+        let mut mountopts = MountOptions::default();
+        mountopts.options.push("allow_other".into());
+        assert_eq!(
+            fusermount_options(&mountopts).unwrap(),
+            "allow_other,auto_unmount"
+        );
+
+        mountopts.blkdev = Some("/dev/loop0".into());
+        mountopts.auto_unmount = false;
+        assert_eq!(
+            fusermount_options(&mountopts).unwrap(),
+            "allow_other,blkdev,fsname=/dev/loop0"
+        );
+
+        mountopts.options.clear();
+        mountopts.blkdev = Some("/dev/disk/by-label/a,b".into());
+        assert_eq!(
+            fusermount_options(&mountopts).unwrap(),
+            "blkdev,fsname=/dev/disk/by-label/a\\,b"
+        );
+    }
+}
@@ -1,59 +1,138 @@
-use std::{ffi::OsStr, mem, os::unix::prelude::*};
+//! Decoding the FUSE messages.
+//!
+//! The `Decoder` is what polyfuse uses for parsing the arguments of requests,
+//! and is also available for parsing the payloads not covered by `op`,
+//! such as the argument of an `ioctl` request.
+
+use std::{ffi::OsStr, fmt, mem, os::unix::prelude::*};
 use zerocopy::{FromBytes, LayoutVerified};
 
+/// The error that occurs when decoding a message.
 #[derive(Debug)]
-pub(crate) enum DecodeError {
+#[non_exhaustive]
+pub enum DecodeError {
+    /// The message is shorter than expected.
     UnexpectedEof,
+    /// The string does not have the terminating NUL character.
     MissingNulCharacter,
+    /// The bytes are not aligned for the requested type.
     Unaligned,
+    /// The message length in the header is inconsistent with the received data.
+    InvalidLength,
 }
 
-pub(crate) struct Decoder<'a> {
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEof => f.write_str("unexpected end of message"),
+            DecodeError::MissingNulCharacter => f.write_str("missing NUL character"),
+            DecodeError::Unaligned => f.write_str("unaligned bytes"),
+            DecodeError::InvalidLength => f.write_str("invalid message length"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A cursor for reading the values from the bytes of a message in order.
+///
+/// Every method checks the bounds of the remaining bytes and returns an error,
+/// rather than panicking, if the message is truncated.  On error, the cursor
+/// is not advanced.
+///
+/// ```
+/// use polyfuse::decoder::Decoder;
+///
+/// let bytes = b"foo\0bar";
+/// let mut decoder = Decoder::new(&bytes[..]);
+/// assert_eq!(decoder.fetch_str().unwrap(), "foo");
+/// assert_eq!(decoder.fetch_bytes(3).unwrap(), b"bar");
+/// assert!(decoder.fetch_bytes(1).is_err());
+/// ```
+pub struct Decoder<'a> {
     bytes: &'a [u8],
+    wanted: Option<usize>,
 }
 
-impl<'a> Decoder<'a> {
-    pub(crate) fn new(bytes: &'a [u8]) -> Self {
-        Self { bytes }
+impl fmt::Debug for Decoder<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Decoder")
+            .field("remaining", &self.bytes.len())
+            .finish()
     }
+}
 
-    pub(crate) fn fetch_bytes(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
-        if self.bytes.len() < count {
-            return Err(DecodeError::UnexpectedEof);
+impl<'a> Decoder<'a> {
+    /// Create a decoder reading from the specified bytes.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            wanted: None,
         }
+    }
 
-        let (bytes, remaining) = self.bytes.split_at(count);
-        self.bytes = remaining;
+    /// Return the remaining bytes without consuming them.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.bytes
+    }
 
-        debug_assert!(bytes.len() >= count);
+    /// Return the number of bytes requested by the last fetch that failed
+    /// due to the end of message.
+    pub(crate) fn wanted(&self) -> Option<usize> {
+        self.wanted
+    }
 
+    /// Fetch the specified number of bytes.
+    pub fn fetch_bytes(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        let bytes = self.peek_bytes(count)?;
+        self.bytes = &self.bytes[count..];
         Ok(bytes)
     }
 
     /// Fetch a value of Plain-Old-Data (POD) type by reference.
-    pub(crate) fn fetch<T>(&mut self) -> Result<&'a T, DecodeError>
+    ///
+    /// The types in `polyfuse_kernel` implement `FromBytes` and thus can be fetched
+    /// with this method.  If the remaining bytes are not properly aligned for `T`,
+    /// `DecodeError::Unaligned` is returned.
+    pub fn fetch<T>(&mut self) -> Result<&'a T, DecodeError>
     where
         T: FromBytes,
     {
-        let bytes = self.fetch_bytes(mem::size_of::<T>())?;
+        let bytes = self.peek_bytes(mem::size_of::<T>())?;
         let verified = LayoutVerified::<_, T>::new(bytes).ok_or(DecodeError::Unaligned)?;
+        self.bytes = &self.bytes[bytes.len()..];
         Ok(verified.into_ref())
     }
 
     /// Fetch an array of Plain-Old Data (POD) type by reference.
-    #[allow(dead_code)]
-    pub(crate) fn fetch_array<T>(&mut self, count: usize) -> Result<&'a [T], DecodeError>
+    pub fn fetch_array<T>(&mut self, count: usize) -> Result<&'a [T], DecodeError>
     where
         T: FromBytes,
     {
-        let bytes = self.fetch_bytes(mem::size_of::<T>() * count)?;
+        let len = mem::size_of::<T>().saturating_mul(count);
+        let bytes = self.peek_bytes(len)?;
         let verified = LayoutVerified::<_, [T]>::new_slice(bytes) //
             .ok_or(DecodeError::Unaligned)?;
+        self.bytes = &self.bytes[bytes.len()..];
         Ok(verified.into_slice())
     }
 
+    fn peek_bytes(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        match self.bytes.get(..count) {
+            Some(bytes) => Ok(bytes),
+            None => {
+                self.wanted = Some(count);
+                Err(DecodeError::UnexpectedEof)
+            }
+        }
+    }
+
     /// Fetch a zero-terminated OS string by reference.
-    pub(crate) fn fetch_str(&mut self) -> Result<&'a OsStr, DecodeError> {
+    ///
+    /// The returned string does not include the terminating NUL character.
+    /// The terminator is searched only within the remaining bytes, and
+    /// `DecodeError::MissingNulCharacter` is returned if it is not found.
+    pub fn fetch_str(&mut self) -> Result<&'a OsStr, DecodeError> {
         let len = self
             .bytes
             .iter()
@@ -85,6 +164,49 @@ mod tests {
         assert_eq!(decoder.fetch_str().ok(), Some(OsStr::from_bytes(b"bar")));
     }
 
+    #[test]
+    fn fetch_header_arg_and_name() {
+        use polyfuse_kernel::*;
+        use zerocopy::AsBytes as _;
+
+        let header = fuse_in_header {
+            opcode: fuse_opcode::FUSE_MKDIR as u32,
+            unique: 2,
+            nodeid: 1,
+            ..Default::default()
+        };
+        let arg = fuse_mkdir_in {
+            mode: 0o755,
+            umask: 0o022,
+        };
+        let mut input = vec![0u64; 16];
+        let bytes = input.as_bytes_mut();
+        let mut len = 0;
+        for chunk in &[header.as_bytes(), arg.as_bytes(), b"foo\0"] {
+            bytes[len..len + chunk.len()].copy_from_slice(chunk);
+            len += chunk.len();
+        }
+
+        let mut decoder = Decoder::new(&bytes[..len]);
+        let header = decoder.fetch::<fuse_in_header>().unwrap();
+        assert_eq!(header.unique, 2);
+        let arg = decoder.fetch::<fuse_mkdir_in>().unwrap();
+        assert_eq!(arg.mode, 0o755);
+        assert_eq!(arg.umask, 0o022);
+        assert_eq!(decoder.fetch_str().unwrap(), "foo");
+        assert!(decoder.remaining().is_empty());
+
+        // The truncated input is reported as an error.
+        let mut decoder = Decoder::new(&bytes[..len - 1]);
+        assert!(decoder.fetch::<fuse_in_header>().is_ok());
+        assert!(decoder.fetch::<fuse_mkdir_in>().is_ok());
+        assert!(matches!(
+            decoder.fetch_str(),
+            Err(DecodeError::MissingNulCharacter)
+        ));
+        assert_eq!(decoder.remaining(), b"foo");
+    }
+
     #[test]
     fn unexpected_eof() {
         const INPUT: &[u8] = &[3, 1, 4, 1, 5, 9, 2, 6, 5];
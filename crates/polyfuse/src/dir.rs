@@ -0,0 +1,167 @@
+//! Management of directory entries.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::{OsStr, OsString},
+    fmt,
+};
+
+/// A table of directory entries that assigns a stable offset to each entry.
+///
+/// The offsets in the replies to `readdir` requests are the opaque cookies
+/// chosen by the filesystem, and the kernel resumes the next `readdir` from
+/// the offset of the last received entry.  If the offsets are the positions
+/// in the listing, they are shifted by the modifications of the directory
+/// between the paginated requests, and some entries are skipped or emitted twice.
+///
+/// `DirEntries` avoids this problem by assigning each inserted entry a cookie
+/// that is never reused in the table, and by listing the entries in the order of
+/// the cookies.  Resuming from an offset by `since` thus emits every entry that
+/// has not been removed exactly once, regardless of the modifications made
+/// in the meantime.  The entries inserted during the pagination may or may not
+/// be emitted, which is allowed by POSIX.
+pub struct DirEntries<T> {
+    entries: BTreeMap<u64, (OsString, T)>,
+    cookies: HashMap<OsString, u64>,
+    next_cookie: u64,
+}
+
+impl<T> fmt::Debug for DirEntries<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirEntries")
+            .field("len", &self.entries.len())
+            .finish()
+    }
+}
+
+impl<T> Default for DirEntries<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> DirEntries<T> {
+    /// Create an empty table.
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            cookies: HashMap::new(),
+            next_cookie: 1,
+        }
+    }
+
+    /// Insert an entry with the specified name.
+    ///
+    /// If the entry with the same name already exists, its value is replaced
+    /// and returned, and its cookie is kept unchanged.
+    pub fn insert(&mut self, name: impl Into<OsString>, value: T) -> Option<T> {
+        let name = name.into();
+        if let Some(cookie) = self.cookies.get(&name) {
+            let (_, old) = self.entries.get_mut(cookie).expect("inconsistent cookie");
+            return Some(std::mem::replace(old, value));
+        }
+
+        let cookie = self.next_cookie;
+        self.next_cookie += 1;
+        self.cookies.insert(name.clone(), cookie);
+        self.entries.insert(cookie, (name, value));
+        None
+    }
+
+    /// Remove the entry with the specified name.
+    pub fn remove(&mut self, name: &OsStr) -> Option<T> {
+        let cookie = self.cookies.remove(name)?;
+        self.entries.remove(&cookie).map(|(_, value)| value)
+    }
+
+    /// Return the reference to the entry with the specified name.
+    pub fn get(&self, name: &OsStr) -> Option<&T> {
+        let cookie = self.cookies.get(name)?;
+        self.entries.get(cookie).map(|(_, value)| value)
+    }
+
+    /// Return the mutable reference to the entry with the specified name.
+    pub fn get_mut(&mut self, name: &OsStr) -> Option<&mut T> {
+        let cookie = self.cookies.get(name)?;
+        self.entries.get_mut(cookie).map(|(_, value)| value)
+    }
+
+    /// Return the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Return whether the table has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over the entries following the specified offset, along with their cookies.
+    ///
+    /// The filesystem passes `Readdir::offset` to this method, and replies with
+    /// the returned cookies as the offsets of the entries, e.g. by `ReaddirOut::entry`.
+    /// The offset `0` iterates over all entries.
+    pub fn since(&self, offset: u64) -> impl Iterator<Item = (u64, &OsStr, &T)> + '_ {
+        self.entries
+            .range(offset.saturating_add(1)..)
+            .map(|(&cookie, (name, value))| (cookie, name.as_os_str(), value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paginate_while_modified() {
+        let mut dir = DirEntries::new();
+        for name in &["a", "b", "c", "d", "e"] {
+            dir.insert(*name, ());
+        }
+
+        let page = |dir: &DirEntries<()>, offset: u64| -> Vec<(u64, OsString)> {
+            dir.since(offset)
+                .take(2)
+                .map(|(cookie, name, _)| (cookie, name.to_owned()))
+                .collect()
+        };
+
+        let mut listed = vec![];
+        let first = page(&dir, 0);
+        listed.extend(first.iter().map(|(_, name)| name.clone()));
+        let offset = first.last().unwrap().0;
+
+        // Remove the listed and unlisted entries, and replace another one.
+        assert_eq!(dir.remove(OsStr::new("a")), Some(()));
+        assert_eq!(dir.remove(OsStr::new("d")), Some(()));
+        assert_eq!(dir.insert("c", ()), Some(()));
+        dir.insert("f", ());
+
+        let mut offset = offset;
+        loop {
+            let next = page(&dir, offset);
+            if next.is_empty() {
+                break;
+            }
+            listed.extend(next.iter().map(|(_, name)| name.clone()));
+            offset = next.last().unwrap().0;
+        }
+
+        assert_eq!(listed, ["a", "b", "c", "e", "f"]);
+        assert_eq!(dir.len(), 4);
+    }
+
+    #[test]
+    fn cookies_are_not_reused() {
+        let mut dir = DirEntries::new();
+        dir.insert("a", 1);
+        dir.remove(OsStr::new("a"));
+        dir.insert("a", 2);
+
+        let entries: Vec<_> = dir.since(0).collect();
+        assert_eq!(entries, [(2, OsStr::new("a"), &2)]);
+        assert_eq!(dir.get(OsStr::new("a")), Some(&2));
+        assert_eq!(dir.since(2).count(), 0);
+        assert_eq!(dir.since(u64::MAX).count(), 0);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::op::DecodeError;
+use std::{error, fmt, io};
+
+/// The error type returned from `Session`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The message sent from the kernel could not be decoded.
+    Decode(DecodeError),
+
+    /// The kernel speaks the ABI version unsupported by polyfuse.
+    UnsupportedVersion { major: u32, minor: u32 },
+
+    /// The connection with the kernel was closed, e.g. the filesystem
+    /// was unmounted before completing the initialization.
+    ConnectionClosed,
+
+    /// The initialization was given up since the kernel kept sending the other
+    /// operations before `INIT`, e.g. because the file descriptor is not connected
+    /// to the FUSE device.
+    ///
+    /// `count` is the number of the operations received before giving up,
+    /// and `opcode` is the opcode of the last one.
+    OperationBeforeInit { opcode: u32, count: usize },
+
+    /// An I/O error occurred while communicating with the kernel.
+    Io(io::Error),
+}
+
+impl Error {
+    /// Return whether this error indicates that the non-blocking operation
+    /// would block.
+    pub fn is_would_block(&self) -> bool {
+        match self {
+            Self::Io(err) => err.kind() == io::ErrorKind::WouldBlock,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Decode(err) => fmt::Display::fmt(err, f),
+            Self::UnsupportedVersion { major, minor } => {
+                write!(f, "unsupported ABI version: {}.{}", major, minor)
+            }
+            Self::ConnectionClosed => f.write_str("the connection with the kernel was closed"),
+            Self::OperationBeforeInit { opcode, count } => write!(
+                f,
+                "received {} operations before INIT (last opcode={})",
+                count, opcode
+            ),
+            Self::Io(err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Decode(err) => Some(err),
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        match err.raw_os_error() {
+            Some(libc::ENODEV) => Self::ConnectionClosed,
+            _ => Self::Io(err),
+        }
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(err: DecodeError) -> Self {
+        Self::Decode(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            Error::ConnectionClosed => io::Error::from_raw_os_error(libc::ENODEV),
+            Error::Decode(..) => io::Error::new(io::ErrorKind::InvalidData, err),
+            Error::UnsupportedVersion { .. } => io::Error::other(err),
+            Error::OperationBeforeInit { .. } => {
+                io::Error::new(io::ErrorKind::ConnectionRefused, err)
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Management of file handles.
+
+use crate::op::Poll;
+use std::{collections::HashMap, fmt};
+
+/// A table of the states associated with the file handles.
+///
+/// The filesystem replies to `open`, `opendir` and `create` requests with
+/// a file handle (`fh`) of its choice, and the kernel passes it back to the
+/// subsequent requests on the opened file, such as `read`, `write`, `readdir`,
+/// `flush` and `fsync`, until it is closed by `release` or `releasedir`.
+///
+/// `HandleTable` allocates a unique handle for each opened file by `insert`,
+/// and keeps the associated state (e.g. the backing file or the cursor of
+/// a directory stream) until it is removed by `remove`.  The handles are never
+/// reused in the table, so a stale handle cannot refer to another file.
+/// The handle `0` is never allocated.
+pub struct HandleTable<T> {
+    entries: HashMap<u64, T>,
+    next_fh: u64,
+}
+
+impl<T> fmt::Debug for HandleTable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandleTable")
+            .field("len", &self.entries.len())
+            .finish()
+    }
+}
+
+impl<T> Default for HandleTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> HandleTable<T> {
+    /// Create an empty table.
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            next_fh: 1,
+        }
+    }
+
+    /// Allocate a new handle associated with the specified state.
+    ///
+    /// The returned value is passed to the kernel, e.g. by `OpenOut::fh`.
+    pub fn insert(&mut self, value: T) -> u64 {
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.entries.insert(fh, value);
+        fh
+    }
+
+    /// Return the reference to the state associated with a handle.
+    pub fn get(&self, fh: u64) -> Option<&T> {
+        self.entries.get(&fh)
+    }
+
+    /// Return the mutable reference to the state associated with a handle.
+    pub fn get_mut(&mut self, fh: u64) -> Option<&mut T> {
+        self.entries.get_mut(&fh)
+    }
+
+    /// Release a handle, and return the state associated with it.
+    ///
+    /// This method should be called on `release` or `releasedir` requests.
+    pub fn remove(&mut self, fh: u64) -> Option<T> {
+        self.entries.remove(&fh)
+    }
+
+    /// Return the number of the open handles.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Return whether the table has no open handles.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// A registry of the poll handles associated with the file handles.
+///
+/// When a `poll` request asks for the notification (`Poll::kh` returns `Some`),
+/// the filesystem must send `Notifier::poll_wakeup` with the poll handle (`kh`)
+/// once the file becomes ready.  `PollHandles` keeps the poll handle of each file
+/// handle until `remove` is called on `release`, so that no wakeup is sent for
+/// the closed files, which the kernel simply ignores.
+#[derive(Debug, Default)]
+pub struct PollHandles {
+    handles: HashMap<u64, u64>,
+}
+
+impl PollHandles {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the poll handle requested by a `poll` request, if any.
+    ///
+    /// The poll handle replaces the one previously registered for the same file handle.
+    /// It returns the registered poll handle.
+    pub fn register(&mut self, op: &Poll<'_>) -> Option<u64> {
+        let kh = op.kh()?;
+        self.handles.insert(op.fh(), kh);
+        Some(kh)
+    }
+
+    /// Return the poll handle registered for a file handle.
+    pub fn get(&self, fh: u64) -> Option<u64> {
+        self.handles.get(&fh).copied()
+    }
+
+    /// Unregister the poll handle of a file handle, and return it.
+    ///
+    /// This method should be called on `release` requests.
+    pub fn remove(&mut self, fh: u64) -> Option<u64> {
+        self.handles.remove(&fh)
+    }
+
+    /// Iterate over the pairs of the file handle and the poll handle of the open files.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.handles.iter().map(|(&fh, &kh)| (fh, kh))
+    }
+
+    /// Return the number of the registered poll handles.
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Return whether no poll handles are registered.
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op::Operation;
+    use polyfuse_kernel::*;
+    use zerocopy::AsBytes as _;
+
+    #[test]
+    fn allocate_and_release() {
+        let mut table = HandleTable::new();
+        let dir = table.insert(vec!["a", "b"]);
+        let file = table.insert(vec![]);
+        assert_ne!(dir, 0);
+        assert_ne!(dir, file);
+        assert_eq!(table.len(), 2);
+
+        table.get_mut(file).unwrap().push("c");
+        assert_eq!(table.get(dir), Some(&vec!["a", "b"]));
+        assert_eq!(table.get(file), Some(&vec!["c"]));
+
+        assert_eq!(table.remove(dir), Some(vec!["a", "b"]));
+        assert_eq!(table.remove(dir), None);
+        assert!(table.get(dir).is_none());
+
+        // The released handles are not reused.
+        let reopened = table.insert(vec![]);
+        assert_ne!(reopened, dir);
+        assert_ne!(reopened, file);
+
+        table.remove(file);
+        table.remove(reopened);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn poll_handles() {
+        let header = fuse_in_header {
+            opcode: fuse_opcode::FUSE_POLL as u32,
+            nodeid: 2,
+            ..Default::default()
+        };
+        let poll = |fh: u64, kh: u64, flags: u32| fuse_poll_in {
+            fh,
+            kh,
+            flags,
+            events: libc::POLLIN as u32,
+        };
+
+        let mut handles = PollHandles::new();
+        for arg in &[
+            poll(1, 10, FUSE_POLL_SCHEDULE_NOTIFY),
+            poll(2, 20, FUSE_POLL_SCHEDULE_NOTIFY),
+            poll(3, 30, 0),
+        ] {
+            match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+                Operation::Poll(op) => {
+                    handles.register(&op);
+                }
+                _ => panic!("incorrect operation is returned"),
+            }
+        }
+        assert_eq!(handles.get(1), Some(10));
+        assert_eq!(handles.get(3), None, "no notification requested");
+
+        // The poll handle is gone with the released file.
+        assert_eq!(handles.remove(1), Some(10));
+        assert_eq!(handles.get(1), None);
+        assert_eq!(handles.iter().collect::<Vec<_>>(), [(2, 20)]);
+
+        handles.remove(2);
+        assert!(handles.is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+//! Management of the lifetime of inodes.
+
+use crate::op::Forget;
+use std::{collections::HashMap, fmt};
+
+/// A table of inodes that tracks their lookup counts.
+///
+/// The kernel holds a reference to an inode for each successful reply
+/// containing its entry (`lookup`, `mknod`, `mkdir`, `symlink`, `link`,
+/// `create` and the entries of `readdirplus` except `"."` and `".."`),
+/// and releases them by `forget` requests.  The filesystem must keep the
+/// inode alive until all of the references are released.
+///
+/// `InodeTable` encodes this contract: the filesystem calls `lookup`
+/// whenever it replies with an entry, and passes the received `forget`
+/// requests to `forget` or `forget_all`.  The inode is removed from the table
+/// exactly once, when its lookup count reaches zero.
+pub struct InodeTable<T> {
+    entries: HashMap<u64, Entry<T>>,
+}
+
+struct Entry<T> {
+    value: T,
+    nlookup: u64,
+}
+
+impl<T> fmt::Debug for InodeTable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InodeTable")
+            .field("len", &self.entries.len())
+            .finish()
+    }
+}
+
+impl<T> Default for InodeTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> InodeTable<T> {
+    /// Create an empty table.
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Insert an inode that the kernel does not hold any reference to, such as the root inode.
+    ///
+    /// The lookup count of the inserted inode starts at zero, and hence it is never
+    /// removed unless the kernel looks it up and then forgets it.
+    pub fn insert(&mut self, ino: u64, value: T) {
+        self.entries.insert(ino, Entry { value, nlookup: 0 });
+    }
+
+    /// Increment the lookup count of an inode, inserting it by `f` if it does not exist.
+    ///
+    /// This method must be called each time the filesystem replies with the entry
+    /// of the inode.
+    pub fn lookup(&mut self, ino: u64, f: impl FnOnce() -> T) -> &mut T {
+        let entry = self.entries.entry(ino).or_insert_with(|| Entry {
+            value: f(),
+            nlookup: 0,
+        });
+        entry.nlookup += 1;
+        &mut entry.value
+    }
+
+    /// Decrement the lookup count of an inode.
+    ///
+    /// If the lookup count reaches zero, the inode is removed from the table
+    /// and returned to the caller, which should release the resources associated with it.
+    pub fn forget(&mut self, ino: u64, nlookup: u64) -> Option<T> {
+        let entry = self.entries.get_mut(&ino)?;
+        entry.nlookup = entry.nlookup.saturating_sub(nlookup);
+        if entry.nlookup > 0 {
+            return None;
+        }
+        self.entries.remove(&ino).map(|entry| entry.value)
+    }
+
+    /// Decrement the lookup counts by the received `forget` requests.
+    ///
+    /// The callback is invoked with the inodes whose lookup count reaches zero.
+    pub fn forget_all(&mut self, forgets: &[Forget], mut f: impl FnMut(u64, T)) {
+        for forget in forgets {
+            if let Some(value) = self.forget(forget.ino(), forget.nlookup()) {
+                f(forget.ino(), value);
+            }
+        }
+    }
+
+    /// Release all of the references held by the kernel at once.
+    ///
+    /// This is intended for the case where the connection is lost and the filesystem
+    /// is mounted again (see [`Remount`]), since the new connection starts with no
+    /// references and never sends `forget` for the ones held by the old connection.
+    /// The callback is invoked with the looked-up inodes, which are removed from
+    /// the table.  The inodes inserted by `insert` and never looked up are kept.
+    ///
+    /// [`Remount`]: crate::Remount
+    pub fn forget_everything(&mut self, mut f: impl FnMut(u64, T)) {
+        let forgotten: Vec<u64> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.nlookup > 0)
+            .map(|(&ino, _)| ino)
+            .collect();
+        for ino in forgotten {
+            if let Some(entry) = self.entries.remove(&ino) {
+                f(ino, entry.value);
+            }
+        }
+    }
+
+    /// Return the reference to an inode.
+    pub fn get(&self, ino: u64) -> Option<&T> {
+        self.entries.get(&ino).map(|entry| &entry.value)
+    }
+
+    /// Return the mutable reference to an inode.
+    pub fn get_mut(&mut self, ino: u64) -> Option<&mut T> {
+        self.entries.get_mut(&ino).map(|entry| &mut entry.value)
+    }
+
+    /// Return the current lookup count of an inode.
+    pub fn nlookup(&self, ino: u64) -> Option<u64> {
+        self.entries.get(&ino).map(|entry| entry.nlookup)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op::Operation;
+    use polyfuse_kernel::*;
+    use zerocopy::AsBytes as _;
+
+    #[test]
+    fn forget_everything() {
+        let mut table = InodeTable::new();
+        table.insert(1, "root");
+        table.lookup(2, || "foo");
+        table.lookup(2, || "foo");
+        table.lookup(3, || "bar");
+
+        let mut forgotten = vec![];
+        table.forget_everything(|ino, value| forgotten.push((ino, value)));
+        forgotten.sort();
+        assert_eq!(forgotten, [(2, "foo"), (3, "bar")]);
+
+        assert_eq!(table.get(1), Some(&"root"));
+        assert_eq!(table.nlookup(2), None);
+        assert_eq!(table.nlookup(3), None);
+    }
+
+    #[test]
+    fn lookup_and_forget() {
+        let mut table = InodeTable::new();
+        table.insert(1, "root");
+
+        for _ in 0..3 {
+            table.lookup(2, || "foo");
+        }
+        assert_eq!(table.nlookup(2), Some(3));
+
+        assert_eq!(table.forget(2, 1), None);
+        assert_eq!(table.forget(2, 2), Some("foo"));
+        assert_eq!(table.get(2), None);
+
+        // The removed inode is never returned twice.
+        assert_eq!(table.forget(2, 1), None);
+        assert_eq!(table.forget(3, 1), None);
+
+        // A forgotten inode can be looked up again.
+        table.lookup(2, || "bar");
+        assert_eq!(table.get(2), Some(&"bar"));
+
+        assert_eq!(table.get(1), Some(&"root"));
+    }
+
+    #[test]
+    fn forget_batch() {
+        let mut table = InodeTable::new();
+        table.lookup(2, || "foo");
+        table.lookup(3, || "bar");
+        table.lookup(3, || "bar");
+
+        let header = fuse_in_header {
+            opcode: fuse_opcode::FUSE_BATCH_FORGET as u32,
+            ..Default::default()
+        };
+        // fuse_batch_forget_in, followed by two fuse_forget_one.
+        let arg: [u64; 5] = [2, 2, 1, 3, 1];
+        let forgets = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Forget(forgets) => forgets,
+            _ => panic!("incorrect operation is returned"),
+        };
+
+        let mut freed = vec![];
+        table.forget_all(&forgets, |ino, value| freed.push((ino, value)));
+        assert_eq!(freed, [(2, "foo")]);
+        assert_eq!(table.nlookup(3), Some(1));
+    }
+}
@@ -1,17 +1,42 @@
 //! A FUSE (Filesystem in Userspace) library for Rust.
+//!
+//! # Integration with async runtimes
+//!
+//! The core of this crate does not depend on any async runtime, nor on the I/O
+//! traits of `futures`.  A `Session` is a plain blocking connection with the
+//! FUSE device, and can be driven from any event loop in the following way:
+//!
+//! 1. Switch the device to the non-blocking mode by `Session::set_nonblocking`.
+//! 2. Register the file descriptor obtained via `AsRawFd` to the reactor of
+//!    the runtime, such as `tokio::io::unix::AsyncFd` or `async_io::Async`.
+//! 3. Call `Session::try_next_request` whenever the descriptor becomes readable,
+//!    until it returns `Poll::Pending`.
+//!
+//! The replies never block, so they can be sent directly from within the async
+//! tasks.  The `with-tokio` and `with-async-std` examples in the repository
+//! demonstrate this pattern; they are not published and nothing else is needed
+//! to use the runtimes.
 
 #![doc(html_root_url = "https://docs.rs/polyfuse/0.4.0")]
 #![forbid(clippy::todo, clippy::unimplemented)]
 
 mod conn;
-mod decoder;
+mod error;
 mod session;
 
 pub mod bytes;
+pub mod decoder;
+pub mod dir;
+pub mod handle;
+pub mod inode;
 pub mod op;
 pub mod reply;
 
 pub use crate::{
+    error::Error,
     op::Operation,
-    session::{Data, KernelConfig, Notifier, Request, Session},
+    session::{
+        Channel, CuseConfig, Data, ExitReason, KernelConfig, KernelInit, Notifier, NotifyBatch,
+        Remount, Request, Retrieve, SecurityContext, Session,
+    },
 };
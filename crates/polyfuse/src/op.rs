@@ -1,26 +1,92 @@
 use crate::decoder::Decoder;
 use polyfuse_kernel::*;
 use std::{convert::TryFrom, ffi::OsStr, fmt, time::Duration, u32, u64};
+use zerocopy::AsBytes as _;
 
+/// The maximum number of bytes dumped in the message of `DecodeError`.
+const MAX_DUMP_LEN: usize = 32;
+
+/// The error that occurs when decoding the argument of a request.
+///
+/// The message contains the opcode, the length of the argument and
+/// the leading bytes of the offending argument, for diagnosing
+/// a protocol mismatch with the kernel.
 #[derive(Debug)]
 pub struct DecodeError {
     inner: crate::decoder::DecodeError,
+    context: Option<Box<DecodeContext>>,
+}
+
+#[derive(Debug)]
+struct DecodeContext {
+    opcode: u32,
+    offset: usize,
+    wanted: Option<usize>,
+    len: usize,
+    dump: Vec<u8>,
 }
 
 impl DecodeError {
     #[inline]
-    const fn new(inner: crate::decoder::DecodeError) -> Self {
-        Self { inner }
+    pub(crate) const fn new(inner: crate::decoder::DecodeError) -> Self {
+        Self {
+            inner,
+            context: None,
+        }
+    }
+
+    fn with_context(self, header: &fuse_in_header, arg: &[u8], decoder: &Decoder<'_>) -> Self {
+        let offset = arg.len() - decoder.remaining().len();
+        Self {
+            context: Some(Box::new(DecodeContext {
+                opcode: header.opcode,
+                offset,
+                wanted: decoder.wanted().map(|wanted| offset.saturating_add(wanted)),
+                len: arg.len(),
+                dump: arg[..arg.len().min(MAX_DUMP_LEN)].to_vec(),
+            })),
+            ..self
+        }
+    }
+
+    /// Return the opcode of the request that failed to decode, if known.
+    pub fn opcode(&self) -> Option<u32> {
+        self.context.as_ref().map(|cx| cx.opcode)
     }
 }
 
 impl fmt::Display for DecodeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "failed to decode request message")
+        write!(f, "failed to decode request message")?;
+
+        let cx = match self.context {
+            Some(ref cx) => cx,
+            None => return Ok(()),
+        };
+        write!(f, " (opcode={}, {}", cx.opcode, self.inner)?;
+        match cx.wanted {
+            Some(wanted) => write!(f, ", expected {} bytes but got {}", wanted, cx.len)?,
+            None => write!(f, " at offset {} of {} bytes", cx.offset, cx.len)?,
+        }
+        f.write_str("): [")?;
+        for (i, b) in cx.dump.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        if cx.len > cx.dump.len() {
+            f.write_str(" ..")?;
+        }
+        f.write_str("]")
     }
 }
 
-impl std::error::Error for DecodeError {}
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.inner)
+    }
+}
 
 /// The kind of filesystem operation requested by the kernel.
 #[non_exhaustive]
@@ -65,8 +131,15 @@ pub enum Operation<'op, T> {
     Interrupt(Interrupt<'op>),
     NotifyReply(NotifyReply<'op>, T),
 
-    #[doc(hidden)]
-    Unknown,
+    /// The request whose opcode is not recognized or not supported by polyfuse,
+    /// e.g. those introduced by the newer kernels.
+    ///
+    /// The filesystem should reply to it with `ENOSYS`, as to the other
+    /// unimplemented operations.
+    Unknown {
+        /// The raw opcode of the request.
+        opcode: u32,
+    },
 }
 
 impl<T> fmt::Debug for Operation<'_, T>
@@ -123,24 +196,40 @@ where
                 .field("data", data)
                 .finish(),
 
-            _ => f.debug_struct("Unknown").finish(),
+            Operation::Unknown { opcode } => {
+                f.debug_struct("Unknown").field("opcode", opcode).finish()
+            }
         }
     }
 }
 
 impl<'op, T> Operation<'op, T> {
     #[inline]
-    pub(crate) fn unknown() -> Self {
-        Self::Unknown
+    pub(crate) fn unknown(opcode: u32) -> Self {
+        Self::Unknown { opcode }
     }
 
+    /// Decode the argument of a request.
+    ///
+    /// `init_flags` is the flags negotiated at the initialization, which
+    /// determine the layout of some argument types.
     pub(crate) fn decode(
         header: &'op fuse_in_header,
         arg: &'op [u8],
         data: T,
+        init_flags: u32,
     ) -> Result<Self, DecodeError> {
         let mut decoder = Decoder::new(arg);
+        Self::decode_arg(header, &mut decoder, data, init_flags)
+            .map_err(|err| err.with_context(header, arg, &decoder))
+    }
 
+    fn decode_arg(
+        header: &'op fuse_in_header,
+        decoder: &mut Decoder<'op>,
+        data: T,
+        init_flags: u32,
+    ) -> Result<Self, DecodeError> {
         match fuse_opcode::try_from(header.opcode).ok() {
             Some(fuse_opcode::FUSE_FORGET) => {
                 let arg: &fuse_forget_in = decoder.fetch().map_err(DecodeError::new)?;
@@ -278,9 +367,20 @@ impl<'op, T> Operation<'op, T> {
             }
 
             Some(fuse_opcode::FUSE_SETXATTR) => {
-                let arg = decoder
-                    .fetch::<fuse_setxattr_in>()
-                    .map_err(DecodeError::new)?;
+                let arg = if init_flags & FUSE_SETXATTR_EXT != 0 {
+                    *decoder
+                        .fetch::<fuse_setxattr_in>()
+                        .map_err(DecodeError::new)?
+                } else {
+                    // The kernel sends the argument without `setxattr_flags`
+                    // unless FUSE_SETXATTR_EXT is negotiated.
+                    let compat = decoder
+                        .fetch_bytes(FUSE_COMPAT_SETXATTR_IN_SIZE)
+                        .map_err(DecodeError::new)?;
+                    let mut arg = fuse_setxattr_in::default();
+                    arg.as_bytes_mut()[..FUSE_COMPAT_SETXATTR_IN_SIZE].copy_from_slice(compat);
+                    arg
+                };
                 let name = decoder.fetch_str().map_err(DecodeError::new)?;
                 let value = decoder
                     .fetch_bytes(arg.size as usize)
@@ -400,7 +500,9 @@ impl<'op, T> Operation<'op, T> {
 
             _ => {
                 tracing::warn!("unsupported opcode: {}", header.opcode);
-                Ok(Operation::Unknown)
+                Ok(Operation::Unknown {
+                    opcode: header.opcode,
+                })
             }
         }
     }
@@ -549,6 +651,9 @@ impl<'op> NotifyReply<'op> {
 }
 
 /// Interrupt a previous FUSE request.
+///
+/// This request is not returned when `Session::set_auto_interrupt` is enabled,
+/// since the session answers the target request automatically.
 pub struct Interrupt<'op> {
     #[allow(dead_code)]
     header: &'op fuse_in_header,
@@ -576,6 +681,10 @@ impl<'op> Interrupt<'op> {
 /// with its attribute using `ReplyEntry`.  In addition, the lookup count
 /// of the corresponding inode is incremented on success.
 ///
+/// If the filesystem is configured with `KernelConfig::export_support`,
+/// the name may be `"."` or `".."`, which must be resolved to the
+/// directory itself and its parent, respectively.
+///
 /// See also the documentation of `ReplyEntry` for tuning the reply parameters.
 pub struct Lookup<'op> {
     header: &'op fuse_in_header,
@@ -639,6 +748,16 @@ impl<'op> Getattr<'op> {
 ///
 /// When the setting of attribute values succeeds, the filesystem replies its value
 /// to the kernel using `ReplyAttr`.
+///
+/// A request with `size` is a truncation of the file, and `fh` and `lock_owner`
+/// are also provided if it comes from `ftruncate(2)` on an opened file.
+/// When `KernelConfig::writeback_cache` is enabled, the kernel flushes the dirty
+/// pages of the file and holds off the subsequent writebacks until the reply
+/// to the truncation is received.  Thus the filesystem does not need to order the
+/// truncation against the buffered writes by itself, as long as it has completed
+/// the `write` requests replied before and applies the new size before replying.
+/// On the other hand, the writebacks may be sent later with any opened handle,
+/// so the filesystem must not assume that `fh` is the handle used for the writes.
 pub struct Setattr<'op> {
     header: &'op fuse_in_header,
     arg: &'op fuse_setattr_in,
@@ -691,6 +810,8 @@ impl<'op> Setattr<'op> {
     }
 
     /// Return the size of the file content to be set.
+    ///
+    /// See the documentation of `Setattr` for the ordering against the writes.
     #[inline]
     pub fn size(&self) -> Option<u64> {
         self.get(FATTR_SIZE, |arg| arg.size)
@@ -703,7 +824,7 @@ impl<'op> Setattr<'op> {
             if arg.valid & FATTR_ATIME_NOW != 0 {
                 SetAttrTime::Now
             } else {
-                SetAttrTime::Timespec(Duration::new(arg.atime, arg.atimensec))
+                SetAttrTime::from_timespec(arg.atime, arg.atimensec)
             }
         })
     }
@@ -715,7 +836,7 @@ impl<'op> Setattr<'op> {
             if arg.valid & FATTR_MTIME_NOW != 0 {
                 SetAttrTime::Now
             } else {
-                SetAttrTime::Timespec(Duration::new(arg.mtime, arg.mtimensec))
+                SetAttrTime::from_timespec(arg.mtime, arg.mtimensec)
             }
         })
     }
@@ -731,20 +852,59 @@ impl<'op> Setattr<'op> {
     pub fn lock_owner(&self) -> Option<LockOwner> {
         self.get(FATTR_LOCKOWNER, |arg| LockOwner::from_raw(arg.lock_owner))
     }
+
+    /// Return whether the filesystem should clear the setuid and setgid bits.
+    ///
+    /// The kernel sets this flag when the file is truncated by a caller without
+    /// `CAP_FSETID`.  The filesystem must then clear `S_ISUID`, and `S_ISGID` if
+    /// the group execute bit is set, atomically with the change of the attributes,
+    /// as `truncate(2)` does on the local filesystems.
+    ///
+    /// This flag is set only if `KernelConfig::handle_killpriv_v2` is enabled.
+    #[inline]
+    pub fn kill_suidgid(&self) -> bool {
+        self.arg.valid & FATTR_KILL_SUIDGID != 0
+    }
 }
 
 /// The time value requested to be set.
 #[derive(Copy, Clone, Debug)]
 #[non_exhaustive]
 pub enum SetAttrTime {
-    /// Set the specified time value.
+    /// Set the specified time value, as the elapsed time since the UNIX epoch.
     Timespec(Duration),
 
     /// Set the current time.
     Now,
+
+    /// Set the specified time value, as the duration before the UNIX epoch.
+    BeforeEpoch(Duration),
+}
+
+impl SetAttrTime {
+    /// Convert the seconds and nanoseconds sent by the kernel, where the seconds
+    /// are the two's complement of `time_t`.
+    fn from_timespec(secs: u64, nsecs: u32) -> Self {
+        let secs = secs as i64;
+        if secs >= 0 {
+            Self::Timespec(Duration::new(secs as u64, nsecs))
+        } else {
+            let before = Duration::from_secs(secs.wrapping_neg() as u64);
+            let after = Duration::new(0, nsecs);
+            match before.checked_sub(after) {
+                Some(before) => Self::BeforeEpoch(before),
+                // The malformed nanoseconds out of range carry the time past the epoch.
+                None => Self::Timespec(after - before),
+            }
+        }
+    }
 }
 
 /// Read a symbolic link.
+///
+/// The filesystem should reply with the target of the link without the trailing
+/// NUL, e.g. by `ReadlinkOut`, which also checks that the target fits in the
+/// buffer provided by the kernel.
 pub struct Readlink<'op> {
     header: &'op fuse_in_header,
 }
@@ -846,10 +1006,26 @@ impl<'op> Mknod<'op> {
         self.arg.rdev
     }
 
-    #[doc(hidden)] // TODO: dox
+    /// Return the umask of the calling process.
+    ///
+    /// If `KernelConfig::dont_mask` is negotiated, the kernel does not apply
+    /// the umask to `mode`, and the filesystem must apply it by itself,
+    /// i.e. create the node with `mode & !umask`.  Otherwise, `mode` has
+    /// already been masked.
+    #[inline]
     pub fn umask(&self) -> u32 {
         self.arg.umask
     }
+
+    /// Return the file mode with the umask applied, i.e. `mode & !umask`.
+    ///
+    /// The kernel always sends the umask of the calling process, so the result
+    /// is the mode to create the node with, regardless of whether
+    /// `KernelConfig::dont_mask` is negotiated.
+    #[inline]
+    pub fn masked_mode(&self) -> u32 {
+        self.arg.mode & !self.arg.umask
+    }
 }
 
 /// Create a directory node.
@@ -888,10 +1064,26 @@ impl<'op> Mkdir<'op> {
         self.arg.mode
     }
 
-    #[doc(hidden)] // TODO: dox
+    /// Return the umask of the calling process.
+    ///
+    /// If `KernelConfig::dont_mask` is negotiated, the kernel does not apply
+    /// the umask to `mode`, and the filesystem must apply it by itself,
+    /// i.e. create the node with `mode & !umask`.  Otherwise, `mode` has
+    /// already been masked.
+    #[inline]
     pub fn umask(&self) -> u32 {
         self.arg.umask
     }
+
+    /// Return the file mode with the umask applied, i.e. `mode & !umask`.
+    ///
+    /// The kernel always sends the umask of the calling process, so the result
+    /// is the mode to create the node with, regardless of whether
+    /// `KernelConfig::dont_mask` is negotiated.
+    #[inline]
+    pub fn masked_mode(&self) -> u32 {
+        self.arg.mode & !self.arg.umask
+    }
 }
 
 // TODO: description about lookup count.
@@ -1056,6 +1248,12 @@ impl<'op> Link<'op> {
 /// handling the opened file.
 ///
 /// See also the documentation of `ReplyOpen` for tuning the reply parameters.
+///
+/// If the filesystem does not need any per-open state, it should reply with
+/// an `ENOSYS` error when `Session::no_open_support` returns `true`.  In that
+/// case, the kernel treats the error as a successful open with the handle `0`,
+/// and never sends `open` requests for the subsequent opens.
+/// `Session::set_zero_message_open` lets the session reply it automatically.
 pub struct Open<'op> {
     header: &'op fuse_in_header,
     arg: &'op fuse_open_in,
@@ -1080,17 +1278,38 @@ impl<'op> Open<'op> {
     /// Return the open flags.
     ///
     /// The creating flags (`O_CREAT`, `O_EXCL` and `O_NOCTTY`) are removed and
-    /// these flags are handled by the kernel.
+    /// these flags are handled by the kernel.  The file mode and the umask for
+    /// creating a file are not passed to this operation: when `open(2)` creates
+    /// a new file, the kernel sends either `Create` or `Mknod` beforehand.
     ///
     /// If the mount option contains `-o default_permissions`, the access mode flags
     /// (`O_RDONLY`, `O_WRONLY` and `O_RDWR`) might be handled by the kernel and in that case,
     /// these flags are omitted before issuing the request. Otherwise, the filesystem should
     /// handle these flags and return an `EACCES` error when provided access mode is
     /// invalid.
+    ///
+    /// When `KernelConfig::atomic_o_trunc` is enabled (the default), `O_TRUNC` is
+    /// kept in the flags and the kernel does not send a separate `setattr` request
+    /// for truncating the file.  The filesystem must truncate the file to zero
+    /// length as a part of the open if `O_TRUNC` is present, e.g. by passing it
+    /// through to the backing file.  Otherwise, `O_TRUNC` is removed from the flags.
     #[inline]
     pub fn flags(&self) -> u32 {
         self.arg.flags
     }
+
+    /// Return whether the filesystem should clear the setuid and setgid bits
+    /// when the file is truncated by `O_TRUNC`.
+    ///
+    /// This flag is set only if `KernelConfig::handle_killpriv_v2` is enabled,
+    /// the open carries `O_TRUNC` and the caller lacks `CAP_FSETID`.  In that case,
+    /// the filesystem should clear `S_ISUID`, and `S_ISGID` if the group execute
+    /// bit is also set, as a part of truncating the file.  When the flag is not
+    /// set, the filesystem must leave these bits untouched.
+    #[inline]
+    pub fn kill_suidgid(&self) -> bool {
+        self.arg.open_flags & FUSE_OPEN_KILL_SUIDGID != 0
+    }
 }
 
 /// Read data from a file.
@@ -1104,6 +1323,16 @@ impl<'op> Open<'op> {
 /// the filesystem should send *exactly* the specified range of file content to the
 /// kernel. If the length of the passed data is shorter than `size`, the rest of
 /// the data will be substituted with zeroes.
+///
+/// # Direct I/O
+///
+/// Only the reads through the page cache are aligned to the page size.  When the
+/// file is opened in `direct_io` mode or by the caller with `O_DIRECT` (see `direct`),
+/// `offset` and `size` are the ones passed to the syscall as they are, and may
+/// not be aligned at all.  The filesystem forwarding such requests to a backing
+/// file opened with `O_DIRECT` must align them to the logical block size of the
+/// backing device by itself (e.g. by reading the enclosing blocks into an aligned
+/// buffer), or the backing file fails with `EINVAL`.
 pub struct Read<'op> {
     header: &'op fuse_in_header,
     arg: &'op fuse_read_in,
@@ -1147,6 +1376,14 @@ impl<'op> Read<'op> {
         self.arg.flags
     }
 
+    /// Return whether the file is opened by the caller with `O_DIRECT`.
+    ///
+    /// See the section "Direct I/O" in the documentation of `Read`.
+    #[inline]
+    pub fn direct(&self) -> bool {
+        self.arg.flags & libc::O_DIRECT as u32 != 0
+    }
+
     /// Return the identifier of lock owner.
     #[inline]
     pub fn lock_owner(&self) -> Option<LockOwner> {
@@ -1168,6 +1405,22 @@ impl<'op> Read<'op> {
 ///
 /// When the file is not opened in `direct_io` mode (i.e. the page caching is enabled),
 /// the filesystem should receive *exactly* the specified range of file content from the kernel.
+///
+/// # Direct I/O
+///
+/// As for `Read`, the range of the write is not aligned when the file is opened
+/// in `direct_io` mode or with `O_DIRECT`.  Forwarding it to a backing file opened
+/// with `O_DIRECT` requires a read-modify-write of the enclosing blocks through
+/// an aligned buffer.
+///
+/// # Writeback cache
+///
+/// When `KernelConfig::writeback_cache` is enabled, the kernel writes back the dirty
+/// pages in the page cache asynchronously, and such requests are marked with
+/// `is_writeback`.  The caller identity in the request header (`uid`, `gid` and `pid`)
+/// then does not refer to the process that originally wrote the data, so the filesystem
+/// should not apply per-caller permission checks to those requests; the permission
+/// has already been checked when the file was opened.
 pub struct Write<'op> {
     header: &'op fuse_in_header,
     arg: &'op fuse_write_in,
@@ -1211,6 +1464,14 @@ impl<'op> Write<'op> {
         self.arg.flags
     }
 
+    /// Return whether the file is opened by the caller with `O_DIRECT`.
+    ///
+    /// See the section "Direct I/O" in the documentation of `Write`.
+    #[inline]
+    pub fn direct(&self) -> bool {
+        self.arg.flags & libc::O_DIRECT as u32 != 0
+    }
+
     /// Return the identifier of lock owner.
     #[inline]
     pub fn lock_owner(&self) -> Option<LockOwner> {
@@ -1220,6 +1481,22 @@ impl<'op> Write<'op> {
             None
         }
     }
+
+    /// Return whether this request writes back the dirty pages in the page cache.
+    ///
+    /// See the section "Writeback cache" in the documentation of `Write`.
+    #[inline]
+    pub fn is_writeback(&self) -> bool {
+        self.arg.write_flags & FUSE_WRITE_CACHE != 0
+    }
+
+    /// Return whether the filesystem should clear the setuid and setgid bits.
+    ///
+    /// This flag is set only if `KernelConfig::handle_killpriv_v2` is enabled.
+    #[inline]
+    pub fn kill_suidgid(&self) -> bool {
+        self.arg.write_flags & FUSE_WRITE_KILL_SUIDGID != 0
+    }
 }
 
 /// Release an opened file.
@@ -1333,7 +1610,7 @@ impl<'op> Fsync<'op> {
 /// Set an extended attribute.
 pub struct Setxattr<'op> {
     header: &'op fuse_in_header,
-    arg: &'op fuse_setxattr_in,
+    arg: fuse_setxattr_in,
     name: &'op OsStr,
     value: &'op [u8],
 }
@@ -1365,10 +1642,23 @@ impl<'op> Setxattr<'op> {
     }
 
     /// Return the flags that specifies the meanings of this operation.
+    ///
+    /// The value is a combination of `XATTR_CREATE` and `XATTR_REPLACE`.
+    /// If `XATTR_CREATE` is set and the attribute already exists, the filesystem
+    /// should send an `EEXIST` error.  If `XATTR_REPLACE` is set and the attribute
+    /// does not exist, it should send an `ENODATA` error.
     #[inline]
     pub fn flags(&self) -> u32 {
         self.arg.flags
     }
+
+    /// Return whether the SGID bit should be cleared when setting the POSIX ACL.
+    ///
+    /// This value is always `false` unless `KernelConfig::setxattr_ext` is enabled.
+    #[inline]
+    pub fn acl_kill_sgid(&self) -> bool {
+        self.arg.setxattr_flags & FUSE_SETXATTR_ACL_KILL_SGID != 0
+    }
 }
 
 /// Get an extended attribute.
@@ -1483,6 +1773,12 @@ impl<'op> Removexattr<'op> {
 /// flush operations might be issued for one `Open`.
 /// Also, it is not guaranteed that flush will always be issued
 /// after some writes.
+///
+/// When the filesystem implements the POSIX locks (i.e. `KernelConfig::posix_locks`
+/// is enabled), it must release all of the locks held by `lock_owner` on the file
+/// when receiving this request.  The kernel never sends the unlocking `setlk`
+/// request on close, since closing any file descriptor drops the locks of the
+/// process on the file; without releasing them here, the locks leak.
 pub struct Flush<'op> {
     header: &'op fuse_in_header,
     arg: &'op fuse_flush_in,
@@ -1509,6 +1805,9 @@ impl<'op> Flush<'op> {
     }
 
     /// Return the identifier of lock owner.
+    ///
+    /// It is the same as `Setlk::owner` of the lock requests issued by the
+    /// process closing the file descriptor.
     #[inline]
     pub fn lock_owner(&self) -> LockOwner {
         LockOwner::from_raw(self.arg.lock_owner)
@@ -1519,6 +1818,10 @@ impl<'op> Flush<'op> {
 ///
 /// If the directory is successfully opened, the filesystem must send
 /// the identifier to the opened directory handle using `ReplyOpen`.
+///
+/// Similar to `Open`, the filesystem should reply with an `ENOSYS` error
+/// to avoid the subsequent `opendir` requests when `Session::no_opendir_support`
+/// returns `true`.
 pub struct Opendir<'op> {
     header: &'op fuse_in_header,
     arg: &'op fuse_open_in,
@@ -1823,6 +2126,10 @@ impl<'op> Flock<'op> {
 }
 
 /// Check file access permissions.
+///
+/// This request is sent on `access(2)` and `chdir(2)`, only if the filesystem is
+/// mounted without `default_permissions`, since the kernel checks the permissions
+/// by itself otherwise.  See `KernelConfig::default_permissions` for details.
 pub struct Access<'op> {
     header: &'op fuse_in_header,
     arg: &'op fuse_access_in,
@@ -1853,9 +2160,24 @@ impl<'op> Access<'op> {
 ///
 /// This operation is a combination of `Mknod` and `Open`. If an `ENOSYS` error is returned
 /// for this operation, those operations will be used instead.
+/// `FUSE_CREATE` is available on every ABI version supported by this crate
+/// (see `Session::protocol_minor`), so the fallback happens only when the
+/// filesystem chooses not to implement this operation.
+/// Since `Open` carries neither the file mode nor the umask, the filesystem
+/// should create the file with `masked_mode` in both `Create` and `Mknod`,
+/// so that the created file has the same permissions whichever path the kernel takes.
+///
+/// If the file is successfully created and opened, a pair of `EntryOut` and `OpenOut`
+/// with the corresponding attribute values and the file handle must be sent to the kernel,
+/// e.g. by `req.reply((entry_out, open_out))`.
 ///
-/// If the file is successfully created and opened, a pair of `ReplyEntry` and `ReplyOpen`
-/// with the corresponding attribute values and the file handle must be sent to the kernel.
+/// Unlike `Open`, the kernel does not accept an `ENOSYS` error for this operation
+/// as the zero-message open, even if `Session::no_open_support` is `true`.
+/// Instead, a stateless filesystem that does not keep the file open can leave
+/// the file handle zero, as `OpenOut::default()`.  The kernel never interprets
+/// the value of the handle and passes it back as is in the subsequent requests
+/// on the file, such as `read`, `write` and `release`.  The other fields of
+/// `OpenOut` (e.g. `keep_cache` or `direct_io`) take effect regardless of the handle.
 pub struct Create<'op> {
     header: &'op fuse_in_header,
     arg: &'op fuse_create_in,
@@ -1896,17 +2218,39 @@ impl<'op> Create<'op> {
 
     /// Return the open flags.
     ///
-    /// This is the same as `Open::flags`.
+    /// This is the same as `Open::flags`.  Note that the flags passed to `open(2)`
+    /// are stored in the `flags` field of `fuse_create_in`, while its `open_flags`
+    /// field carries the `FUSE_OPEN_*` flags, such as the one checked by `kill_suidgid`.
+    #[allow(clippy::misnamed_getters)]
     #[inline]
     pub fn open_flags(&self) -> u32 {
         self.arg.flags
     }
 
-    #[doc(hidden)] // TODO: dox
+    /// Return whether the filesystem should clear the setuid and setgid bits
+    /// when the file is truncated by `O_TRUNC`.
+    ///
+    /// This is the same as `Open::kill_suidgid`.
+    #[inline]
+    pub fn kill_suidgid(&self) -> bool {
+        self.arg.open_flags & FUSE_OPEN_KILL_SUIDGID != 0
+    }
+
+    /// Return the umask of the calling process.
+    ///
+    /// This is the same as `Mknod::umask`.
     #[inline]
     pub fn umask(&self) -> u32 {
         self.arg.umask
     }
+
+    /// Return the file mode with the umask applied.
+    ///
+    /// This is the same as `Mknod::masked_mode`.
+    #[inline]
+    pub fn masked_mode(&self) -> u32 {
+        self.arg.mode & !self.arg.umask
+    }
 }
 
 /// Map block index within a file to block index within device.
@@ -2068,7 +2412,18 @@ impl<'op> CopyFileRange<'op> {
 
 /// Poll for readiness.
 ///
-/// The mask of ready poll events must be replied using `ReplyPoll`.
+/// The mask of ready poll events must be replied using `PollOut`.
+///
+/// The request comes in one of the two modes, distinguished by `schedule_notify`:
+///
+/// * If it returns `false`, the kernel only queries the current readiness.
+///   The filesystem replies the ready events and must not send any wakeup.
+/// * If it returns `true`, the caller is going to wait for the events.  In addition
+///   to replying the current readiness, the filesystem must keep the poll handle
+///   returned by `kh` and send `Notifier::poll_wakeup` with that handle once
+///   the events become ready.  The kernel then polls the file again.
+///
+/// Confusing them makes the caller of `poll(2)` either spin or hang forever.
 pub struct Poll<'op> {
     header: &'op fuse_in_header,
     arg: &'op fuse_poll_in,
@@ -2104,16 +2459,557 @@ impl<'op> Poll<'op> {
         self.arg.events
     }
 
+    /// Return whether the kernel asks for a wakeup notification when the events
+    /// become ready, i.e. `FUSE_POLL_SCHEDULE_NOTIFY` is set.
+    #[inline]
+    pub fn schedule_notify(&self) -> bool {
+        self.arg.flags & FUSE_POLL_SCHEDULE_NOTIFY != 0
+    }
+
     /// Return the handle to this poll.
     ///
     /// If the returned value is not `None`, the filesystem should send the notification
-    /// when the corresponding I/O will be ready.
+    /// when the corresponding I/O will be ready.  The poll handles can be tracked by
+    /// `PollHandles` in the `handle` module.  It returns `Some` if and only if
+    /// `schedule_notify` returns `true`.
     #[inline]
     pub fn kh(&self) -> Option<u64> {
-        if self.arg.flags & FUSE_POLL_SCHEDULE_NOTIFY != 0 {
+        if self.schedule_notify() {
             Some(self.arg.kh)
         } else {
             None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(opcode: fuse_opcode, nodeid: u64) -> fuse_in_header {
+        fuse_in_header {
+            opcode: opcode as u32,
+            nodeid,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn decode_lookup_dotdot() {
+        let header = header(fuse_opcode::FUSE_LOOKUP, 5);
+        let arg = b"..\0";
+
+        let op = match Operation::decode(&header, &arg[..], (), 0).unwrap() {
+            Operation::Lookup(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert_eq!(op.parent(), 5);
+        assert_eq!(op.name(), OsStr::new(".."));
+    }
+
+    #[test]
+    fn decode_unterminated_names() {
+        let decode = |opcode: fuse_opcode, arg: &[u8]| {
+            let header = header(opcode, 1);
+            match Operation::decode(&header, arg, (), 0) {
+                Ok(..) => panic!("unterminated name is decoded"),
+                Err(err) => {
+                    assert!(matches!(
+                        err.inner,
+                        crate::decoder::DecodeError::MissingNulCharacter
+                    ));
+                    assert_eq!(err.opcode(), Some(opcode as u32));
+                }
+            }
+        };
+
+        decode(fuse_opcode::FUSE_LOOKUP, b"foo");
+        decode(fuse_opcode::FUSE_UNLINK, b"foo");
+        decode(fuse_opcode::FUSE_RMDIR, b"foo");
+        decode(fuse_opcode::FUSE_LOOKUP, b"");
+
+        // The terminator of the first name does not terminate the second one.
+        let mut arg = fuse_rename_in { newdir: 2 }.as_bytes().to_vec();
+        arg.extend_from_slice(b"foo\0bar");
+        decode(fuse_opcode::FUSE_RENAME, &arg[..]);
+    }
+
+    #[test]
+    fn decode_getattr() {
+        let header = header(fuse_opcode::FUSE_GETATTR, 3);
+        let arg = fuse_getattr_in {
+            getattr_flags: FUSE_GETATTR_FH,
+            dummy: 0,
+            fh: 7,
+        };
+
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Getattr(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert_eq!(op.ino(), 3);
+        assert_eq!(op.fh(), Some(7));
+
+        let arg = fuse_getattr_in {
+            getattr_flags: 0,
+            ..arg
+        };
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Getattr(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert_eq!(op.fh(), None);
+    }
+
+    #[test]
+    fn decode_write_kill_suidgid() {
+        let header = header(fuse_opcode::FUSE_WRITE, 2);
+        let arg = fuse_write_in {
+            fh: 1,
+            offset: 0,
+            size: 0,
+            write_flags: FUSE_WRITE_KILL_SUIDGID,
+            lock_owner: 0,
+            flags: libc::O_WRONLY as u32,
+            padding: 0,
+        };
+
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Write(op, ()) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert!(op.kill_suidgid());
+        assert!(op.lock_owner().is_none());
+        assert!(!op.direct());
+        assert!(!op.is_writeback());
+
+        let arg = fuse_write_in {
+            write_flags: FUSE_WRITE_LOCKOWNER,
+            flags: libc::O_WRONLY as u32 | libc::O_DIRECT as u32,
+            ..arg
+        };
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Write(op, ()) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert!(!op.kill_suidgid());
+        assert!(op.direct());
+    }
+
+    #[test]
+    fn decode_write_writeback() {
+        let header = header(fuse_opcode::FUSE_WRITE, 2);
+        let arg = fuse_write_in {
+            fh: 1,
+            offset: 4096,
+            size: 0,
+            write_flags: FUSE_WRITE_CACHE,
+            lock_owner: 0,
+            flags: libc::O_WRONLY as u32,
+            padding: 0,
+        };
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Write(op, ()) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert!(op.is_writeback());
+        assert!(op.lock_owner().is_none());
+        assert!(!op.kill_suidgid());
+
+        let arg = fuse_write_in {
+            write_flags: FUSE_WRITE_CACHE | FUSE_WRITE_LOCKOWNER,
+            lock_owner: 0xdead,
+            ..arg
+        };
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Write(op, ()) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert!(op.is_writeback());
+        assert!(op.lock_owner().is_some());
+
+        let arg = fuse_write_in {
+            write_flags: 0,
+            ..arg
+        };
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Write(op, ()) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert!(!op.is_writeback());
+    }
+
+    #[test]
+    fn decode_mkdir() {
+        let header = header(fuse_opcode::FUSE_MKDIR, 1);
+        let arg = fuse_mkdir_in {
+            mode: 0o777,
+            umask: 0o022,
+        };
+        let mut arg = arg.as_bytes().to_vec();
+        arg.extend_from_slice(b"foo\0");
+
+        let op = match Operation::decode(&header, &arg[..], (), FUSE_DONT_MASK).unwrap() {
+            Operation::Mkdir(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert_eq!(op.parent(), 1);
+        assert_eq!(op.name(), "foo");
+        assert_eq!(op.mode(), 0o777);
+        assert_eq!(op.umask(), 0o022);
+        assert_eq!(op.masked_mode(), 0o755);
+    }
+
+    #[test]
+    fn decode_create_fallback_mode() {
+        let mode = libc::S_IFREG | 0o666;
+
+        let header = header(fuse_opcode::FUSE_CREATE, 1);
+        let arg = fuse_create_in {
+            flags: (libc::O_WRONLY | libc::O_CREAT) as u32,
+            mode,
+            umask: 0o027,
+            open_flags: 0,
+        };
+        let mut arg = arg.as_bytes().to_vec();
+        arg.extend_from_slice(b"foo\0");
+        let create = match Operation::decode(&header, &arg[..], (), FUSE_DONT_MASK).unwrap() {
+            Operation::Create(op) => op.masked_mode(),
+            _ => panic!("incorrect operation is returned"),
+        };
+
+        // The kernel falls back to mknod and open if create is not supported.
+        let header = self::header(fuse_opcode::FUSE_MKNOD, 1);
+        let arg = fuse_mknod_in {
+            mode,
+            rdev: 0,
+            umask: 0o027,
+            padding: 0,
+        };
+        let mut arg = arg.as_bytes().to_vec();
+        arg.extend_from_slice(b"foo\0");
+        let mknod = match Operation::decode(&header, &arg[..], (), FUSE_DONT_MASK).unwrap() {
+            Operation::Mknod(op) => op.masked_mode(),
+            _ => panic!("incorrect operation is returned"),
+        };
+
+        assert_eq!(create, libc::S_IFREG | 0o640);
+        assert_eq!(mknod, create);
+    }
+
+    #[test]
+    fn decode_setattr_truncate() {
+        let header = header(fuse_opcode::FUSE_SETATTR, 2);
+        let arg = fuse_setattr_in {
+            valid: FATTR_SIZE | FATTR_FH | FATTR_LOCKOWNER,
+            fh: 3,
+            size: 4096,
+            lock_owner: 42,
+            ..Default::default()
+        };
+
+        let op = match Operation::decode(&header, arg.as_bytes(), (), FUSE_WRITEBACK_CACHE).unwrap()
+        {
+            Operation::Setattr(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert_eq!(op.ino(), 2);
+        assert_eq!(op.size(), Some(4096));
+        assert_eq!(op.fh(), Some(3));
+        assert_eq!(op.lock_owner().map(LockOwner::into_raw), Some(42));
+        assert!(op.mode().is_none());
+
+        // truncate(2) by path.
+        let arg = fuse_setattr_in {
+            valid: FATTR_SIZE,
+            ..arg
+        };
+        let op = match Operation::decode(&header, arg.as_bytes(), (), FUSE_WRITEBACK_CACHE).unwrap()
+        {
+            Operation::Setattr(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert_eq!(op.size(), Some(4096));
+        assert!(op.fh().is_none());
+        assert!(op.lock_owner().is_none());
+        assert!(!op.kill_suidgid());
+    }
+
+    #[test]
+    fn decode_setattr_kill_suidgid() {
+        let header = header(fuse_opcode::FUSE_SETATTR, 2);
+        let decode = |valid: u32| {
+            let arg = fuse_setattr_in {
+                valid,
+                size: 0,
+                ..Default::default()
+            };
+            match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+                Operation::Setattr(op) => (op.size(), op.kill_suidgid()),
+                _ => panic!("incorrect operation is returned"),
+            }
+        };
+        assert_eq!(decode(FATTR_SIZE | FATTR_KILL_SUIDGID), (Some(0), true));
+        assert_eq!(decode(FATTR_SIZE), (Some(0), false));
+    }
+
+    #[test]
+    fn decode_setattr_before_epoch() {
+        let header = header(fuse_opcode::FUSE_SETATTR, 2);
+        let arg = fuse_setattr_in {
+            valid: FATTR_ATIME | FATTR_MTIME,
+            atime: 1_600_000_000,
+            atimensec: 1,
+            mtime: -86_400i64 as u64,
+            mtimensec: 250_000_000,
+            ..Default::default()
+        };
+
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Setattr(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        match op.atime() {
+            Some(SetAttrTime::Timespec(atime)) => {
+                assert_eq!(atime, Duration::new(1_600_000_000, 1))
+            }
+            t => panic!("unexpected atime: {:?}", t),
+        }
+        match op.mtime() {
+            Some(SetAttrTime::BeforeEpoch(mtime)) => {
+                assert_eq!(mtime, Duration::new(86_399, 750_000_000))
+            }
+            t => panic!("unexpected mtime: {:?}", t),
+        }
+    }
+
+    #[test]
+    fn decode_setattr_nsecs_out_of_range() {
+        let header = header(fuse_opcode::FUSE_SETATTR, 2);
+        let arg = fuse_setattr_in {
+            valid: FATTR_ATIME | FATTR_MTIME,
+            atime: -1i64 as u64,
+            atimensec: 1_500_000_000,
+            mtime: -2i64 as u64,
+            mtimensec: 1_500_000_000,
+            ..Default::default()
+        };
+
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Setattr(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        match op.atime() {
+            Some(SetAttrTime::Timespec(atime)) => {
+                assert_eq!(atime, Duration::from_millis(500))
+            }
+            t => panic!("unexpected atime: {:?}", t),
+        }
+        match op.mtime() {
+            Some(SetAttrTime::BeforeEpoch(mtime)) => {
+                assert_eq!(mtime, Duration::from_millis(500))
+            }
+            t => panic!("unexpected mtime: {:?}", t),
+        }
+    }
+
+    #[test]
+    fn decode_short_payload() {
+        let header = header(fuse_opcode::FUSE_GETATTR, 2);
+        let arg = [0xabu8; 8];
+        let err = match Operation::decode(&header, &arg[..], (), 0) {
+            Err(err) => err,
+            Ok(..) => panic!("short payload is decoded"),
+        };
+        assert_eq!(err.opcode(), Some(fuse_opcode::FUSE_GETATTR as u32));
+
+        let msg = err.to_string();
+        assert!(msg.contains("opcode=3"), "{}", msg);
+        assert!(msg.contains("expected 16 bytes but got 8"), "{}", msg);
+        assert!(msg.ends_with("[ab ab ab ab ab ab ab ab]"), "{}", msg);
+    }
+
+    #[test]
+    fn decode_poll() {
+        let header = header(fuse_opcode::FUSE_POLL, 2);
+        let arg = fuse_poll_in {
+            fh: 1,
+            kh: 10,
+            flags: 0,
+            events: libc::POLLIN as u32,
+        };
+
+        // Query the current readiness only.
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Poll(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert!(!op.schedule_notify());
+        assert_eq!(op.kh(), None);
+        assert_eq!(op.events(), libc::POLLIN as u32);
+
+        // Wait for the wakeup notification with the poll handle.
+        let arg = fuse_poll_in {
+            flags: FUSE_POLL_SCHEDULE_NOTIFY,
+            ..arg
+        };
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Poll(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert!(op.schedule_notify());
+        assert_eq!(op.kh(), Some(10));
+    }
+
+    #[test]
+    fn decode_unknown() {
+        let mut header = header(fuse_opcode::FUSE_GETATTR, 1);
+        header.opcode = 9999;
+        let op = Operation::decode(&header, &[0u8; 16], (), 0).unwrap();
+        assert!(matches!(op, Operation::Unknown { opcode: 9999 }));
+        assert_eq!(format!("{:?}", op), "Unknown { opcode: 9999 }");
+    }
+
+    #[test]
+    fn decode_open_trunc() {
+        let header = header(fuse_opcode::FUSE_OPEN, 2);
+        let arg = fuse_open_in {
+            flags: libc::O_WRONLY as u32 | libc::O_TRUNC as u32,
+            open_flags: FUSE_OPEN_KILL_SUIDGID,
+        };
+
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Open(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert_eq!(op.ino(), 2);
+        assert_eq!(op.flags() & libc::O_ACCMODE as u32, libc::O_WRONLY as u32);
+        assert!(op.flags() & libc::O_TRUNC as u32 != 0);
+        assert!(op.kill_suidgid());
+
+        // The flag is absent unless the kernel requests it.
+        let arg = fuse_open_in {
+            open_flags: 0,
+            ..arg
+        };
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Open(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert!(op.flags() & libc::O_TRUNC as u32 != 0);
+        assert!(!op.kill_suidgid());
+    }
+
+    #[test]
+    fn decode_flush() {
+        let header = header(fuse_opcode::FUSE_FLUSH, 2);
+        let arg = fuse_flush_in {
+            fh: 42,
+            unused: 0,
+            padding: 0,
+            lock_owner: 0xdead_beef,
+        };
+
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Flush(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert_eq!(op.ino(), 2);
+        assert_eq!(op.fh(), 42);
+        assert_eq!(op.lock_owner(), LockOwner::from_raw(0xdead_beef));
+    }
+
+    #[test]
+    fn decode_opendir() {
+        let header = header(fuse_opcode::FUSE_OPENDIR, 1);
+        let arg = fuse_open_in {
+            flags: libc::O_RDONLY as u32 | libc::O_DIRECTORY as u32,
+            open_flags: 0,
+        };
+
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Opendir(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert_eq!(op.ino(), 1);
+        assert_eq!(op.flags(), libc::O_RDONLY as u32 | libc::O_DIRECTORY as u32);
+    }
+
+    #[test]
+    fn decode_setxattr() {
+        let header = header(fuse_opcode::FUSE_SETXATTR, 2);
+        let legacy = fuse_setxattr_in {
+            size: 3,
+            flags: libc::XATTR_CREATE as u32,
+            ..Default::default()
+        };
+        let mut arg = legacy.as_bytes()[..FUSE_COMPAT_SETXATTR_IN_SIZE].to_vec();
+        arg.extend_from_slice(b"user.foo\0bar");
+
+        let op = match Operation::decode(&header, &arg[..], (), 0).unwrap() {
+            Operation::Setxattr(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert_eq!(op.ino(), 2);
+        assert_eq!(op.name(), "user.foo");
+        assert_eq!(op.value(), b"bar");
+        assert_eq!(op.flags(), libc::XATTR_CREATE as u32);
+        assert!(!op.acl_kill_sgid());
+
+        let ext = fuse_setxattr_in {
+            size: 3,
+            flags: libc::XATTR_REPLACE as u32,
+            setxattr_flags: FUSE_SETXATTR_ACL_KILL_SGID,
+            padding: 0,
+        };
+        let mut arg = ext.as_bytes().to_vec();
+        arg.extend_from_slice(b"user.foo\0bar");
+
+        let op = match Operation::decode(&header, &arg[..], (), FUSE_SETXATTR_EXT).unwrap() {
+            Operation::Setxattr(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert_eq!(op.name(), "user.foo");
+        assert_eq!(op.value(), b"bar");
+        assert_eq!(op.flags(), libc::XATTR_REPLACE as u32);
+        assert!(op.acl_kill_sgid());
+    }
+
+    #[test]
+    fn decode_read() {
+        let header = header(fuse_opcode::FUSE_READ, 2);
+        let arg = fuse_read_in {
+            fh: 42,
+            offset: 4096,
+            size: 512,
+            read_flags: FUSE_READ_LOCKOWNER,
+            lock_owner: 0xdead_beef,
+            flags: libc::O_RDONLY as u32 | libc::O_DIRECT as u32,
+            padding: 0,
+        };
+
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Read(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert_eq!(op.ino(), 2);
+        assert_eq!(op.fh(), 42);
+        assert_eq!(op.offset(), 4096);
+        assert_eq!(op.size(), 512);
+        assert_eq!(op.flags(), libc::O_RDONLY as u32 | libc::O_DIRECT as u32);
+        assert!(op.direct());
+        assert_eq!(op.lock_owner().map(LockOwner::into_raw), Some(0xdead_beef));
+
+        let arg = fuse_read_in {
+            read_flags: 0,
+            flags: libc::O_RDONLY as u32,
+            ..arg
+        };
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Read(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+        assert!(op.lock_owner().is_none());
+        assert!(!op.direct());
+    }
+}
@@ -1,6 +1,12 @@
 use crate::bytes::{Bytes, FillBytes};
 use polyfuse_kernel::*;
-use std::{convert::TryInto as _, ffi::OsStr, fmt, mem, os::unix::prelude::*, time::Duration};
+use std::{
+    convert::TryInto as _,
+    ffi::OsStr,
+    fmt, io, mem,
+    os::unix::prelude::*,
+    time::{Duration, Instant},
+};
 use zerocopy::AsBytes as _;
 
 /// Attributes about a file.
@@ -69,26 +75,104 @@ impl FileAttr {
         self.attr.blocks = blocks;
     }
 
-    /// Set the last accessed time.
+    /// Set the last accessed time, as the elapsed time since the UNIX epoch.
+    ///
+    /// Use `atime_timespec` to specify the time before the epoch.
     #[inline]
     pub fn atime(&mut self, atime: Duration) {
         self.attr.atime = atime.as_secs();
         self.attr.atimensec = atime.subsec_nanos();
     }
 
-    /// Set the last modification time.
+    /// Set the last accessed time from the fields of a `timespec`.
+    ///
+    /// The seconds may be negative for the time before the UNIX epoch, and
+    /// the nanoseconds are added to them as `timespec`, e.g. `(-1, 999_999_999)`
+    /// means one nanosecond before the epoch.
+    #[inline]
+    pub fn atime_timespec(&mut self, secs: i64, nsecs: u32) {
+        self.attr.atime = secs as u64;
+        self.attr.atimensec = nsecs;
+    }
+
+    /// Set the last modification time, as the elapsed time since the UNIX epoch.
+    ///
+    /// Use `mtime_timespec` to specify the time before the epoch.
     #[inline]
     pub fn mtime(&mut self, mtime: Duration) {
         self.attr.mtime = mtime.as_secs();
         self.attr.mtimensec = mtime.subsec_nanos();
     }
 
-    /// Set the last created time.
+    /// Set the last modification time from the fields of a `timespec`.
+    ///
+    /// See the documentation of `atime_timespec` for details.
+    #[inline]
+    pub fn mtime_timespec(&mut self, secs: i64, nsecs: u32) {
+        self.attr.mtime = secs as u64;
+        self.attr.mtimensec = nsecs;
+    }
+
+    /// Set the last created time, as the elapsed time since the UNIX epoch.
+    ///
+    /// Use `ctime_timespec` to specify the time before the epoch.
     #[inline]
     pub fn ctime(&mut self, ctime: Duration) {
         self.attr.ctime = ctime.as_secs();
         self.attr.ctimensec = ctime.subsec_nanos();
     }
+
+    /// Set the last created time from the fields of a `timespec`.
+    ///
+    /// See the documentation of `atime_timespec` for details.
+    #[inline]
+    pub fn ctime_timespec(&mut self, secs: i64, nsecs: u32) {
+        self.attr.ctime = secs as u64;
+        self.attr.ctimensec = nsecs;
+    }
+
+    /// Set the attribute values from a `libc::stat`.
+    ///
+    /// This method copies the inode number, mode, size, number of hard links,
+    /// owner, device ID, block size, number of blocks, and the three timestamps.
+    /// The timestamps before the epoch are also transferred as is.
+    #[allow(clippy::unnecessary_cast)] // the widths of the fields depend on the platform.
+    pub fn stat(&mut self, st: &libc::stat) {
+        self.attr.ino = st.st_ino as u64;
+        self.attr.size = st.st_size as u64;
+        self.attr.mode = st.st_mode as u32;
+        self.attr.nlink = st.st_nlink as u32;
+        self.attr.uid = st.st_uid;
+        self.attr.gid = st.st_gid;
+        self.attr.rdev = st.st_rdev as u32;
+        self.attr.blksize = st.st_blksize as u32;
+        self.attr.blocks = st.st_blocks as u64;
+        // `time_t` is 32 bits wide on some platforms, and must be sign-extended.
+        self.atime_timespec(st.st_atime as i64, st.st_atime_nsec as u32);
+        self.mtime_timespec(st.st_mtime as i64, st.st_mtime_nsec as u32);
+        self.ctime_timespec(st.st_ctime as i64, st.st_ctime_nsec as u32);
+    }
+
+    /// Set the attribute flags, such as `FUSE_ATTR_SUBMOUNT` or `FUSE_ATTR_DAX`.
+    ///
+    /// This field is available since ABI 7.32 and is ignored by older kernels.
+    #[inline]
+    pub fn flags(&mut self, flags: u32) {
+        self.attr.flags = flags;
+    }
+
+    /// Set whether the kernel should map the file contents with DAX semantics.
+    ///
+    /// This sets `FUSE_ATTR_DAX` in the attribute flags, which is honored only if
+    /// `KernelConfig::inode_dax` is accepted by the kernel (ABI 7.36 or later).
+    #[inline]
+    pub fn dax(&mut self, enabled: bool) {
+        if enabled {
+            self.attr.flags |= FUSE_ATTR_DAX;
+        } else {
+            self.attr.flags &= !FUSE_ATTR_DAX;
+        }
+    }
 }
 
 #[derive(Default)]
@@ -121,6 +205,17 @@ impl Bytes for EntryOut {
 }
 
 impl EntryOut {
+    /// Create an entry with the specified inode number and the attribute values
+    /// copied from a `libc::stat`.
+    ///
+    /// See also the documentation of `FileAttr::stat`.
+    pub fn from_stat(ino: u64, st: &libc::stat) -> Self {
+        let mut out = Self::default();
+        out.ino(ino);
+        out.attr().stat(st);
+        out
+    }
+
     /// Return the object to fill attribute values about this entry.
     #[inline]
     pub fn attr(&mut self) -> &mut FileAttr {
@@ -144,6 +239,10 @@ impl EntryOut {
     /// when the filesystem reuse inode numbers.  That is, the operations
     /// must ensure that the pair of entry's inode number and generation
     /// are unique for the lifetime of the filesystem.
+    ///
+    /// This requirement is essential when the filesystem is exported via
+    /// NFS, since the clients identify files by the pair of them even after
+    /// the kernel has forgotten the inode.
     pub fn generation(&mut self, generation: u64) {
         self.out.generation = generation;
     }
@@ -167,6 +266,20 @@ impl EntryOut {
         self.out.entry_valid = ttl.as_secs();
         self.out.entry_valid_nsec = ttl.subsec_nanos();
     }
+
+    /// Set the validity timeout for inode attributes to the time remaining until `deadline`.
+    ///
+    /// See also the documentation of `AttrOut::ttl_until`.
+    pub fn ttl_attr_until(&mut self, deadline: Instant) {
+        self.ttl_attr(remaining(deadline));
+    }
+
+    /// Set the validity timeout for the name to the time remaining until `deadline`.
+    ///
+    /// See also the documentation of `AttrOut::ttl_until`.
+    pub fn ttl_entry_until(&mut self, deadline: Instant) {
+        self.ttl_entry(remaining(deadline));
+    }
 }
 
 #[derive(Default)]
@@ -193,6 +306,21 @@ impl AttrOut {
         self.out.attr_valid = ttl.as_secs();
         self.out.attr_valid_nsec = ttl.subsec_nanos();
     }
+
+    /// Set the validity timeout for this attribute to the time remaining until `deadline`.
+    ///
+    /// The remaining time is computed at the call, so this method should be called
+    /// just before sending the reply.  If `deadline` has already passed, the timeout
+    /// is set to zero, which means that the kernel does not cache the attribute
+    /// and asks the filesystem again at the next access.
+    pub fn ttl_until(&mut self, deadline: Instant) {
+        self.ttl(remaining(deadline));
+    }
+}
+
+#[inline]
+fn remaining(deadline: Instant) -> Duration {
+    deadline.saturating_duration_since(Instant::now())
 }
 
 impl Bytes for AttrOut {
@@ -243,6 +371,8 @@ impl Bytes for OpenOut {
 
 impl OpenOut {
     /// Set the handle of opened file.
+    ///
+    /// The handles can be allocated by `HandleTable` in the `handle` module.
     pub fn fh(&mut self, fh: u64) {
         self.out.fh = fh;
     }
@@ -257,6 +387,10 @@ impl OpenOut {
     }
 
     /// Indicates that the direct I/O is used on this file.
+    ///
+    /// The reads and writes on the file bypass the page cache, and thus are not
+    /// read ahead.  This is the only per-open control over the readahead, whose
+    /// size is negotiated globally by `KernelConfig::max_readahead`.
     pub fn direct_io(&mut self, enabled: bool) {
         self.set_flag(FOPEN_DIRECT_IO, enabled);
     }
@@ -278,6 +412,139 @@ impl OpenOut {
     pub fn cache_dir(&mut self, enabled: bool) {
         self.set_flag(FOPEN_CACHE_DIR, enabled);
     }
+
+    /// Indicates that the kernel should not send `flush` requests
+    /// when the file is closed.
+    ///
+    /// This flag is supported since Linux 5.16 (ABI 7.35) and is ignored by older kernels.
+    pub fn noflush(&mut self, enabled: bool) {
+        self.set_flag(FOPEN_NOFLUSH, enabled);
+    }
+
+    /// Allow the concurrent direct writes to the same file handle.
+    ///
+    /// By default, the kernel serializes the direct writes on a file.
+    /// This flag is meaningful only if `direct_io` is enabled, and is supported
+    /// since Linux 6.2 (ABI 7.38).  It is ignored by older kernels.
+    pub fn parallel_direct_writes(&mut self, enabled: bool) {
+        self.set_flag(FOPEN_PARALLEL_DIRECT_WRITES, enabled);
+    }
+
+    /// Specify the backing file to which the kernel forwards the reads and writes
+    /// on the opened file.
+    ///
+    /// The backing ID is obtained by `Session::open_backing`.  This option is
+    /// meaningful only if `KernelConfig::passthrough` is granted by the kernel.
+    pub fn passthrough(&mut self, backing_id: u32) {
+        self.set_flag(FOPEN_PASSTHROUGH, true);
+        self.out.backing_id = backing_id as i32;
+    }
+}
+
+/// The data to be replied to a `read` request, assembled from multiple chunks.
+///
+/// The chunks are written to the kernel by a single vectored write,
+/// without concatenating them into an intermediate buffer.
+#[derive(Debug, Default)]
+pub struct ReadOut<'a> {
+    chunks: Vec<&'a [u8]>,
+}
+
+impl<'a> ReadOut<'a> {
+    /// Create an empty `ReadOut`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a chunk of bytes to the end of the reply data.
+    pub fn push(&mut self, chunk: &'a [u8]) -> &mut Self {
+        self.chunks.push(chunk);
+        self
+    }
+
+    /// Return the total length of the reply data.
+    pub fn len(&self) -> usize {
+        self.chunks.size()
+    }
+
+    /// Return whether the reply data is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Bytes for ReadOut<'_> {
+    #[inline]
+    fn size(&self) -> usize {
+        self.chunks.size()
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        self.chunks.count()
+    }
+
+    #[inline]
+    fn fill_bytes<'a>(&'a self, dst: &mut dyn FillBytes<'a>) {
+        self.chunks.fill_bytes(dst)
+    }
+}
+
+/// The target of a symbolic link to be replied to a `readlink` request.
+///
+/// The kernel expects the target *without* the trailing NUL, and receives it
+/// into a buffer of one page minus one byte for the terminator.  So the target
+/// must not contain any NUL bytes and must be shorter than `PATH_MAX` (4096)
+/// bytes, which are checked by `new`.  Replying a longer target makes the write
+/// to the FUSE device fail, and the caller of `readlink(2)` gets an error.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadlinkOut<'a> {
+    target: &'a [u8],
+}
+
+impl<'a> ReadlinkOut<'a> {
+    /// Create a `ReadlinkOut` with the specified link target.
+    pub fn new<T>(target: &'a T) -> io::Result<Self>
+    where
+        T: AsRef<OsStr> + ?Sized,
+    {
+        let target = target.as_ref().as_bytes();
+        if target.contains(&0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the link target must not contain NUL bytes",
+            ));
+        }
+        if target.len() >= libc::PATH_MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the link target is too long",
+            ));
+        }
+        Ok(Self { target })
+    }
+
+    /// Return the link target.
+    pub fn target(&self) -> &'a OsStr {
+        OsStr::from_bytes(self.target)
+    }
+}
+
+impl Bytes for ReadlinkOut<'_> {
+    #[inline]
+    fn size(&self) -> usize {
+        self.target.size()
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        self.target.count()
+    }
+
+    #[inline]
+    fn fill_bytes<'a>(&'a self, dst: &mut dyn FillBytes<'a>) {
+        self.target.fill_bytes(dst)
+    }
 }
 
 #[derive(Default)]
@@ -577,6 +844,10 @@ impl PollOut {
 
 pub struct ReaddirOut {
     buf: Vec<u8>,
+    pos: u64,
+    skip: u64,
+    num_entries: usize,
+    last_offset: Option<u64>,
 }
 
 impl fmt::Debug for ReaddirOut {
@@ -606,7 +877,72 @@ impl ReaddirOut {
     pub fn new(capacity: usize) -> Self {
         Self {
             buf: Vec::with_capacity(capacity),
+            pos: 0,
+            skip: 0,
+            num_entries: 0,
+            last_offset: None,
+        }
+    }
+
+    /// Create a buffer sized to the maximum length requested by the `readdir` request.
+    ///
+    /// This is equivalent to `ReaddirOut::new(op.size() as usize)`.  The reply must
+    /// not exceed the requested size, so the capacity should be derived from the
+    /// request rather than specified manually.
+    pub fn for_op(op: &crate::op::Readdir<'_>) -> Self {
+        Self::new(op.size() as usize)
+    }
+
+    /// Return the number of entries added to the buffer.
+    ///
+    /// Unlike `Bytes::count`, this value is the number of directory entries,
+    /// not including the skipped ones or the one that did not fit.
+    pub fn num_entries(&self) -> usize {
+        self.num_entries
+    }
+
+    /// Return the offset of the last entry added to the buffer.
+    ///
+    /// The next `readdir` request from the kernel resumes from this offset.
+    /// This method returns `None` if no entry has been added.
+    pub fn last_offset(&self) -> Option<u64> {
+        self.last_offset
+    }
+
+    /// Specify the offset of the directory stream from which the entries are emitted.
+    ///
+    /// The entries pushed by `push` are skipped until the number of pushed entries
+    /// exceeds the specified value.  The filesystem usually passes `Readdir::offset`
+    /// to this method.
+    pub fn resume_from(&mut self, offset: u64) -> &mut Self {
+        self.skip = offset;
+        self
+    }
+
+    /// Push an entry, assigning its offset automatically.
+    ///
+    /// The offset of the `n`-th pushed entry is `n`, so that the subsequent
+    /// `readdir` request resumes after the last emitted entry by calling
+    /// `resume_from` with the requested offset.  Since the kernel may issue
+    /// `readdir` requests from an arbitrary offset previously returned,
+    /// the filesystem must push the entries in the same order on every call.
+    /// For the directories modified between the paginated requests, use `entry`
+    /// with the stable offsets provided by `dir::DirEntries` instead.
+    ///
+    /// This method returns `true` if the buffer is full and the entry has not been
+    /// added.  This method should not be mixed with `entry`.
+    pub fn push(&mut self, name: &OsStr, ino: u64, typ: u32) -> bool {
+        let off = self.pos + 1;
+        if off <= self.skip {
+            self.pos = off;
+            return false;
         }
+
+        let full = self.entry(name, ino, typ, off);
+        if !full {
+            self.pos = off;
+        }
+        full
     }
 
     pub fn entry(&mut self, name: &OsStr, ino: u64, typ: u32, off: u64) -> bool {
@@ -632,6 +968,9 @@ impl ReaddirOut {
         self.buf.extend_from_slice(name);
         self.buf.resize(lenbefore + aligned_entry_size, 0);
 
+        self.num_entries += 1;
+        self.last_offset = Some(off);
+
         false
     }
 }
@@ -640,3 +979,305 @@ impl ReaddirOut {
 const fn aligned(len: usize) -> usize {
     (len + mem::size_of::<u64>() - 1) & !(mem::size_of::<u64>() - 1)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readlink_long_target() {
+        struct Collect(Vec<u8>);
+        impl FillBytes<'_> for Collect {
+            fn put(&mut self, chunk: &[u8]) {
+                self.0.extend_from_slice(chunk);
+            }
+        }
+
+        let target = "a/".repeat(2047) + "b";
+        assert_eq!(target.len(), libc::PATH_MAX as usize - 1);
+        let out = ReadlinkOut::new(&target).unwrap();
+        assert_eq!(out.target(), OsStr::new(&target));
+
+        // The header length is computed from the size of the reply, without the terminator.
+        let mut collect = Collect(vec![]);
+        out.fill_bytes(&mut collect);
+        assert_eq!(out.size(), target.len());
+        assert_eq!(collect.0, target.as_bytes());
+
+        let too_long = target + "c";
+        let err = ReadlinkOut::new(&too_long).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = ReadlinkOut::new("target\0").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn create_without_handle() {
+        struct Collect(Vec<u8>);
+        impl FillBytes<'_> for Collect {
+            fn put(&mut self, chunk: &[u8]) {
+                self.0.extend_from_slice(chunk);
+            }
+        }
+
+        let mut entry_out = EntryOut::default();
+        entry_out.ino(2);
+        let mut open_out = OpenOut::default();
+        open_out.keep_cache(true);
+
+        let reply = (entry_out, open_out);
+        let mut collect = Collect(vec![]);
+        reply.fill_bytes(&mut collect);
+        assert_eq!(collect.0.len(), reply.size());
+
+        let (_, open_out) = collect.0.split_at(mem::size_of::<fuse_entry_out>());
+        assert_eq!(open_out.len(), mem::size_of::<fuse_open_out>());
+        // fh(8) + open_flags(4) + backing_id(4)
+        assert_eq!(open_out[0..8], 0u64.to_ne_bytes(), "fh");
+        assert_eq!(
+            open_out[8..12],
+            FOPEN_KEEP_CACHE.to_ne_bytes(),
+            "open_flags"
+        );
+    }
+
+    #[test]
+    fn attr_flags() {
+        let mut out = AttrOut::default();
+        out.attr().ino(1);
+        out.attr().flags(FUSE_ATTR_SUBMOUNT);
+
+        let bytes = out.out.as_bytes();
+        assert_eq!(bytes.len(), 104);
+        // attr_valid(8) + attr_valid_nsec(4) + dummy(4) + ino(8)
+        assert_eq!(bytes[16..24], 1u64.to_ne_bytes());
+        // ... + size, blocks, [amc]time(5 * 8) + [amc]timensec, mode, nlink, uid, gid, rdev, blksize(9 * 4)
+        assert_eq!(bytes[100..104], FUSE_ATTR_SUBMOUNT.to_ne_bytes());
+
+        out.attr().dax(true);
+        let bytes = out.out.as_bytes();
+        assert_eq!(
+            bytes[100..104],
+            (FUSE_ATTR_SUBMOUNT | FUSE_ATTR_DAX).to_ne_bytes()
+        );
+
+        out.attr().dax(false);
+        let bytes = out.out.as_bytes();
+        assert_eq!(bytes[100..104], FUSE_ATTR_SUBMOUNT.to_ne_bytes());
+    }
+
+    #[test]
+    fn entry_generation() {
+        let mut out = EntryOut::default();
+        out.ino(2);
+        out.generation(3);
+
+        let bytes = out.out.as_bytes();
+        assert_eq!(bytes.len(), 128);
+        // nodeid(8) + generation(8)
+        assert_eq!(bytes[0..8], 2u64.to_ne_bytes());
+        assert_eq!(bytes[8..16], 3u64.to_ne_bytes());
+    }
+
+    #[test]
+    fn entry_from_stat() {
+        let mut st = unsafe { mem::zeroed::<libc::stat>() };
+        st.st_ino = 42;
+        st.st_mode = libc::S_IFREG | 0o644;
+        st.st_size = 4096;
+        st.st_nlink = 2;
+        st.st_uid = 1000;
+        st.st_gid = 100;
+        st.st_rdev = 7;
+        st.st_blksize = 512;
+        st.st_blocks = 8;
+        st.st_atime = -1;
+        st.st_atime_nsec = 999_999_999;
+        st.st_mtime = 1_600_000_000;
+        st.st_mtime_nsec = 1;
+        st.st_ctime = 1_600_000_001;
+        st.st_ctime_nsec = 2;
+
+        let out = EntryOut::from_stat(3, &st);
+        assert_eq!(out.out.nodeid, 3);
+
+        let attr = &out.out.attr;
+        assert_eq!(attr.ino, 42);
+        assert_eq!(attr.mode, libc::S_IFREG | 0o644);
+        assert_eq!(attr.size, 4096);
+        assert_eq!(attr.nlink, 2);
+        assert_eq!(attr.uid, 1000);
+        assert_eq!(attr.gid, 100);
+        assert_eq!(attr.rdev, 7);
+        assert_eq!(attr.blksize, 512);
+        assert_eq!(attr.blocks, 8);
+        // The timestamps before the epoch are sent as the two's complement.
+        assert_eq!(attr.atime as i64, -1);
+        assert_eq!(attr.atimensec, 999_999_999);
+        assert_eq!(attr.mtime, 1_600_000_000);
+        assert_eq!(attr.mtimensec, 1);
+        assert_eq!(attr.ctime, 1_600_000_001);
+        assert_eq!(attr.ctimensec, 2);
+    }
+
+    #[test]
+    fn ttl_until_deadline() {
+        let mut out = AttrOut::default();
+        out.ttl_until(Instant::now() + Duration::new(10, 500_000_000));
+        // A few moments have elapsed since the deadline was computed.
+        let ttl = Duration::new(out.out.attr_valid, out.out.attr_valid_nsec);
+        assert!(ttl <= Duration::new(10, 500_000_000));
+        assert!(ttl > Duration::from_secs(10));
+        assert!(out.out.attr_valid_nsec < 1_000_000_000);
+
+        // A deadline in the past disables the caching.
+        let past = Instant::now();
+        std::thread::sleep(Duration::from_millis(1));
+        out.ttl_until(past);
+        assert_eq!(out.out.attr_valid, 0);
+        assert_eq!(out.out.attr_valid_nsec, 0);
+
+        let mut out = EntryOut::default();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        out.ttl_attr_until(deadline);
+        out.ttl_entry_until(deadline);
+        assert!(out.out.attr_valid == 59 || out.out.attr_valid == 60);
+        assert!(out.out.entry_valid == 59 || out.out.entry_valid == 60);
+    }
+
+    #[test]
+    fn attr_timespec() {
+        let mut out = AttrOut::default();
+        out.attr().mtime_timespec(-86_400 * 365, 500);
+        out.attr().atime_timespec(i64::from(i32::MIN), 0);
+        out.attr().ctime(Duration::new(1_600_000_000, 2));
+
+        let attr = &out.out.attr;
+        assert_eq!(attr.mtime as i64, -86_400 * 365);
+        assert_eq!(attr.mtimensec, 500);
+        assert_eq!(attr.atime as i64, -0x8000_0000);
+        assert_eq!(attr.atimensec, 0);
+        assert_eq!(attr.ctime, 1_600_000_000);
+        assert_eq!(attr.ctimensec, 2);
+
+        // The seconds are encoded as the 64-bit two's complement.
+        let bytes = out.out.as_bytes();
+        let mtime = &bytes[16 + 8 + 8 + 8 + 8..][..8];
+        assert_eq!(mtime, (-86_400i64 * 365).to_ne_bytes());
+    }
+
+    #[test]
+    fn open_flags() {
+        let open_flags = |f: fn(&mut OpenOut)| {
+            let mut out = OpenOut::default();
+            f(&mut out);
+            // fh(8) + open_flags(4) + backing_id(4)
+            let bytes = out.out.as_bytes();
+            assert_eq!(bytes.len(), 16);
+            u32::from_ne_bytes(bytes[8..12].try_into().unwrap())
+        };
+
+        assert_eq!(open_flags(|out| out.noflush(true)), FOPEN_NOFLUSH);
+        assert_eq!(
+            open_flags(|out| out.parallel_direct_writes(true)),
+            FOPEN_PARALLEL_DIRECT_WRITES
+        );
+        assert_eq!(open_flags(|out| out.passthrough(3)), FOPEN_PASSTHROUGH);
+        assert_eq!(
+            open_flags(|out| {
+                out.direct_io(true);
+                out.parallel_direct_writes(true);
+                out.parallel_direct_writes(false);
+            }),
+            FOPEN_DIRECT_IO
+        );
+    }
+
+    #[test]
+    fn open_backing_id() {
+        let mut out = OpenOut::default();
+        out.fh(1);
+        out.passthrough(42);
+
+        let bytes = out.out.as_bytes();
+        // fh(8) + open_flags(4) + backing_id(4)
+        assert_eq!(bytes[0..8], 1u64.to_ne_bytes());
+        assert_eq!(bytes[12..16], 42i32.to_ne_bytes());
+    }
+
+    #[test]
+    fn readdir_for_op() {
+        use crate::op::Operation;
+
+        let header = fuse_in_header {
+            opcode: fuse_opcode::FUSE_READDIR as u32,
+            nodeid: 1,
+            ..Default::default()
+        };
+        let arg = fuse_read_in {
+            size: 4096,
+            ..Default::default()
+        };
+        let op = match Operation::decode(&header, arg.as_bytes(), (), 0).unwrap() {
+            Operation::Readdir(op) => op,
+            _ => panic!("incorrect operation is returned"),
+        };
+
+        let out = ReaddirOut::for_op(&op);
+        assert_eq!(
+            out.buf.capacity(),
+            ReaddirOut::new(op.size() as usize).buf.capacity()
+        );
+        assert!(out.buf.capacity() >= 4096);
+    }
+
+    #[test]
+    fn readdir_paginated() {
+        let names = ["a", "b", "c", "d", "e"];
+
+        let readdir = |offset: u64| {
+            // Each entry occupies 32 bytes, so that two entries fit in a buffer.
+            let mut out = ReaddirOut::new(64 + 8);
+            out.resume_from(offset);
+            for (i, name) in names.iter().enumerate() {
+                if out.push(OsStr::new(name), i as u64 + 10, 0) {
+                    break;
+                }
+            }
+            out.buf
+                .chunks(32)
+                .map(|entry| {
+                    let ino = u64::from_ne_bytes(entry[0..8].try_into().unwrap());
+                    let off = u64::from_ne_bytes(entry[8..16].try_into().unwrap());
+                    let namelen = u32::from_ne_bytes(entry[16..20].try_into().unwrap());
+                    let name = &entry[24..24 + namelen as usize];
+                    (ino, off, std::str::from_utf8(name).unwrap().to_owned())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let first = readdir(0);
+        assert_eq!(first, [(10, 1, "a".into()), (11, 2, "b".into())]);
+
+        let mut out = ReaddirOut::new(64 + 8);
+        assert_eq!((out.num_entries(), out.last_offset()), (0, None));
+        out.resume_from(1);
+        for (i, name) in names.iter().enumerate() {
+            if out.push(OsStr::new(name), i as u64 + 10, 0) {
+                break;
+            }
+        }
+        assert_eq!((out.num_entries(), out.last_offset()), (2, Some(3)));
+
+        let second = readdir(first.last().unwrap().1);
+        assert_eq!(second, [(12, 3, "c".into()), (13, 4, "d".into())]);
+
+        let third = readdir(second.last().unwrap().1);
+        assert_eq!(third, [(14, 5, "e".into())]);
+
+        assert!(readdir(third.last().unwrap().1).is_empty());
+
+        // The kernel may rewind to an arbitrary offset returned previously.
+        assert_eq!(readdir(1)[0], (11, 2, "b".into()));
+    }
+}
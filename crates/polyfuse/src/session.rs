@@ -1,23 +1,31 @@
 use crate::{
     bytes::{Bytes, FillBytes},
     conn::{Connection, MountOptions},
-    decoder::Decoder,
+    decoder::{self, Decoder},
+    error::Error,
     op::{DecodeError, Operation},
+    reply::XattrOut,
 };
 use polyfuse_kernel::*;
 use std::{
     cmp,
+    collections::{HashMap, VecDeque},
     convert::{TryFrom, TryInto as _},
     ffi::OsStr,
     fmt,
+    future::Future,
     io::{self, prelude::*, IoSlice, IoSliceMut},
     mem::{self, MaybeUninit},
     os::unix::prelude::*,
     path::{Path, PathBuf},
+    pin::Pin,
     sync::{
-        atomic::{AtomicBool, AtomicU64, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
     },
+    task::{self, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
 };
 use zerocopy::AsBytes as _;
 
@@ -53,12 +61,81 @@ const INIT_FLAGS_MASK: u32 = FUSE_ASYNC_READ
     | FUSE_WRITEBACK_CACHE
     | FUSE_POSIX_ACL
     | FUSE_DO_READDIRPLUS
-    | FUSE_READDIRPLUS_AUTO;
+    | FUSE_READDIRPLUS_AUTO
+    | FUSE_EXPLICIT_INVAL_DATA
+    | FUSE_CACHE_SYMLINKS
+    | FUSE_HANDLE_KILLPRIV_V2
+    | FUSE_SUBMOUNTS
+    | FUSE_SETXATTR_EXT;
+
+const INIT_FLAGS2_MASK: u32 =
+    ((FUSE_SECURITY_CTX | FUSE_CREATE_SUPP_GROUP | FUSE_PASSTHROUGH | FUSE_HAS_INODE_DAX) >> 32)
+        as u32;
+
+// The names of the INIT flags, in the layout of `KernelInit::flags`.
+const INIT_FLAG_NAMES: &[(&str, u64)] = &[
+    ("FUSE_ASYNC_READ", FUSE_ASYNC_READ as u64),
+    ("FUSE_POSIX_LOCKS", FUSE_POSIX_LOCKS as u64),
+    ("FUSE_FILE_OPS", FUSE_FILE_OPS as u64),
+    ("FUSE_ATOMIC_O_TRUNC", FUSE_ATOMIC_O_TRUNC as u64),
+    ("FUSE_EXPORT_SUPPORT", FUSE_EXPORT_SUPPORT as u64),
+    ("FUSE_BIG_WRITES", FUSE_BIG_WRITES as u64),
+    ("FUSE_DONT_MASK", FUSE_DONT_MASK as u64),
+    ("FUSE_SPLICE_WRITE", FUSE_SPLICE_WRITE as u64),
+    ("FUSE_SPLICE_MOVE", FUSE_SPLICE_MOVE as u64),
+    ("FUSE_SPLICE_READ", FUSE_SPLICE_READ as u64),
+    ("FUSE_FLOCK_LOCKS", FUSE_FLOCK_LOCKS as u64),
+    ("FUSE_HAS_IOCTL_DIR", FUSE_HAS_IOCTL_DIR as u64),
+    ("FUSE_AUTO_INVAL_DATA", FUSE_AUTO_INVAL_DATA as u64),
+    ("FUSE_DO_READDIRPLUS", FUSE_DO_READDIRPLUS as u64),
+    ("FUSE_READDIRPLUS_AUTO", FUSE_READDIRPLUS_AUTO as u64),
+    ("FUSE_ASYNC_DIO", FUSE_ASYNC_DIO as u64),
+    ("FUSE_WRITEBACK_CACHE", FUSE_WRITEBACK_CACHE as u64),
+    ("FUSE_NO_OPEN_SUPPORT", FUSE_NO_OPEN_SUPPORT as u64),
+    ("FUSE_PARALLEL_DIROPS", FUSE_PARALLEL_DIROPS as u64),
+    ("FUSE_HANDLE_KILLPRIV", FUSE_HANDLE_KILLPRIV as u64),
+    ("FUSE_POSIX_ACL", FUSE_POSIX_ACL as u64),
+    ("FUSE_ABORT_ERROR", FUSE_ABORT_ERROR as u64),
+    ("FUSE_MAX_PAGES", FUSE_MAX_PAGES as u64),
+    ("FUSE_CACHE_SYMLINKS", FUSE_CACHE_SYMLINKS as u64),
+    ("FUSE_NO_OPENDIR_SUPPORT", FUSE_NO_OPENDIR_SUPPORT as u64),
+    ("FUSE_EXPLICIT_INVAL_DATA", FUSE_EXPLICIT_INVAL_DATA as u64),
+    ("FUSE_MAP_ALIGNMENT", FUSE_MAP_ALIGNMENT as u64),
+    ("FUSE_SUBMOUNTS", FUSE_SUBMOUNTS as u64),
+    ("FUSE_HANDLE_KILLPRIV_V2", FUSE_HANDLE_KILLPRIV_V2 as u64),
+    ("FUSE_SETXATTR_EXT", FUSE_SETXATTR_EXT as u64),
+    ("FUSE_INIT_EXT", FUSE_INIT_EXT as u64),
+    ("FUSE_INIT_RESERVED", FUSE_INIT_RESERVED as u64),
+    ("FUSE_SECURITY_CTX", FUSE_SECURITY_CTX),
+    ("FUSE_HAS_INODE_DAX", FUSE_HAS_INODE_DAX),
+    ("FUSE_CREATE_SUPP_GROUP", FUSE_CREATE_SUPP_GROUP),
+    ("FUSE_HAS_EXPIRE_ONLY", FUSE_HAS_EXPIRE_ONLY),
+    ("FUSE_PASSTHROUGH", FUSE_PASSTHROUGH),
+];
+
+// The size of `fuse_init_in` sent by the kernel prior to ABI 7.36.
+const COMPAT_INIT_IN_SIZE: usize = 16;
+
+// The size of `fuse_init_in` sent by the kernel prior to ABI 7.6,
+// which contains only the protocol version.
+const COMPAT_5_INIT_IN_SIZE: usize = 8;
 
 // ==== KernelConfig ====
 
 /// Parameters for setting up the connection with FUSE driver
 /// and the kernel side behavior.
+///
+/// The default configuration enables the following capabilities, which
+/// can be disabled individually by the corresponding methods or all at
+/// once by `no_default_flags`:
+///
+/// * `async_read`
+/// * `atomic_o_trunc`
+/// * `auto_inval_data`
+/// * `async_dio`
+/// * `parallel_dirops`
+/// * `handle_killpriv`
+#[derive(Clone)]
 pub struct KernelConfig {
     mountopts: MountOptions,
     init_out: fuse_init_out,
@@ -80,6 +157,33 @@ impl KernelConfig {
         self
     }
 
+    /// Specify whether to mount the filesystem by calling `mount(2)` directly,
+    /// without the setuid mount helper `fusermount`.
+    ///
+    /// This is intended for the rootless containers, where the process has
+    /// `CAP_SYS_ADMIN` in its own user and mount namespaces.  The FUSE mounts in
+    /// the non-initial user namespaces are supported since Linux 4.18.  If the
+    /// `mount(2)` fails with `EPERM`, the mount helper is used instead.
+    ///
+    /// The directly mounted filesystem is lazily unmounted when the session is
+    /// dropped, but `auto_unmount` has no effect since there is no helper process
+    /// watching the daemon.  The block device mounts are not supported.
+    ///
+    /// It can be tried with `unshare(1)`, which runs the daemon enabling this option
+    /// as the root in new user and mount namespaces:
+    ///
+    /// ```text
+    /// $ unshare -Urm ./target/debug/myfs /tmp/mnt
+    /// ```
+    ///
+    /// The mount is only visible in the namespace, e.g. via
+    /// `nsenter -t <pid> -U -m --preserve-credentials ls /tmp/mnt`.
+    /// This option has no effect on FreeBSD.
+    pub fn direct_mount(&mut self, enabled: bool) -> &mut Self {
+        self.mountopts.direct_mount = enabled;
+        self
+    }
+
     #[doc(hidden)] // TODO: dox
     pub fn mount_option(&mut self, option: &str) -> &mut Self {
         for option in option.split(',').map(|s| s.trim()) {
@@ -93,7 +197,58 @@ impl KernelConfig {
         self
     }
 
-    #[doc(hidden)] // TODO: dox
+    /// Specify whether the kernel checks the permissions by itself, based on
+    /// the file mode and the owner reported by the filesystem.
+    ///
+    /// This is the same as the `default_permissions` mount option.  When enabled,
+    /// the kernel never sends `access` requests, and the filesystem does not
+    /// need to implement the access checks by itself.
+    pub fn default_permissions(&mut self, enabled: bool) -> &mut Self {
+        self.mountopts
+            .options
+            .retain(|opt| opt != "default_permissions");
+        if enabled {
+            self.mountopts.options.push("default_permissions".into());
+        }
+        self
+    }
+
+    /// Specify whether the filesystem is mounted read-only.
+    ///
+    /// This is the same as the `ro` mount option, and the kernel rejects the
+    /// modifications of the filesystem with `EROFS` by itself.  In addition,
+    /// `Session::set_reject_writes` lets the session reject the modifying requests
+    /// on behalf of the filesystem.
+    pub fn read_only(&mut self, enabled: bool) -> &mut Self {
+        self.mountopts
+            .options
+            .retain(|opt| opt != "ro" && opt != "rw");
+        if enabled {
+            self.mountopts.options.push("ro".into());
+        }
+        self
+    }
+
+    /// Specify that the filesystem is mounted as a block device filesystem (`fuseblk`)
+    /// backed by the specified device, e.g. `/dev/loop0`.
+    ///
+    /// The block device is used as the source of the mount, which allows the
+    /// filesystem to use `bmap` and the block size given by the `blksize=N`
+    /// mount option.  This mode requires the root privilege, and `fusermount`
+    /// refuses to mount otherwise.  It is not supported on FreeBSD.
+    ///
+    /// To try it out, attach an image file to a loop device with
+    /// `losetup --find --show disk.img` and pass the printed device path here.
+    pub fn blkdev(&mut self, device: impl AsRef<Path>) -> &mut Self {
+        self.mountopts.blkdev = Some(device.as_ref().to_owned());
+        self
+    }
+
+    /// Specify the absolute path of the mount helper.
+    ///
+    /// By default, the path is taken from the environment variable
+    /// `POLYFUSE_FUSERMOUNT`, or `fusermount3` and then `fusermount`
+    /// are searched in `PATH`.
     pub fn fusermount_path(&mut self, program: impl AsRef<OsStr>) -> &mut Self {
         let program = Path::new(program.as_ref());
         assert!(
@@ -119,6 +274,27 @@ impl KernelConfig {
         }
     }
 
+    #[inline]
+    fn set_init_flag2(&mut self, flag: u64, enabled: bool) {
+        let flag = (flag >> 32) as u32;
+        if enabled {
+            self.init_out.flags2 |= flag;
+        } else {
+            self.init_out.flags2 &= !flag;
+        }
+    }
+
+    /// Disable all of the capabilities enabled by default.
+    ///
+    /// This is useful for opting into exactly the capabilities supported by the
+    /// filesystem, by calling this method before enabling them individually.
+    /// The capabilities enabled so far by the other methods are also cleared.
+    pub fn no_default_flags(&mut self) -> &mut Self {
+        self.init_out.flags &= !INIT_FLAGS_MASK;
+        self.init_out.flags2 &= !INIT_FLAGS2_MASK;
+        self
+    }
+
     /// Specify that the filesystem supports asynchronous read requests.
     ///
     /// Enabled by default.
@@ -129,6 +305,10 @@ impl KernelConfig {
 
     /// Specify that the filesystem supports the `O_TRUNC` open flag.
     ///
+    /// When enabled, `O_TRUNC` is passed to the filesystem via `op::Open::flags`
+    /// and the filesystem must truncate the file on open by itself.  When disabled,
+    /// the kernel truncates the file by a separate `setattr` request instead.
+    ///
     /// Enabled by default.
     pub fn atomic_o_trunc(&mut self, enabled: bool) -> &mut Self {
         self.set_init_flag(FUSE_ATOMIC_O_TRUNC, enabled);
@@ -137,9 +317,31 @@ impl KernelConfig {
 
     /// Specify that the kernel check the validity of attributes on every read.
     ///
+    /// This option is mutually exclusive with `explicit_inval_data`,
+    /// and enabling it disables the latter.
+    ///
     /// Enabled by default.
     pub fn auto_inval_data(&mut self, enabled: bool) -> &mut Self {
         self.set_init_flag(FUSE_AUTO_INVAL_DATA, enabled);
+        if enabled {
+            self.set_init_flag(FUSE_EXPLICIT_INVAL_DATA, false);
+        }
+        self
+    }
+
+    /// Specify that the kernel should not invalidate the cached pages
+    /// on its own, and the filesystem is responsible for invalidating them
+    /// explicitly via `Notifier::inval_inode`.
+    ///
+    /// This is useful for filesystems whose contents may be changed out of band.
+    /// This option is mutually exclusive with `auto_inval_data`,
+    /// and enabling it disables the latter.  Whether the kernel granted
+    /// this capability can be checked by `Session::explicit_inval_data`.
+    pub fn explicit_inval_data(&mut self, enabled: bool) -> &mut Self {
+        self.set_init_flag(FUSE_EXPLICIT_INVAL_DATA, enabled);
+        if enabled {
+            self.set_init_flag(FUSE_AUTO_INVAL_DATA, false);
+        }
         self
     }
 
@@ -168,6 +370,21 @@ impl KernelConfig {
         self
     }
 
+    /// Specify that the filesystem is responsible for unsetting setuid and setgid bits,
+    /// following the requests from the kernel.
+    ///
+    /// Unlike `handle_killpriv`, the kernel tells the filesystem when these bits
+    /// should be cleared, via `Write::kill_suidgid`, `Setattr::kill_suidgid`,
+    /// `Open::kill_suidgid` and `Create::kill_suidgid`.  The kernel requests it
+    /// only when the caller lacks `CAP_FSETID`, so the filesystem should not
+    /// clear these bits on its own.
+    ///
+    /// This capability is supported since Linux 5.11 (ABI 7.33).
+    pub fn handle_killpriv_v2(&mut self, enabled: bool) -> &mut Self {
+        self.set_init_flag(FUSE_HANDLE_KILLPRIV_V2, enabled);
+        self
+    }
+
     /// The filesystem supports the POSIX-style file lock.
     pub fn posix_locks(&mut self, enabled: bool) -> &mut Self {
         self.set_init_flag(FUSE_POSIX_LOCKS, enabled);
@@ -181,19 +398,41 @@ impl KernelConfig {
     }
 
     /// Specify that the filesystem supports lookups of `"."` and `".."`.
+    ///
+    /// This flag is required to export the mounted filesystem via NFS.
+    /// When enabled, the kernel may issue `LOOKUP` requests whose name is
+    /// `"."` or `".."` in order to reconnect a file handle to the directory
+    /// tree, so the filesystem must resolve them to the directory itself
+    /// and its parent, respectively.
+    ///
+    /// Since NFS file handles outlive the kernel's inode cache, the pair of
+    /// inode number and generation replied by `ReplyEntry` must also be
+    /// stable across lookups and unique for the lifetime of the filesystem.
     pub fn export_support(&mut self, enabled: bool) -> &mut Self {
         self.set_init_flag(FUSE_EXPORT_SUPPORT, enabled);
         self
     }
 
     /// Specify that the kernel should not apply the umask to the file mode
-    /// on `create` operations.
+    /// on `mknod`, `mkdir` and `create` operations.
+    ///
+    /// When enabled, the filesystem is responsible for applying the umask
+    /// passed along with these requests, e.g. `op::Mkdir::umask`, which
+    /// allows it to take the default ACLs into account.  Whether the kernel
+    /// granted this capability can be checked by `Session::dont_mask`.
     pub fn dont_mask(&mut self, enabled: bool) -> &mut Self {
         self.set_init_flag(FUSE_DONT_MASK, enabled);
         self
     }
 
     /// Specify that the kernel should enable writeback caching.
+    ///
+    /// When enabled, the kernel buffers the writes in the page cache and sends
+    /// them to the filesystem later, and treats its cached size and modification
+    /// times as authoritative.  See the documentation of `op::Setattr` for
+    /// the ordering of truncations against the buffered writes.
+    /// Whether the kernel granted this capability can be checked by
+    /// `Session::writeback_cache`.
     pub fn writeback_cache(&mut self, enabled: bool) -> &mut Self {
         self.set_init_flag(FUSE_WRITEBACK_CACHE, enabled);
         self
@@ -219,8 +458,115 @@ impl KernelConfig {
         self
     }
 
+    /// Specify that the kernel should cache the results of `readlink`.
+    ///
+    /// The cached symlinks are invalidated by `Notifier::inval_inode`.
+    /// Whether the kernel granted this capability can be checked by
+    /// `Session::cache_symlinks`.
+    pub fn cache_symlinks(&mut self, enabled: bool) -> &mut Self {
+        self.set_init_flag(FUSE_CACHE_SYMLINKS, enabled);
+        self
+    }
+
+    /// Specify that the filesystem contains nested mountpoints.
+    ///
+    /// When enabled, the inodes whose attribute has `FUSE_ATTR_SUBMOUNT` set
+    /// via `FileAttr::flags` are treated as the boundaries of submounts,
+    /// and the kernel automatically mounts them when they are crossed.
+    /// Whether the kernel granted this capability can be checked by
+    /// `Session::submounts`.
+    ///
+    /// This capability is supported since Linux 5.10 (ABI 7.32).
+    pub fn submounts(&mut self, enabled: bool) -> &mut Self {
+        self.set_init_flag(FUSE_SUBMOUNTS, enabled);
+        self
+    }
+
+    /// Specify that the kernel should send the extended arguments of `setxattr` requests.
+    ///
+    /// When enabled, the flags for clearing the SGID bit on setting the POSIX ACL
+    /// are available via `op::Setxattr::acl_kill_sgid`.
+    ///
+    /// This capability is supported since Linux 5.17 (ABI 7.33).
+    pub fn setxattr_ext(&mut self, enabled: bool) -> &mut Self {
+        self.set_init_flag(FUSE_SETXATTR_EXT, enabled);
+        self
+    }
+
+    /// Specify that the kernel should send the security context of the new files
+    /// along with `mknod`, `mkdir`, `symlink` and `create` requests.
+    ///
+    /// The security context is computed by the LSM (e.g. SELinux or Smack) of the
+    /// caller, and the filesystem should store it as the extended attribute of the
+    /// created inode so that the new file is labeled correctly.  The received contexts
+    /// are available via `Request::security_contexts`.
+    ///
+    /// This capability is supported since Linux 5.17 (ABI 7.36), but the contexts
+    /// are decoded only from the kernels since Linux 6.5 (ABI 7.38), which send
+    /// them as request extensions.
+    pub fn security_context(&mut self, enabled: bool) -> &mut Self {
+        self.set_init_flag2(FUSE_SECURITY_CTX, enabled);
+        self
+    }
+
+    /// Specify that the kernel should send the supplementary group of the caller
+    /// along with `mknod`, `mkdir`, `symlink` and `create` requests.
+    ///
+    /// The received groups are available via `Request::supp_groups`.
+    /// This capability is supported since Linux 6.5 (ABI 7.38),
+    /// and is silently ignored on older kernels.
+    pub fn create_supp_group(&mut self, enabled: bool) -> &mut Self {
+        self.set_init_flag2(FUSE_CREATE_SUPP_GROUP, enabled);
+        self
+    }
+
+    /// Specify that the filesystem decides whether to enable DAX per inode.
+    ///
+    /// When enabled, the kernel maps the file contents with DAX semantics
+    /// only for the inodes whose attributes are replied with `FileAttr::dax`.
+    /// This is meaningful only for a DAX-capable transport such as virtio-fs,
+    /// and is supported since Linux 5.17 (ABI 7.36).
+    pub fn inode_dax(&mut self, enabled: bool) -> &mut Self {
+        self.set_init_flag2(FUSE_HAS_INODE_DAX, enabled);
+        self
+    }
+
+    /// Specify that the filesystem uses the passthrough mode.
+    ///
+    /// In the passthrough mode, the filesystem registers a backing file by
+    /// `Session::open_backing` and returns its ID via `OpenOut::passthrough`,
+    /// and then the kernel services the reads and writes on the opened file
+    /// directly against the backing file without sending the requests.
+    /// Whether the kernel granted this capability can be checked by
+    /// `Session::passthrough`.
+    ///
+    /// This capability is supported since Linux 6.9 (ABI 7.40),
+    /// and registering the backing files requires `CAP_SYS_ADMIN`.
+    pub fn passthrough(&mut self, enabled: bool) -> &mut Self {
+        self.set_init_flag2(FUSE_PASSTHROUGH, enabled);
+        // The backing files must not be stacked on another FUSE filesystem.
+        self.init_out.max_stack_depth = if enabled { 1 } else { 0 };
+        self
+    }
+
     /// Set the maximum readahead.
+    ///
+    /// The value is clamped to the one offered by the kernel at initialization,
+    /// and the negotiated value can be obtained by `Session::max_readahead`.
+    ///
+    /// The readahead is configured only globally for the whole filesystem, and
+    /// the protocol provides no way to change it per file or per open.  A file that
+    /// should not be read ahead (e.g. accessed randomly) can be opened with
+    /// `OpenOut::direct_io`, which bypasses the page cache and hence the readahead
+    /// entirely.  Otherwise, the readahead of each open file is tuned by the kernel,
+    /// or by the applications via `posix_fadvise(2)` (e.g. `POSIX_FADV_RANDOM`).
+    ///
+    /// # Panic
+    /// It causes an assertion panic if the setting value is zero, which disables
+    /// the readahead entirely.  Disable `async_read` instead if the filesystem
+    /// does not want the kernel to read ahead asynchronously.
     pub fn max_readahead(&mut self, value: u32) -> &mut Self {
+        assert!(value > 0, "max_readahead must be greater than zero");
         self.init_out.max_readahead = value;
         self
     }
@@ -239,7 +585,37 @@ impl KernelConfig {
         self
     }
 
-    /// Return the maximum number of pending *background* requests.
+    /// Cap the minor version of the kernel ABI negotiated with the kernel.
+    ///
+    /// By default, the negotiated version is the older one of the kernel's and
+    /// the one supported by polyfuse.  Setting a lower value pins it, e.g. for
+    /// testing the filesystem against the semantics of a specific ABI version
+    /// on a newer kernel.  The value is never raised above either of them.
+    ///
+    /// Note that this only affects the version replied to the kernel.  The
+    /// capabilities introduced in the later versions should be disabled separately.
+    ///
+    /// # Panic
+    /// It causes an assertion panic if the value is older than the version supported
+    /// by polyfuse.
+    pub fn protocol_minor(&mut self, minor: u32) -> &mut Self {
+        assert!(
+            minor >= MINIMUM_SUPPORTED_MINOR_VERSION,
+            "the minor version must be greater or equal to {}",
+            MINIMUM_SUPPORTED_MINOR_VERSION,
+        );
+        self.init_out.minor = cmp::min(minor, FUSE_KERNEL_MINOR_VERSION);
+        self
+    }
+
+    /// Set the maximum number of pending *background* requests, such as
+    /// readahead and asynchronous direct I/O.
+    ///
+    /// If the setting value is 0, the kernel's default (12) is used.
+    ///
+    /// Note that the kernel silently caps the value for unprivileged users
+    /// to the sysctl `fs.fuse.max_user_bgreq`.  See the documentation of
+    /// `Session::max_background` for how to check the value actually applied.
     pub fn max_background(&mut self, max_background: u16) -> &mut Self {
         self.init_out.max_background = max_background;
         self
@@ -249,6 +625,8 @@ impl KernelConfig {
     /// the filesystem as *congested*.
     ///
     /// If the setting value is 0, the value is automatically calculated by using max_background.
+    /// As with `max_background`, the kernel caps the value for unprivileged users
+    /// to the sysctl `fs.fuse.max_user_congthresh`.
     ///
     /// # Panics
     /// It cause a panic if the setting value is greater than `max_background`.
@@ -276,9 +654,151 @@ impl KernelConfig {
     }
 }
 
+// ==== KernelInit ====
+
+/// The parameters of the INIT request sent by the kernel.
+pub struct KernelInit {
+    arg: fuse_init_in,
+}
+
+impl fmt::Debug for KernelInit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KernelInit")
+            .field("major", &self.arg.major)
+            .field("minor", &self.arg.minor)
+            .field("max_readahead", &self.arg.max_readahead)
+            .field("flags", &self.flags())
+            .finish()
+    }
+}
+
+impl KernelInit {
+    /// Return the major version of the ABI spoken by the kernel.
+    pub fn major(&self) -> u32 {
+        self.arg.major
+    }
+
+    /// Return the minor version of the ABI spoken by the kernel.
+    pub fn minor(&self) -> u32 {
+        self.arg.minor
+    }
+
+    /// Return the maximum readahead offered by the kernel.
+    pub fn max_readahead(&self) -> u32 {
+        self.arg.max_readahead
+    }
+
+    /// Return the capability flags offered by the kernel.
+    ///
+    /// The flags stored in `flags2` (e.g. `FUSE_CREATE_SUPP_GROUP`) are placed
+    /// in the upper 32 bits.
+    pub fn flags(&self) -> u64 {
+        self.arg.flags as u64 | (self.arg.flags2 as u64) << 32
+    }
+}
+
+// ==== CuseConfig ====
+
+/// Parameters for setting up a CUSE (character device in userspace) session.
+pub struct CuseConfig {
+    devname: String,
+    init_out: cuse_init_out,
+}
+
+impl CuseConfig {
+    /// Create a new `CuseConfig` for the device with the specified name.
+    ///
+    /// The device is created by the kernel as `/dev/<devname>`.
+    pub fn new(devname: impl Into<String>) -> Self {
+        Self {
+            devname: devname.into(),
+            init_out: cuse_init_out {
+                major: FUSE_KERNEL_VERSION,
+                minor: FUSE_KERNEL_MINOR_VERSION,
+                max_read: DEFAULT_MAX_WRITE,
+                max_write: DEFAULT_MAX_WRITE,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Set the major number of the device.
+    ///
+    /// If the value is 0, the kernel allocates the number dynamically.
+    pub fn dev_major(&mut self, major: u32) -> &mut Self {
+        self.init_out.dev_major = major;
+        self
+    }
+
+    /// Set the minor number of the device.
+    pub fn dev_minor(&mut self, minor: u32) -> &mut Self {
+        self.init_out.dev_minor = minor;
+        self
+    }
+
+    /// Specify that the device supports the unrestricted `ioctl` requests.
+    pub fn unrestricted_ioctl(&mut self, enabled: bool) -> &mut Self {
+        if enabled {
+            self.init_out.flags |= CUSE_UNRESTRICTED_IOCTL;
+        } else {
+            self.init_out.flags &= !CUSE_UNRESTRICTED_IOCTL;
+        }
+        self
+    }
+
+    /// Set the maximum size of the read requests.
+    pub fn max_read(&mut self, value: u32) -> &mut Self {
+        self.init_out.max_read = value;
+        self
+    }
+
+    /// Set the maximum size of the write buffer.
+    ///
+    /// # Panic
+    /// It causes an assertion panic if the setting value is less than the absolute minimum.
+    pub fn max_write(&mut self, value: u32) -> &mut Self {
+        assert!(
+            value >= MIN_MAX_WRITE,
+            "max_write must be greater or equal to {}",
+            MIN_MAX_WRITE,
+        );
+        self.init_out.max_write = value;
+        self
+    }
+
+    fn devinfo(&self) -> Vec<u8> {
+        format!("DEVNAME={}\0", self.devname).into_bytes()
+    }
+}
+
 // ==== Session ====
 
+/// The reason why the kernel has closed the connection.
+///
+/// See the documentation of `Session::exit_reason` for details.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ExitReason {
+    /// The filesystem has been unmounted.
+    Unmounted,
+
+    /// The connection has been aborted by `Session::abort` or via the FUSE control
+    /// filesystem, and the mountpoint remains until it is unmounted explicitly.
+    Aborted,
+
+    /// The connection has been closed for a reason that could not be determined,
+    /// e.g. for the CUSE sessions or when `/proc/self/mountinfo` is not available.
+    Closed,
+}
+
 /// The object containing the contextrual information about a FUSE session.
+///
+/// The session API is blocking and does not depend on any async runtime.
+/// A single-threaded daemon can simply call `next_request` in a loop and
+/// reply to each request on the spot, as the `hello` example does.
+///
+/// The underlying device file descriptor can be obtained via `AsRawFd`,
+/// e.g. for registering it to a custom event loop.
 pub struct Session {
     inner: Arc<SessionInner>,
 }
@@ -290,11 +810,25 @@ impl fmt::Debug for Session {
 }
 
 struct SessionInner {
-    conn: Connection,
+    conn: Arc<Connection>,
+    stream: bool,
     init_out: fuse_init_out,
-    bufsize: usize,
+    // The read-only flags offered by the kernel, which are not sent back in the INIT reply.
+    readonly_flags: u32,
+    bufsize: AtomicUsize,
+    compact_buffers: AtomicBool,
+    zero_message_open: AtomicBool,
+    reject_writes: AtomicBool,
     exited: AtomicBool,
+    aborted: AtomicBool,
+    shutdown: AtomicBool,
+    shutdown_error: AtomicI32,
+    exit_reason: Mutex<Option<ExitReason>>,
     notify_unique: AtomicU64,
+    retrieves: Retrieves,
+    interrupts: Interrupts,
+    watchdog: Watchdog,
+    handlers: Handlers,
 }
 
 impl SessionInner {
@@ -309,6 +843,180 @@ impl SessionInner {
         // FIXME: choose appropriate atomic ordering.
         self.exited.store(true, Ordering::SeqCst)
     }
+
+    /// Called when the kernel has closed the connection.
+    fn disconnected(&self, conn: &Connection) {
+        // The kernel never replies to the pending notifications anymore.
+        self.retrieves.abort_all();
+
+        let mut exit_reason = self.exit_reason.lock().unwrap();
+        if exit_reason.is_some() {
+            return;
+        }
+        let reason = if self.aborted.load(Ordering::SeqCst) {
+            ExitReason::Aborted
+        } else {
+            // An aborted connection remains mounted until it is unmounted explicitly.
+            match conn.is_mounted() {
+                Ok(true) => ExitReason::Aborted,
+                Ok(false) => ExitReason::Unmounted,
+                Err(..) => ExitReason::Closed,
+            }
+        };
+        tracing::debug!(?reason, "the connection is closed");
+        *exit_reason = Some(reason);
+    }
+
+    fn next_request(self: &Arc<Self>, conn: &Arc<Connection>) -> Result<Option<Request>, Error> {
+        // FIXME: Align the allocated region in `arg` with the FUSE argument types.
+        let bufsize = self.bufsize.load(Ordering::SeqCst);
+        let mut arg = vec![0u8; bufsize - mem::size_of::<fuse_in_header>()];
+
+        loop {
+            if self.shutdown.load(Ordering::SeqCst) {
+                return Ok(None);
+            }
+
+            let mut header = fuse_in_header::default();
+
+            let res = if self.stream {
+                // The header is read separately so as not to consume the next message.
+                (&**conn).read(header.as_bytes_mut())
+            } else {
+                (&**conn).read_vectored(&mut [
+                    io::IoSliceMut::new(header.as_bytes_mut()),
+                    io::IoSliceMut::new(&mut arg[..]),
+                ])
+            };
+
+            let arg_len = match res {
+                Ok(0) if self.stream => {
+                    tracing::debug!("EOF");
+                    self.disconnected(conn);
+                    return Ok(None);
+                }
+                Ok(len) if self.stream => read_remaining(&**conn, &mut header, &mut arg, len)?,
+                Ok(len) => validate_message_len(&header, len)?,
+
+                Err(err) => match err.raw_os_error() {
+                    Some(libc::ENODEV) => {
+                        tracing::debug!("ENODEV");
+                        self.disconnected(conn);
+                        return Ok(None);
+                    }
+                    Some(libc::ENOENT) => {
+                        tracing::debug!("ENOENT");
+                        continue;
+                    }
+                    _ => return Err(err.into()),
+                },
+            };
+
+            let opcode = fuse_opcode::try_from(header.opcode).ok();
+
+            // The thread may have been blocked in `read` when the session was shut down.
+            if self.shutdown.load(Ordering::SeqCst) {
+                let replied = !matches!(
+                    opcode,
+                    Some(fuse_opcode::FUSE_FORGET)
+                        | Some(fuse_opcode::FUSE_BATCH_FORGET)
+                        | Some(fuse_opcode::FUSE_INTERRUPT)
+                        | Some(fuse_opcode::FUSE_NOTIFY_REPLY)
+                );
+                if replied {
+                    let code = self.shutdown_error.load(Ordering::SeqCst);
+                    let res = write_bytes(&**conn, Reply::new(header.unique, code, ()));
+                    if let Err(err) = res {
+                        tracing::debug!("failed to reply to the request after shutdown: {}", err);
+                    }
+                }
+                return Ok(None);
+            }
+
+            if self.zero_message_open.load(Ordering::SeqCst) {
+                let no_open = match opcode {
+                    Some(fuse_opcode::FUSE_OPEN) => FUSE_NO_OPEN_SUPPORT,
+                    Some(fuse_opcode::FUSE_OPENDIR) => FUSE_NO_OPENDIR_SUPPORT,
+                    _ => 0,
+                };
+                if self.readonly_flags & no_open != 0 {
+                    let res = write_bytes(&**conn, Reply::new(header.unique, libc::ENOSYS, ()));
+                    if let Err(err) = res {
+                        tracing::debug!("failed to reply to the open request: {}", err);
+                    }
+                    continue;
+                }
+            }
+
+            if self.reject_writes.load(Ordering::SeqCst) && is_modifying(opcode) {
+                let res = write_bytes(&**conn, Reply::new(header.unique, libc::EROFS, ()));
+                if let Err(err) = res {
+                    tracing::debug!("failed to reply to the modifying request: {}", err);
+                }
+                continue;
+            }
+
+            if let Some(fuse_opcode::FUSE_NOTIFY_REPLY) = opcode {
+                if self.retrieves.complete(header.unique, &arg[..arg_len]) {
+                    continue;
+                }
+            }
+
+            let (handling, inflight) = match opcode {
+                Some(fuse_opcode::FUSE_INTERRUPT) if self.interrupts.enabled() => {
+                    self.interrupts.interrupt(&arg[..arg_len]);
+                    continue;
+                }
+                // These requests are never replied.
+                Some(fuse_opcode::FUSE_FORGET)
+                | Some(fuse_opcode::FUSE_BATCH_FORGET)
+                | Some(fuse_opcode::FUSE_INTERRUPT)
+                | Some(fuse_opcode::FUSE_NOTIFY_REPLY) => (false, None),
+                _ if self.interrupts.enabled() || self.watchdog.enabled() => {
+                    let inflight = self.interrupts.register(conn, header.unique);
+                    self.watchdog.register(&header, &inflight);
+                    (true, Some(inflight))
+                }
+                _ => (true, None),
+            };
+            if handling {
+                self.handlers.enter();
+            }
+
+            arg.truncate(arg_len);
+
+            // The forget requests are sent frequently under memory pressure and
+            // are never replied, so they never hold the whole buffer.
+            let is_forget = matches!(
+                opcode,
+                Some(fuse_opcode::FUSE_FORGET) | Some(fuse_opcode::FUSE_BATCH_FORGET)
+            );
+            if is_forget || self.compact_buffers.load(Ordering::SeqCst) {
+                arg.shrink_to_fit();
+            }
+
+            let span = tracing::debug_span!(
+                target: "polyfuse::request",
+                "request",
+                unique = header.unique,
+                opcode = header.opcode,
+                nodeid = header.nodeid,
+                uid = header.uid,
+                pid = header.pid,
+            );
+
+            return Ok(Some(Request {
+                session: self.clone(),
+                conn: conn.clone(),
+                header,
+                arg,
+                span,
+                replied: AtomicBool::new(false),
+                handling,
+                inflight,
+            }));
+        }
+    }
 }
 
 impl Drop for Session {
@@ -317,6 +1025,14 @@ impl Drop for Session {
     }
 }
 
+/// Return the file descriptor of the FUSE device (`/dev/fuse` or `/dev/cuse`)
+/// connected to this session.
+///
+/// The file descriptor is owned by the session and remains valid until the
+/// last reference to the session, including the `Request`s and `Notifier`s
+/// created from it, is dropped. At that point it is closed and the filesystem
+/// is unmounted. The caller must not close it, and should duplicate it with
+/// `dup(2)` if it needs to outlive the session.
 impl AsRawFd for Session {
     fn as_raw_fd(&self) -> RawFd {
         self.inner.conn.as_raw_fd()
@@ -325,7 +1041,24 @@ impl AsRawFd for Session {
 
 impl Session {
     /// Start a FUSE daemon mount on the specified path.
-    pub fn mount(mountpoint: PathBuf, config: KernelConfig) -> io::Result<Self> {
+    pub fn mount(mountpoint: PathBuf, config: KernelConfig) -> Result<Self, Error> {
+        Self::mount_with(mountpoint, config, |_, _| Ok(()))
+    }
+
+    /// Start a FUSE daemon mount on the specified path, inspecting the INIT request
+    /// from the kernel by the provided callback.
+    ///
+    /// The callback is invoked with the parameters sent by the kernel before the reply
+    /// is sent, and may adjust the configuration, e.g. depending on the capabilities
+    /// provided by the kernel.  Since the filesystem is already mounted at that point,
+    /// the changes to the mount options are ignored.
+    ///
+    /// If the callback returns an error number, the initialization is rejected with it
+    /// and this function returns the corresponding `Error::Io`.
+    pub fn mount_with<F>(mountpoint: PathBuf, config: KernelConfig, f: F) -> Result<Self, Error>
+    where
+        F: FnOnce(&KernelInit, &mut KernelConfig) -> Result<(), i32>,
+    {
         let KernelConfig {
             mountopts,
             mut init_out,
@@ -333,86 +1066,512 @@ impl Session {
 
         let conn = Connection::open(mountpoint, mountopts)?;
 
-        init_session(&mut init_out, &conn, &conn)?;
-        let bufsize = BUFFER_HEADER_SIZE + init_out.max_write as usize;
+        let readonly_flags = init_session(&mut init_out, &conn, &conn, init_hook(f))?;
 
-        Ok(Self {
+        Ok(Self::new(conn, init_out, readonly_flags))
+    }
+
+    /// Start a FUSE daemon on the FUSE device that has already been opened and mounted.
+    ///
+    /// This is intended for the privilege-separated setup, where a privileged helper
+    /// opens `/dev/fuse`, mounts the filesystem with it, and passes the file descriptor
+    /// to the unprivileged daemon, e.g. via `SCM_RIGHTS`.  This function performs
+    /// the initialization handshake on the file descriptor without mounting anything,
+    /// so the mount options in `config` are ignored.
+    ///
+    /// The session takes the ownership of the file descriptor, and closes it when
+    /// the session and all the objects created from it are dropped, or when this
+    /// function fails.  Since the daemon does not know the mountpoint, it is up to
+    /// the helper to unmount the filesystem, and `Session::abort` is not available.
+    ///
+    /// # Safety
+    /// `fd` must be an open file descriptor owned by the caller, and must not be used
+    /// or closed by the caller after this call, as with `FromRawFd::from_raw_fd`.
+    pub unsafe fn from_raw_fd(fd: RawFd, config: KernelConfig) -> Result<Self, Error> {
+        let mut init_out = config.init_out;
+
+        let conn = Connection::from_raw_fd(fd);
+
+        let readonly_flags = init_session(&mut init_out, &conn, &conn, init_hook(|_, _| Ok(())))?;
+
+        Ok(Self::new(conn, init_out, readonly_flags))
+    }
+
+    /// Start a CUSE daemon that provides a character device in userspace.
+    ///
+    /// The incoming requests are limited to the file operations, such as
+    /// `open`, `read`, `write`, `ioctl`, `poll` and `release`, and the
+    /// inode numbers in them are meaningless.
+    pub fn cuse(config: CuseConfig) -> Result<Self, Error> {
+        let devinfo = config.devinfo();
+        let mut cuse_out = config.init_out;
+
+        let conn = Connection::open_cuse()?;
+
+        init_cuse_session(&mut cuse_out, &devinfo, &conn, &conn)?;
+
+        let init_out = fuse_init_out {
+            minor: cuse_out.minor,
+            max_write: cuse_out.max_write,
+            ..Default::default()
+        };
+        Ok(Self::new(conn, init_out, 0))
+    }
+
+    fn new(conn: Connection, init_out: fuse_init_out, readonly_flags: u32) -> Self {
+        let bufsize = min_buffer_size(&init_out);
+        Self {
             inner: Arc::new(SessionInner {
-                conn,
+                stream: conn.is_stream(),
+                conn: Arc::new(conn),
                 init_out,
-                bufsize,
+                readonly_flags,
+                bufsize: AtomicUsize::new(bufsize),
+                compact_buffers: AtomicBool::new(false),
+                zero_message_open: AtomicBool::new(false),
+                reject_writes: AtomicBool::new(false),
                 exited: AtomicBool::new(false),
+                aborted: AtomicBool::new(false),
+                shutdown: AtomicBool::new(false),
+                shutdown_error: AtomicI32::new(libc::ENODEV),
+                exit_reason: Mutex::new(None),
                 notify_unique: AtomicU64::new(0),
+                retrieves: Retrieves::default(),
+                interrupts: Interrupts::default(),
+                watchdog: Watchdog::default(),
+                handlers: Handlers::default(),
             }),
-        })
+        }
     }
 
-    /// Return whether the kernel supports for zero-message opens.
+    /// Return the capability flags negotiated with the kernel.
     ///
-    /// When the returned value is `true`, the kernel treat an `ENOSYS`
-    /// error for a `FUSE_OPEN` request as successful and does not send
-    /// subsequent `open` requests.  Otherwise, the filesystem should
-    /// implement the handler for `open` requests appropriately.
-    pub fn no_open_support(&self) -> bool {
-        self.inner.init_out.flags & FUSE_NO_OPEN_SUPPORT != 0
+    /// The layout of the returned value is the same as `KernelInit::flags`.
+    /// Only the flags sent back in the reply to `INIT` are included, so the
+    /// read-only flags offered by the kernel (e.g. `FUSE_NO_OPEN_SUPPORT`) are not.
+    pub fn flags(&self) -> u64 {
+        self.inner.init_out.flags as u64 | (self.inner.init_out.flags2 as u64) << 32
     }
 
-    /// Return whether the kernel supports for zero-message opendirs.
+    /// Iterate over the capability flags negotiated with the kernel.
     ///
-    /// See the documentation of `no_open_support` for details.
-    pub fn no_opendir_support(&self) -> bool {
-        self.inner.init_out.flags & FUSE_NO_OPENDIR_SUPPORT != 0
+    /// Each item is a pair of the name of the flag (e.g. `"FUSE_ASYNC_READ"`) and
+    /// its value in the layout of `flags`, which is useful for logging the granted
+    /// capabilities at startup.  The flags that are not known to this crate are
+    /// yielded with the name `"UNKNOWN"`.
+    pub fn capabilities(&self) -> impl Iterator<Item = (&'static str, u64)> {
+        let flags = self.flags();
+        (0..64)
+            .map(|i| 1u64 << i)
+            .filter(move |flag| flags & flag != 0)
+            .map(|flag| {
+                let name = INIT_FLAG_NAMES
+                    .iter()
+                    .find(|&&(_, f)| f == flag)
+                    .map_or("UNKNOWN", |&(name, _)| name);
+                (name, flag)
+            })
     }
 
-    /// Receive an incoming FUSE request from the kernel.
-    pub fn next_request(&self) -> io::Result<Option<Request>> {
-        let mut conn = &self.inner.conn;
-
-        // FIXME: Align the allocated region in `arg` with the FUSE argument types.
-        let mut header = fuse_in_header::default();
-        let mut arg = vec![0u8; self.inner.bufsize - mem::size_of::<fuse_in_header>()];
+    /// Return whether the kernel supports the passthrough mode.
+    ///
+    /// See the documentation of `KernelConfig::passthrough` for details.
+    pub fn passthrough(&self) -> bool {
+        self.inner.init_out.flags2 & (FUSE_PASSTHROUGH >> 32) as u32 != 0
+    }
 
-        loop {
-            match conn.read_vectored(&mut [
-                io::IoSliceMut::new(header.as_bytes_mut()),
-                io::IoSliceMut::new(&mut arg[..]),
-            ]) {
-                Ok(len) => {
-                    if len < mem::size_of::<fuse_in_header>() {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "dequeued request message is too short",
-                        ));
-                    }
-                    unsafe {
-                        arg.set_len(len - mem::size_of::<fuse_in_header>());
-                    }
+    /// Register a file as the backing file of the passthrough mode.
+    ///
+    /// The returned backing ID is passed to the kernel via `OpenOut::passthrough`.
+    /// The kernel keeps its own reference to the backing file while it is opened,
+    /// so the ID can be released by `close_backing` once the open request is replied.
+    pub fn open_backing(&self, fd: RawFd) -> io::Result<u32> {
+        self.inner.conn.open_backing(fd)
+    }
 
-                    break;
-                }
+    /// Unregister the backing file associated with the specified ID.
+    pub fn close_backing(&self, backing_id: u32) -> io::Result<()> {
+        self.inner.conn.close_backing(backing_id)
+    }
 
-                Err(err) => match err.raw_os_error() {
-                    Some(libc::ENODEV) => {
-                        tracing::debug!("ENODEV");
-                        return Ok(None);
-                    }
-                    Some(libc::ENOENT) => {
-                        tracing::debug!("ENOENT");
-                        continue;
-                    }
-                    _ => return Err(err),
-                },
-            }
+    /// Return the maximum readahead negotiated with the kernel.
+    pub fn max_readahead(&self) -> u32 {
+        self.inner.init_out.max_readahead
+    }
+
+    /// Return the maximum size of the data carried by a single `write` request.
+    ///
+    /// The value is negotiated with the kernel at initialization and may be
+    /// smaller than the value specified by `KernelConfig::max_write`.
+    /// It is useful for sizing the buffers that the filesystem copies
+    /// the written data into.
+    pub fn max_write(&self) -> u32 {
+        self.inner.init_out.max_write
+    }
+
+    /// Return the maximum number of pending background requests sent to the kernel
+    /// at initialization.
+    ///
+    /// This is the value specified by `KernelConfig::max_background`, which the
+    /// kernel may cap for unprivileged users without notifying the filesystem.
+    /// The value actually applied can be obtained by `applied_max_background`.
+    pub fn max_background(&self) -> u16 {
+        self.inner.init_out.max_background
+    }
+
+    /// Return the congestion threshold sent to the kernel at initialization.
+    ///
+    /// See the documentation of `max_background` for the caveats.
+    pub fn congestion_threshold(&self) -> u16 {
+        self.inner.init_out.congestion_threshold
+    }
+
+    /// Return the maximum number of pending background requests applied by the kernel.
+    ///
+    /// The value is read from the `max_background` file of the connection in
+    /// the FUSE control filesystem, so the same requirements as `abort` apply.
+    /// If it differs from the value returned by `max_background`, the kernel
+    /// has capped the requested value.
+    pub fn applied_max_background(&self) -> io::Result<u16> {
+        self.read_fusectl_u16("max_background")
+    }
+
+    /// Return the congestion threshold applied by the kernel.
+    ///
+    /// See the documentation of `applied_max_background` for details.
+    pub fn applied_congestion_threshold(&self) -> io::Result<u16> {
+        self.read_fusectl_u16("congestion_threshold")
+    }
+
+    fn read_fusectl_u16(&self, name: &str) -> io::Result<u16> {
+        self.inner
+            .conn
+            .read_fusectl(name)?
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Return the size of the buffer used for receiving a request from the kernel.
+    ///
+    /// By default, the value is `max_write` plus the space reserved for the request
+    /// header and the other arguments, and hence the upper bound of the length of
+    /// any incoming request.  If `FUSE_MAX_PAGES` is negotiated and `max_pages`
+    /// pages are larger than `max_write`, the former is used instead.
+    pub fn buffer_size(&self) -> usize {
+        self.inner.bufsize.load(Ordering::SeqCst)
+    }
+
+    /// Set the size of the buffer used for receiving a request from the kernel.
+    ///
+    /// The kernel rejects the reads from the FUSE device with a buffer smaller than
+    /// the default size, and so does this method with `io::ErrorKind::InvalidInput`.
+    /// A larger buffer can be specified independently of `max_write`, and takes
+    /// effect from the next call of `next_request` on the session and its channels.
+    ///
+    /// Note that the buffer only bounds the incoming requests.  The length of
+    /// the replies, e.g. to `read` and `readdir` requests, is limited by the size
+    /// specified in each request, which is bounded by `max_read` and the maximum
+    /// number of pages per request rather than by this buffer.
+    pub fn set_buffer_size(&self, size: usize) -> io::Result<()> {
+        let min_size = min_buffer_size(&self.inner.init_out);
+        if size < min_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the buffer size must be at least {} bytes", min_size),
+            ));
+        }
+        self.inner.bufsize.store(size, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Return the minor version of the kernel ABI negotiated with the kernel.
+    ///
+    /// The value never exceeds `KernelConfig::protocol_minor` nor the version
+    /// implemented by this crate, and it is at least 23 since the session refuses
+    /// to be initialized with older kernels.  In particular, `FUSE_CREATE`
+    /// (added in 7.9) is always available on the negotiated protocol; a filesystem
+    /// that does not implement `create` should reply `ENOSYS` to let the kernel
+    /// fall back to `mknod` and `open`, as described in `op::Create`.
+    pub fn protocol_minor(&self) -> u32 {
+        self.inner.init_out.minor
+    }
+
+    /// Return whether the kernel supports for zero-message opens.
+    ///
+    /// When the returned value is `true`, the kernel treat an `ENOSYS`
+    /// error for a `FUSE_OPEN` request as successful and does not send
+    /// subsequent `open` requests.  Otherwise, the filesystem should
+    /// implement the handler for `open` requests appropriately.
+    pub fn no_open_support(&self) -> bool {
+        self.inner.readonly_flags & FUSE_NO_OPEN_SUPPORT != 0
+    }
+
+    /// Return whether the kernel supports for zero-message opendirs.
+    ///
+    /// See the documentation of `no_open_support` for details.
+    pub fn no_opendir_support(&self) -> bool {
+        self.inner.readonly_flags & FUSE_NO_OPENDIR_SUPPORT != 0
+    }
+
+    /// Specify whether the session answers the `open` and `opendir` requests
+    /// automatically for the filesystem without any per-open state.
+    ///
+    /// When enabled and the kernel supports the zero-message opens (see
+    /// `no_open_support` and `no_opendir_support`), the session replies to
+    /// the `open` and `opendir` requests with `ENOSYS` by itself and never returns
+    /// them from `next_request`, so the kernel stops sending them.  The subsequent
+    /// requests on the opened files then carry the handle `0`.  If the kernel
+    /// does not support them, the requests are returned as usual and the filesystem
+    /// must reply with a handle.
+    pub fn set_zero_message_open(&self, enabled: bool) {
+        self.inner
+            .zero_message_open
+            .store(enabled, Ordering::SeqCst);
+    }
+
+    /// Return whether the filesystem is mounted read-only.
+    ///
+    /// See the documentation of `KernelConfig::read_only` for details.
+    pub fn read_only(&self) -> bool {
+        self.inner.conn.read_only()
+    }
+
+    /// Specify whether the session rejects the requests modifying the filesystem.
+    ///
+    /// When enabled, the session replies with `EROFS` by itself to the requests
+    /// that modify the filesystem, such as `write`, `setattr`, `create`, `mkdir`,
+    /// `unlink`, `rename` and `setxattr`, and never returns them from `next_request`.
+    /// This is intended for the read-only filesystems (see `read_only`), so that
+    /// the handlers need not check it everywhere.  The `open` requests are passed
+    /// to the filesystem regardless of their access mode.
+    pub fn set_reject_writes(&self, enabled: bool) {
+        self.inner.reject_writes.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Return whether the kernel leaves the invalidation of the cached pages
+    /// to the filesystem.
+    ///
+    /// See the documentation of `KernelConfig::explicit_inval_data` for details.
+    pub fn explicit_inval_data(&self) -> bool {
+        self.inner.init_out.flags & FUSE_EXPLICIT_INVAL_DATA != 0
+    }
+
+    /// Return whether the kernel caches the results of `readlink`.
+    pub fn cache_symlinks(&self) -> bool {
+        self.inner.init_out.flags & FUSE_CACHE_SYMLINKS != 0
+    }
+
+    /// Return whether the kernel enables writeback caching.
+    ///
+    /// See the documentation of `KernelConfig::writeback_cache` for details.
+    pub fn writeback_cache(&self) -> bool {
+        self.inner.init_out.flags & FUSE_WRITEBACK_CACHE != 0
+    }
+
+    /// Return whether the filesystem is mounted with the `default_permissions` option.
+    ///
+    /// See the documentation of `KernelConfig::default_permissions` for details.
+    pub fn default_permissions(&self) -> bool {
+        self.inner.conn.default_permissions()
+    }
+
+    /// Return whether the filesystem applies the umask to the file mode by itself.
+    ///
+    /// See the documentation of `KernelConfig::dont_mask` for details.
+    pub fn dont_mask(&self) -> bool {
+        self.inner.init_out.flags & FUSE_DONT_MASK != 0
+    }
+
+    /// Return whether the kernel supports the nested mountpoints.
+    ///
+    /// See the documentation of `KernelConfig::submounts` for details.
+    pub fn submounts(&self) -> bool {
+        self.inner.init_out.flags & FUSE_SUBMOUNTS != 0
+    }
+
+    /// Receive an incoming FUSE request from the kernel.
+    ///
+    /// The `NOTIFY_REPLY` messages corresponding to the notifications sent by
+    /// `Notifier::retrieve` are consumed internally and never returned from
+    /// this method.
+    ///
+    /// It returns `None` when the kernel has closed the connection, and the
+    /// reason is available via `exit_reason` afterwards.
+    pub fn next_request(&self) -> Result<Option<Request>, Error> {
+        self.inner.next_request(&self.inner.conn)
+    }
+
+    /// Receive an incoming FUSE request from the kernel without blocking.
+    ///
+    /// This method returns `Poll::Pending` immediately if there is no request
+    /// in the queue, and `Poll::Ready(None)` if the session has been closed.
+    /// It requires the device file descriptor to be in the non-blocking mode
+    /// via `set_nonblocking`; otherwise it blocks the same as `next_request`.
+    /// The readiness of the file descriptor obtained by `as_raw_fd` can be
+    /// waited with `poll(2)` or `epoll(7)` together with other event sources.
+    pub fn try_next_request(&self) -> Result<Poll<Option<Request>>, Error> {
+        try_next_request(self.next_request())
+    }
+
+    /// Set the device file descriptor of this session into or out of the non-blocking mode.
+    ///
+    /// When the non-blocking mode is enabled, `next_request` returns an error
+    /// with `io::ErrorKind::WouldBlock` if there is no request in the queue.
+    /// The channels created by `clone_channel` have their own file descriptor
+    /// and are not affected by this setting.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.inner.conn.set_nonblocking(nonblocking)
+    }
+
+    /// Specify whether the buffers of the received requests are shrunk to fit their length.
+    ///
+    /// The kernel requires every read from the FUSE device to provide a buffer
+    /// large enough for the largest possible request, i.e. `buffer_size` bytes.
+    /// By default, each `Request` keeps that buffer as is, which wastes memory when
+    /// many small requests are held at once, e.g. queued for the worker threads.
+    /// When enabled, the buffer is reallocated to the actual length of the request
+    /// just after receiving it, at the cost of an extra copy.  Only the `write`
+    /// requests carrying large data keep large buffers, bounded by `max_write`.
+    ///
+    /// The buffers of `forget` requests are always compacted regardless of this
+    /// setting, since they are never replied and may arrive in bulk.
+    pub fn set_compact_buffers(&self, enabled: bool) {
+        self.inner.compact_buffers.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Specify whether the session answers the interrupted requests automatically.
+    ///
+    /// When enabled, the `interrupt` requests are consumed internally and never
+    /// returned from `next_request`.  Instead, if the target request is still
+    /// being processed, the session immediately replies to it with an `ECANCELED`
+    /// error on behalf of the filesystem.  The subsequent reply from the filesystem
+    /// is silently discarded, so the handler may either check `Request::interrupted`
+    /// to stop its work early or simply ignore the cancellation.
+    ///
+    /// Only the requests received after enabling this option are tracked, and the
+    /// interrupts for the others are silently ignored.  An interrupt that arrives
+    /// before its target request (e.g. received on another channel) is applied
+    /// as soon as the target is received.
+    pub fn set_auto_interrupt(&self, enabled: bool) {
+        self.inner.interrupts.set_enabled(enabled);
+    }
+
+    /// Specify the deadline for the filesystem to reply to each request.
+    ///
+    /// A handler that deadlocks and never replies leaves the caller's operation
+    /// hanging forever, and the filesystem appears frozen without any diagnostic.
+    /// When the deadline is set, a background thread watches the requests returned
+    /// from `next_request` and emits a `WARN` event with the unique ID and the opcode
+    /// of each request that is neither replied nor dropped within the deadline.
+    /// See `set_reply_timeout_error` for answering such requests on behalf of the filesystem.
+    ///
+    /// The `forget` and `interrupt` requests are not watched, since they are never replied.
+    /// Only the requests received after setting the deadline are watched, and `None`
+    /// disables the watchdog.
+    pub fn set_reply_timeout(&self, timeout: Option<Duration>) {
+        self.inner.watchdog.set_timeout(timeout);
+    }
+
+    /// Specify the error replied to the requests that exceed the deadline.
+    ///
+    /// When the error number is set (e.g. `libc::EIO`), the session replies to
+    /// the expired requests with it, so that the caller's operation fails instead
+    /// of hanging.  The subsequent reply from the filesystem is silently discarded,
+    /// as for the interrupted requests (see `set_auto_interrupt`).  By default,
+    /// the expired requests are only logged.
+    pub fn set_reply_timeout_error(&self, code: Option<i32>) {
+        self.inner.watchdog.set_error(code);
+    }
+
+    /// Abort the connection with the kernel.
+    ///
+    /// This has the same effect as writing to the `abort` file of the connection
+    /// in the FUSE control filesystem: all of the pending and subsequent operations
+    /// on the filesystem fail with `ENOTCONN`, and `next_request` returns `None`.
+    /// This is useful for recovering from the filesystem wedged by a bug, e.g. by
+    /// a watchdog thread, since unmounting such a filesystem may hang.
+    ///
+    /// The connection is located under `/sys/fs/fuse/connections` by the device
+    /// number of the filesystem found in `/proc/self/mountinfo`, so the control
+    /// filesystem must be mounted there and the mountpoint must be specified
+    /// without symbolic links.  It is not supported for CUSE sessions or on FreeBSD.
+    pub fn abort(&self) -> io::Result<()> {
+        self.inner.aborted.store(true, Ordering::SeqCst);
+        self.inner
+            .conn
+            .abort()
+            .inspect_err(|_| self.inner.aborted.store(false, Ordering::SeqCst))
+    }
+
+    /// Shut down the session.
+    ///
+    /// After calling this method, `next_request` on the session and its channels
+    /// returns `None` instead of receiving the subsequent requests.  The requests
+    /// being processed by the filesystem are marked as interrupted so that
+    /// the handlers can stop early by checking `Request::interrupted`.
+    ///
+    /// If the error number is specified (e.g. `libc::EIO`), the unanswered requests
+    /// tracked by the session are replied with it on behalf of the filesystem so that
+    /// the callers are not left blocked.  As for the interrupted requests, the subsequent
+    /// replies from the filesystem are silently discarded.  Note that the session tracks
+    /// only the requests received while `set_auto_interrupt` or `set_reply_timeout` was
+    /// enabled, so by default nothing is replied and the filesystem remains responsible
+    /// for replying to all of the requests it has received.
+    ///
+    /// The threads blocked in `next_request` are not woken by this method, but
+    /// they return `None` once the next request arrives or the filesystem is
+    /// unmounted.  That request is replied with the specified error number,
+    /// or `ENODEV` if not specified.  The session remains mounted until it is dropped.
+    pub fn shutdown(&self, code: Option<i32>) {
+        if let Some(code) = code {
+            self.inner.shutdown_error.store(code, Ordering::SeqCst);
         }
+        self.inner.shutdown.store(true, Ordering::SeqCst);
+        self.inner.interrupts.cancel_all(code);
+    }
+
+    /// Block the current thread until the handlers of all received requests finish.
+    ///
+    /// A handler is considered finished when its `Request` is dropped, so this
+    /// method lets a daemon that detaches the handlers (e.g. by `task::spawn`)
+    /// ensure all replies are flushed before exiting.  The requests that are not
+    /// replied to, such as `forget`, are not waited for.  Typically it is called
+    /// after `shutdown` and, in an async context, from within a blocking task.
+    ///
+    /// Return `false` if the timeout elapses before all handlers finish.
+    pub fn join_handlers(&self, timeout: Option<Duration>) -> bool {
+        self.inner.handlers.wait_all(timeout)
+    }
+
+    /// Return the reason why the kernel has closed the connection.
+    ///
+    /// The returned value is `None` until `next_request` (or that of a `Channel`)
+    /// returns `None` for the first time.  A supervising daemon can use it to
+    /// decide whether to mount the filesystem again or to shut down.
+    pub fn exit_reason(&self) -> Option<ExitReason> {
+        *self.inner.exit_reason.lock().unwrap()
+    }
 
-        Ok(Some(Request {
+    /// Create an additional channel for receiving the requests in parallel.
+    ///
+    /// The channel owns a new file descriptor of `/dev/fuse` attached to this
+    /// session via `FUSE_DEV_IOC_CLONE` (Linux 4.2 or later). All channels read
+    /// from the single pending queue of the connection, but each channel has its
+    /// own request processing queue in the kernel, so that the replies of multiple
+    /// worker threads are not serialized on a single device.
+    ///
+    /// On FreeBSD, this method returns an error of `ErrorKind::Unsupported`.
+    pub fn clone_channel(&self) -> io::Result<Channel> {
+        let conn = self.inner.conn.try_clone_device()?;
+        Ok(Channel {
             session: self.inner.clone(),
-            header,
-            arg,
-        }))
+            conn: Arc::new(conn),
+        })
     }
 
     /// Create an instance of `Notifier` corresponding to this session.
+    ///
+    /// See the documentation of `Notifier` for the available notifications.
     pub fn notifier(&self) -> Notifier {
         Notifier {
             session: self.inner.clone(),
@@ -420,14 +1579,40 @@ impl Session {
     }
 }
 
-fn init_session<R, W>(init_out: &mut fuse_init_out, mut reader: R, mut writer: W) -> io::Result<()>
+/// Adapt the user-provided callback to the hook of `init_session`.
+fn init_hook<F>(f: F) -> impl FnOnce(&fuse_init_in, &mut fuse_init_out) -> Result<(), i32>
+where
+    F: FnOnce(&KernelInit, &mut KernelConfig) -> Result<(), i32>,
+{
+    move |init_in, init_out| {
+        let mut config = KernelConfig {
+            mountopts: MountOptions::default(),
+            init_out: *init_out,
+        };
+        f(&KernelInit { arg: *init_in }, &mut config)?;
+        *init_out = config.init_out;
+        Ok(())
+    }
+}
+
+/// Perform the initialization handshake, and return the read-only flags offered by the kernel.
+fn init_session<R, W, F>(
+    init_out: &mut fuse_init_out,
+    mut reader: R,
+    mut writer: W,
+    hook: F,
+) -> Result<u32, Error>
 where
     R: io::Read,
     W: io::Write,
+    F: FnOnce(&fuse_init_in, &mut fuse_init_out) -> Result<(), i32>,
 {
+    let mut hook = Some(hook);
+
     // FIXME: align the allocated buffer in `buf` with FUSE argument types.
     let mut header = fuse_in_header::default();
     let mut arg = vec![0u8; pagesize() * MAX_MAX_PAGES];
+    let mut before_init = BeforeInit::default();
 
     for _ in 0..10 {
         let len = reader.read_vectored(&mut [
@@ -435,28 +1620,23 @@ where
             io::IoSliceMut::new(&mut arg[..]),
         ])?;
         if len < mem::size_of::<fuse_in_header>() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "request message is too short",
-            ));
+            return Err(unexpected_eof());
         }
 
-        let mut decoder = Decoder::new(&arg[..]);
+        let payload = &arg[..len - mem::size_of::<fuse_in_header>()];
 
         match fuse_opcode::try_from(header.opcode) {
             Ok(fuse_opcode::FUSE_INIT) => {
-                let init_in = decoder
-                    .fetch::<fuse_init_in>() //
-                    .map_err(|_| {
-                        io::Error::new(io::ErrorKind::Other, "failed to decode fuse_init_in")
-                    })?;
+                let init_in = decode_init_in(payload).ok_or_else(unexpected_eof)?;
 
                 let capable = init_in.flags & INIT_FLAGS_MASK;
                 let readonly_flags = init_in.flags & !INIT_FLAGS_MASK;
+                let capable2 = init_in.flags2 & INIT_FLAGS2_MASK;
 
                 tracing::debug!("INIT request:");
                 tracing::debug!("  proto = {}.{}:", init_in.major, init_in.minor);
                 tracing::debug!("  flags = 0x{:08x} ({:?})", init_in.flags, capable);
+                tracing::debug!("  flags2 = 0x{:08x} ({:?})", init_in.flags2, capable2);
                 tracing::debug!("  max_readahead = 0x{:08X}", init_in.max_readahead);
                 tracing::debug!("  max_pages = {}", readonly_flags & FUSE_MAX_PAGES != 0);
                 tracing::debug!(
@@ -490,7 +1670,18 @@ where
                         init_in.minor
                     );
                     write_bytes(&mut writer, Reply::new(header.unique, libc::EPROTO, ()))?;
-                    continue;
+                    return Err(Error::UnsupportedVersion {
+                        major: init_in.major,
+                        minor: init_in.minor,
+                    });
+                }
+
+                if let Some(hook) = hook.take() {
+                    if let Err(errno) = hook(&init_in, init_out) {
+                        tracing::warn!("the initialization is rejected by the filesystem");
+                        write_bytes(&mut writer, Reply::new(header.unique, errno, ()))?;
+                        return Err(Error::Io(io::Error::from_raw_os_error(errno)));
+                    }
                 }
 
                 init_out.minor = cmp::min(init_out.minor, init_in.minor);
@@ -500,6 +1691,14 @@ where
                 init_out.flags &= capable;
                 init_out.flags |= FUSE_BIG_WRITES; // the flag was superseded by `max_write`.
 
+                init_out.flags2 &= capable2;
+                if init_out.flags2 & (FUSE_PASSTHROUGH >> 32) as u32 == 0 {
+                    init_out.max_stack_depth = 0;
+                }
+                if init_out.flags2 != 0 {
+                    init_out.flags |= FUSE_INIT_EXT;
+                }
+
                 if init_in.flags & FUSE_MAX_PAGES != 0 {
                     init_out.flags |= FUSE_MAX_PAGES;
                     init_out.max_pages = cmp::min(
@@ -514,6 +1713,7 @@ where
                 tracing::debug!("Reply to INIT:");
                 tracing::debug!("  proto = {}.{}:", init_out.major, init_out.minor);
                 tracing::debug!("  flags = 0x{:08x}", init_out.flags);
+                tracing::debug!("  flags2 = 0x{:08x}", init_out.flags2);
                 tracing::debug!("  max_readahead = 0x{:08X}", init_out.max_readahead);
                 tracing::debug!("  max_write = 0x{:08X}", init_out.max_write);
                 tracing::debug!("  max_background = 0x{:04X}", init_out.max_background);
@@ -524,9 +1724,7 @@ where
                 tracing::debug!("  time_gran = {}", init_out.time_gran);
                 write_bytes(writer, Reply::new(header.unique, 0, init_out.as_bytes()))?;
 
-                init_out.flags |= readonly_flags;
-
-                return Ok(());
+                return Ok(readonly_flags);
             }
 
             _ => {
@@ -535,87 +1733,834 @@ where
                     header.opcode
                 );
                 write_bytes(&mut writer, Reply::new(header.unique, libc::EIO, ()))?;
+                before_init.push(header.opcode);
                 continue;
             }
         }
     }
 
-    Err(io::Error::new(
-        io::ErrorKind::ConnectionRefused,
-        "session initialization is aborted",
-    ))
+    Err(before_init.into_error())
 }
 
-// ==== Request ====
+fn init_cuse_session<R, W>(
+    init_out: &mut cuse_init_out,
+    devinfo: &[u8],
+    mut reader: R,
+    mut writer: W,
+) -> Result<(), Error>
+where
+    R: io::Read,
+    W: io::Write,
+{
+    let mut header = fuse_in_header::default();
+    let mut arg = vec![0u8; pagesize() * MAX_MAX_PAGES];
+    let mut before_init = BeforeInit::default();
 
-/// Context about an incoming FUSE request.
-pub struct Request {
-    session: Arc<SessionInner>,
-    header: fuse_in_header,
-    arg: Vec<u8>,
-}
+    for _ in 0..10 {
+        let len = reader.read_vectored(&mut [
+            io::IoSliceMut::new(header.as_bytes_mut()),
+            io::IoSliceMut::new(&mut arg[..]),
+        ])?;
+        if len < mem::size_of::<fuse_in_header>() {
+            return Err(unexpected_eof());
+        }
 
-impl Request {
-    /// Return the unique ID of the request.
-    #[inline]
-    pub fn unique(&self) -> u64 {
-        self.header.unique
-    }
+        let mut decoder = Decoder::new(&arg[..len - mem::size_of::<fuse_in_header>()]);
 
-    /// Return the user ID of the calling process.
-    #[inline]
-    pub fn uid(&self) -> u32 {
-        self.header.uid
-    }
+        match fuse_opcode::try_from(header.opcode) {
+            Ok(fuse_opcode::CUSE_INIT) => {
+                let init_in = decoder
+                    .fetch::<cuse_init_in>() //
+                    .map_err(DecodeError::new)?;
 
-    /// Return the group ID of the calling process.
-    #[inline]
-    pub fn gid(&self) -> u32 {
-        self.header.gid
-    }
+                tracing::debug!("CUSE_INIT request:");
+                tracing::debug!("  proto = {}.{}:", init_in.major, init_in.minor);
+                tracing::debug!("  flags = 0x{:08x}", init_in.flags);
 
-    /// Return the process ID of the calling process.
-    #[inline]
-    pub fn pid(&self) -> u32 {
-        self.header.pid
-    }
+                if init_in.major != 7 || init_in.minor < MINIMUM_SUPPORTED_MINOR_VERSION {
+                    tracing::warn!(
+                        "polyfuse supports only ABI 7.{} or later. {}.{} is not supported",
+                        MINIMUM_SUPPORTED_MINOR_VERSION,
+                        init_in.major,
+                        init_in.minor
+                    );
+                    write_bytes(&mut writer, Reply::new(header.unique, libc::EPROTO, ()))?;
+                    return Err(Error::UnsupportedVersion {
+                        major: init_in.major,
+                        minor: init_in.minor,
+                    });
+                }
 
-    /// Decode the argument of this request.
-    pub fn operation(&self) -> Result<Operation<'_, Data<'_>>, DecodeError> {
-        if self.session.exited() {
-            return Ok(Operation::unknown());
-        }
+                init_out.minor = cmp::min(init_out.minor, init_in.minor);
+                init_out.flags &= init_in.flags;
 
-        let (arg, data) = match fuse_opcode::try_from(self.header.opcode).ok() {
-            Some(fuse_opcode::FUSE_WRITE) | Some(fuse_opcode::FUSE_NOTIFY_REPLY) => {
-                self.arg.split_at(mem::size_of::<fuse_write_in>())
-            }
-            _ => (&self.arg[..], &[] as &[_]),
-        };
+                tracing::debug!("Reply to CUSE_INIT:");
+                tracing::debug!("  proto = {}.{}:", init_out.major, init_out.minor);
+                tracing::debug!("  flags = 0x{:08x}", init_out.flags);
+                tracing::debug!("  max_read = 0x{:08X}", init_out.max_read);
+                tracing::debug!("  max_write = 0x{:08X}", init_out.max_write);
+                tracing::debug!("  dev = {}:{}", init_out.dev_major, init_out.dev_minor);
+                write_bytes(
+                    writer,
+                    Reply::new(header.unique, 0, (init_out.as_bytes(), devinfo)),
+                )?;
 
-        Operation::decode(&self.header, arg, Data { data })
-    }
+                return Ok(());
+            }
 
-    pub fn reply<T>(&self, arg: T) -> io::Result<()>
-    where
-        T: Bytes,
-    {
-        write_bytes(&self.session.conn, Reply::new(self.unique(), 0, arg))
+            _ => {
+                tracing::warn!(
+                    "ignoring an operation before init (opcode={:?})",
+                    header.opcode
+                );
+                write_bytes(&mut writer, Reply::new(header.unique, libc::EIO, ()))?;
+                before_init.push(header.opcode);
+                continue;
+            }
+        }
     }
 
-    pub fn reply_error(&self, code: i32) -> io::Result<()> {
-        write_bytes(&self.session.conn, Reply::new(self.unique(), code, ()))
-    }
+    Err(before_init.into_error())
 }
 
-/// The remaining part of request message.
-pub struct Data<'op> {
-    data: &'op [u8],
+/// The operations received before `INIT` during the initialization.
+#[derive(Default)]
+struct BeforeInit {
+    last_opcode: u32,
+    count: usize,
 }
 
-impl fmt::Debug for Data<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Data").finish()
+impl BeforeInit {
+    fn push(&mut self, opcode: u32) {
+        self.last_opcode = opcode;
+        self.count += 1;
+    }
+
+    /// Return the error reported when the initialization is given up.
+    fn into_error(self) -> Error {
+        if self.count > 0 {
+            Error::OperationBeforeInit {
+                opcode: self.last_opcode,
+                count: self.count,
+            }
+        } else {
+            io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "session initialization is aborted",
+            )
+            .into()
+        }
+    }
+}
+
+/// Validate the length of the request message received by a single `read`
+/// from the FUSE device.
+///
+/// The device always delivers a whole message at once, so the received length
+/// must be the same as the length in the header.  A shorter read is never
+/// completed by the subsequent `read`, which would return the next message.
+/// Return the length of the argument.
+fn validate_message_len(header: &fuse_in_header, len: usize) -> Result<usize, Error> {
+    let header_len = mem::size_of::<fuse_in_header>();
+    if len < header_len || header.len as usize != len {
+        tracing::error!(
+            "invalid message length: header.len = {}, received = {}",
+            header.len,
+            len
+        );
+        return Err(Error::Decode(DecodeError::new(
+            decoder::DecodeError::InvalidLength,
+        )));
+    }
+    Ok(len - header_len)
+}
+
+/// Complete the request message partially received by the first `read`
+/// from a stream transport.
+///
+/// The length in the header is validated so as not to read beyond the buffer.
+/// Return the length of the argument.
+#[inline]
+fn read_remaining<R>(
+    mut reader: R,
+    header: &mut fuse_in_header,
+    arg: &mut [u8],
+    mut len: usize,
+) -> Result<usize, Error>
+where
+    R: io::Read,
+{
+    let header_len = mem::size_of::<fuse_in_header>();
+
+    while len < header_len {
+        match reader.read(&mut header.as_bytes_mut()[len..])? {
+            0 => return Err(unexpected_eof()),
+            n => len += n,
+        }
+    }
+
+    let total_len = header.len as usize;
+    if total_len < header_len || total_len - header_len > arg.len() || total_len < len {
+        tracing::error!(
+            "invalid message length: header.len = {}, received = {}",
+            total_len,
+            len
+        );
+        return Err(Error::Decode(DecodeError::new(
+            decoder::DecodeError::InvalidLength,
+        )));
+    }
+
+    while len < total_len {
+        match reader.read(&mut arg[len - header_len..total_len - header_len])? {
+            0 => return Err(unexpected_eof()),
+            n => len += n,
+        }
+    }
+
+    Ok(total_len - header_len)
+}
+
+fn unexpected_eof() -> Error {
+    Error::Decode(DecodeError::new(decoder::DecodeError::UnexpectedEof))
+}
+
+/// Decode the payload of `FUSE_INIT` request.
+///
+/// Kernels prior to ABI 7.36 send the shorter version of `fuse_init_in`
+/// without `flags2`, and the missing fields are filled with zeros.
+/// The kernels prior to ABI 7.6 send only the protocol version, which is
+/// decoded so that the version can be rejected or renegotiated properly.
+fn decode_init_in(arg: &[u8]) -> Option<fuse_init_in> {
+    if arg.len() < COMPAT_5_INIT_IN_SIZE {
+        return None;
+    }
+    let mut init_in = fuse_init_in::default();
+    init_in.as_bytes_mut()[..COMPAT_5_INIT_IN_SIZE].copy_from_slice(&arg[..COMPAT_5_INIT_IN_SIZE]);
+    let min_len = match (init_in.major, init_in.minor) {
+        // Only the version is required to reject or renegotiate it.
+        (7, minor) if minor >= 6 => COMPAT_INIT_IN_SIZE,
+        _ => COMPAT_5_INIT_IN_SIZE,
+    };
+    if arg.len() < min_len {
+        return None;
+    }
+    let len = cmp::min(arg.len(), mem::size_of::<fuse_init_in>());
+    init_in.as_bytes_mut()[..len].copy_from_slice(&arg[..len]);
+    if init_in.flags & FUSE_INIT_EXT == 0 {
+        init_in.flags2 = 0;
+    }
+    Some(init_in)
+}
+
+// ==== Channel ====
+
+/// An additional channel of a FUSE session, created by `Session::clone_channel`.
+///
+/// The replies to the requests received from a channel are sent
+/// through the same channel.
+pub struct Channel {
+    session: Arc<SessionInner>,
+    conn: Arc<Connection>,
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Channel").finish()
+    }
+}
+
+impl AsRawFd for Channel {
+    fn as_raw_fd(&self) -> RawFd {
+        self.conn.as_raw_fd()
+    }
+}
+
+impl Channel {
+    /// Receive an incoming FUSE request from the kernel.
+    ///
+    /// See the documentation of `Session::next_request` for details.
+    pub fn next_request(&self) -> Result<Option<Request>, Error> {
+        self.session.next_request(&self.conn)
+    }
+
+    /// Receive an incoming FUSE request from the kernel without blocking.
+    ///
+    /// See the documentation of `Session::try_next_request` for details.
+    pub fn try_next_request(&self) -> Result<Poll<Option<Request>>, Error> {
+        try_next_request(self.next_request())
+    }
+
+    /// Set the device file descriptor of this channel into or out of the non-blocking mode.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.conn.set_nonblocking(nonblocking)
+    }
+}
+
+/// Return the size of the buffer that can receive any request from the kernel.
+///
+/// The payload of a request is bounded by `max_write`, but the kernel splits
+/// the data into pages, and `max_pages` pages may be larger than `max_write`
+/// when it is not a multiple of the page size.  The buffer covers both of them.
+fn min_buffer_size(init_out: &fuse_init_out) -> usize {
+    let mut max_payload = init_out.max_write as usize;
+    if init_out.flags & FUSE_MAX_PAGES != 0 {
+        max_payload = cmp::max(max_payload, init_out.max_pages as usize * pagesize());
+    }
+    BUFFER_HEADER_SIZE + max_payload
+}
+
+#[inline]
+fn try_next_request(res: Result<Option<Request>, Error>) -> Result<Poll<Option<Request>>, Error> {
+    match res {
+        Ok(req) => Ok(Poll::Ready(req)),
+        Err(ref err) if err.is_would_block() => Ok(Poll::Pending),
+        Err(err) => Err(err),
+    }
+}
+
+/// Return whether the request modifies the filesystem, and thus is rejected
+/// by `Session::set_reject_writes`.
+fn is_modifying(opcode: Option<fuse_opcode>) -> bool {
+    matches!(
+        opcode,
+        Some(fuse_opcode::FUSE_SETATTR)
+            | Some(fuse_opcode::FUSE_SYMLINK)
+            | Some(fuse_opcode::FUSE_MKNOD)
+            | Some(fuse_opcode::FUSE_MKDIR)
+            | Some(fuse_opcode::FUSE_UNLINK)
+            | Some(fuse_opcode::FUSE_RMDIR)
+            | Some(fuse_opcode::FUSE_RENAME)
+            | Some(fuse_opcode::FUSE_RENAME2)
+            | Some(fuse_opcode::FUSE_LINK)
+            | Some(fuse_opcode::FUSE_WRITE)
+            | Some(fuse_opcode::FUSE_SETXATTR)
+            | Some(fuse_opcode::FUSE_REMOVEXATTR)
+            | Some(fuse_opcode::FUSE_CREATE)
+            | Some(fuse_opcode::FUSE_TMPFILE)
+            | Some(fuse_opcode::FUSE_FALLOCATE)
+            | Some(fuse_opcode::FUSE_COPY_FILE_RANGE)
+    )
+}
+
+// ==== Remount ====
+
+/// A mounter that mounts the filesystem again after the connection is lost.
+///
+/// `Remount` keeps the mountpoint and the configuration, and produces a fresh
+/// `Session` on each call of `mount`, so that a supervising daemon can recover
+/// from an aborted connection without restarting the process and losing its
+/// in-memory state.  Whether the session should be mounted again is decided
+/// by `should_remount`, based on `Session::exit_reason`.
+///
+/// The old session, including the `Request`s and `Notifier`s created from it,
+/// must be dropped before mounting again, so that the stale mountpoint left
+/// by the aborted connection is unmounted.
+///
+/// # Rebuilding the kernel-visible state
+///
+/// The new connection shares nothing with the old one.  The kernel has dropped
+/// all of the inodes, the open files and the poll handles of the old connection
+/// without sending `forget` or `release`, and starts over from the root inode.
+/// The filesystem must therefore reset the lookup counts (e.g. by
+/// `InodeTable::forget_everything`), discard the file handles and the poll
+/// handles, and serve the new session from that state.  The file contents and
+/// the directory trees kept by the daemon can be reused as is.
+///
+/// # Example
+///
+/// ```no_run
+/// use polyfuse::{KernelConfig, Remount};
+///
+/// # fn main() -> Result<(), polyfuse::Error> {
+/// let mut remount = Remount::new("/tmp/mnt".into(), KernelConfig::default());
+/// loop {
+///     let session = remount.mount()?;
+///     while let Some(req) = session.next_request()? {
+///         // ...
+/// #       drop(req);
+///     }
+///     if !remount.should_remount(&session) {
+///         break;
+///     }
+///     // Drop the old session and reset the state before mounting again.
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The remount cycle can be tested by aborting the connection while the daemon
+/// is running, with `Session::abort` or from the shell via the FUSE control
+/// filesystem (`echo 1 > /sys/fs/fuse/connections/<dev>/abort`, where `<dev>`
+/// is the device number of the mount found in `/proc/self/mountinfo`).
+/// The accesses to the mountpoint fail with `ENOTCONN` until the filesystem
+/// is mounted again, and succeed afterwards.
+#[derive(Clone)]
+pub struct Remount {
+    mountpoint: PathBuf,
+    config: KernelConfig,
+    mount_count: usize,
+}
+
+impl fmt::Debug for Remount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Remount")
+            .field("mountpoint", &self.mountpoint)
+            .field("mount_count", &self.mount_count)
+            .finish()
+    }
+}
+
+impl Remount {
+    /// Create a mounter for the specified mountpoint and configuration.
+    pub fn new(mountpoint: PathBuf, config: KernelConfig) -> Self {
+        Self {
+            mountpoint,
+            config,
+            mount_count: 0,
+        }
+    }
+
+    /// Return the mountpoint of the filesystem.
+    pub fn mountpoint(&self) -> &Path {
+        &self.mountpoint
+    }
+
+    /// Return the number of successful mounts so far.
+    pub fn mount_count(&self) -> usize {
+        self.mount_count
+    }
+
+    /// Mount the filesystem and establish a new session.
+    ///
+    /// This is the same as `Session::mount` with the kept configuration.
+    pub fn mount(&mut self) -> Result<Session, Error> {
+        let session = Session::mount(self.mountpoint.clone(), self.config.clone())?;
+        self.mount_count += 1;
+        Ok(session)
+    }
+
+    /// Return whether the filesystem should be mounted again after `next_request`
+    /// on the session returned `None`.
+    ///
+    /// It returns `true` only if the connection has been aborted.  When the filesystem
+    /// has been unmounted, the daemon is expected to exit.
+    pub fn should_remount(&self, session: &Session) -> bool {
+        matches!(session.exit_reason(), Some(ExitReason::Aborted))
+    }
+}
+
+// ==== Request ====
+
+/// Context about an incoming FUSE request.
+pub struct Request {
+    session: Arc<SessionInner>,
+    conn: Arc<Connection>,
+    header: fuse_in_header,
+    arg: Vec<u8>,
+    span: tracing::Span,
+    replied: AtomicBool,
+    handling: bool,
+    inflight: Option<Arc<Inflight>>,
+}
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        if self.inflight.is_some() {
+            self.session.interrupts.remove(self.header.unique);
+            self.session.watchdog.remove(self.header.unique);
+        }
+        if self.handling {
+            self.session.handlers.exit();
+        }
+    }
+}
+
+impl Request {
+    /// Return the unique ID of the request.
+    #[inline]
+    pub fn unique(&self) -> u64 {
+        self.header.unique
+    }
+
+    /// Return the user ID of the calling process.
+    #[inline]
+    pub fn uid(&self) -> u32 {
+        self.header.uid
+    }
+
+    /// Return the group ID of the calling process.
+    #[inline]
+    pub fn gid(&self) -> u32 {
+        self.header.gid
+    }
+
+    /// Return the process ID of the calling process.
+    #[inline]
+    pub fn pid(&self) -> u32 {
+        self.header.pid
+    }
+
+    /// Return the supplementary group IDs of the calling process.
+    ///
+    /// The kernel attaches this list only to `mknod`, `mkdir`, `symlink` and
+    /// `create` requests, and only if `KernelConfig::create_supp_group` was
+    /// accepted during the initialization.  Currently, the list contains at most
+    /// one group, which owns the parent directory and that the caller is a member of.
+    pub fn supp_groups(&self) -> Option<Vec<u32>> {
+        decode_supp_groups(&self.arg[..], self.header.total_extlen)
+    }
+
+    /// Return the security contexts to be applied to the file created by this request.
+    ///
+    /// The kernel attaches the contexts only to `mknod`, `mkdir`, `symlink` and
+    /// `create` requests, and only if `KernelConfig::security_context` was accepted
+    /// during the initialization.  The returned list may be empty if no LSM
+    /// provides the context for the new file.
+    pub fn security_contexts(&self) -> Option<Vec<SecurityContext<'_>>> {
+        decode_security_contexts(&self.arg[..], self.header.total_extlen)
+    }
+
+    /// Return whether this request has been interrupted, or the session has been shut down.
+    ///
+    /// This method always returns `false` unless `Session::set_auto_interrupt` is enabled
+    /// or the session is shut down by `Session::shutdown`.  See their documentation for details.
+    pub fn interrupted(&self) -> bool {
+        self.inflight
+            .as_ref()
+            .is_some_and(|inflight| inflight.interrupted.load(Ordering::SeqCst))
+            || self.session.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Return the tracing span associated with this request.
+    ///
+    /// The span is created at the `DEBUG` level with the target `polyfuse::request`
+    /// and named `request`, which carries the following fields:
+    ///
+    /// * `unique` - the unique ID of the request.
+    /// * `opcode` - the raw opcode of the request.
+    /// * `nodeid` - the inode number of the request.
+    /// * `uid` and `pid` - the user ID and process ID of the caller.
+    ///
+    /// Each reply emits an event named `reply` within this span, with the fields
+    /// `error` (the error number, or zero on success) and `len` (the total length
+    /// of the reply message).  The handlers may enter this span so that their own
+    /// events are associated with the request, e.g. `let _enter = req.span().enter();`.
+    ///
+    /// Like the other events emitted by polyfuse, these are filtered by the target
+    /// and level in the subscriber, and cost almost nothing when disabled.
+    pub fn span(&self) -> &tracing::Span {
+        &self.span
+    }
+
+    /// Decode the argument of this request.
+    ///
+    /// The argument is limited to the length declared in the request header, which
+    /// `next_request` has validated against the received message.  If it is shorter
+    /// than the argument type of the operation, this method fails with an error
+    /// instead of reading beyond it.
+    ///
+    /// A malformed request is also reported through `tracing` at the warn level.
+    pub fn operation(&self) -> Result<Operation<'_, Data<'_>>, DecodeError> {
+        if self.session.exited() {
+            return Ok(Operation::unknown(self.header.opcode));
+        }
+
+        let (arg, data) = self.split_data();
+        Operation::decode(
+            &self.header,
+            arg,
+            Data { data },
+            self.session.init_out.flags,
+        )
+        .map_err(|err| {
+            tracing::warn!("{}", err);
+            err
+        })
+    }
+
+    /// Return the data payload that follows the argument of this request.
+    ///
+    /// Only `write` requests and `NOTIFY_REPLY` messages carry the payload,
+    /// which is the data to be written and the retrieved cache data, respectively.
+    /// For the other requests, the returned `Data` is empty.
+    ///
+    /// The returned value is the same as the one passed along with `Operation::Write`.
+    pub fn data(&self) -> Data<'_> {
+        let (_, data) = self.split_data();
+        Data { data }
+    }
+
+    fn split_data(&self) -> (&[u8], &[u8]) {
+        match fuse_opcode::try_from(self.header.opcode).ok() {
+            Some(fuse_opcode::FUSE_WRITE) | Some(fuse_opcode::FUSE_NOTIFY_REPLY)
+                if self.arg.len() >= mem::size_of::<fuse_write_in>() =>
+            {
+                self.arg.split_at(mem::size_of::<fuse_write_in>())
+            }
+            _ => (&self.arg[..], &[] as &[_]),
+        }
+    }
+
+    /// Send a reply to the kernel.
+    ///
+    /// The FUSE device processes a reply synchronously and never blocks the write,
+    /// even if its file descriptor is in the non-blocking mode.  Thus this method
+    /// can be called directly from within an async task without stalling the executor.
+    ///
+    /// Each request can be replied at most once.  The second and subsequent
+    /// attempts to reply, including by `reply_error`, fail with an error
+    /// without sending anything to the kernel.
+    ///
+    /// The chunks of `arg` are passed to `writev(2)` directly along with the reply
+    /// header, so the data borrowed by `arg` (e.g. a `&[u8]` or a `ReadOut`) is
+    /// never copied on the way to the kernel.
+    ///
+    /// The reply header, including the unique ID of this request, is generated
+    /// here, so that a payload encoded in advance by [`bytes::encode`] can be
+    /// cached and sent to the multiple requests as a raw byte slice.
+    ///
+    /// [`bytes::encode`]: crate::bytes::encode
+    pub fn reply<T>(&self, arg: T) -> io::Result<()>
+    where
+        T: Bytes,
+    {
+        if !self.begin_reply()? {
+            return Ok(());
+        }
+        self.send_reply(Reply::new(self.unique(), 0, arg))
+    }
+
+    /// Send an error reply to the kernel.
+    ///
+    /// See the documentation of `reply` for details.
+    pub fn reply_error(&self, code: i32) -> io::Result<()> {
+        if !self.begin_reply()? {
+            return Ok(());
+        }
+        self.send_reply(Reply::new(self.unique(), code, ()))
+    }
+
+    /// Send a reply to `getxattr` or `listxattr` request, following the size
+    /// requested by the kernel.
+    ///
+    /// `size` is the value of `op::Getxattr::size` or `op::Listxattr::size`, and
+    /// `value` is the whole attribute value or the list of attribute names.
+    ///
+    /// * When `size` is zero, the length of `value` is replied using `XattrOut`.
+    /// * When `value` fits in `size` bytes, `value` itself is replied.
+    /// * Otherwise, an `ERANGE` error is replied.
+    ///
+    /// If the length of `value` does not fit in `u32`, an `E2BIG` error is replied.
+    pub fn reply_xattr<T>(&self, size: u32, value: T) -> io::Result<()>
+    where
+        T: Bytes,
+    {
+        let len = match u32::try_from(value.size()) {
+            Ok(len) => len,
+            Err(..) => return self.reply_error(libc::E2BIG),
+        };
+        match size {
+            0 => {
+                let mut out = XattrOut::default();
+                XattrOut::size(&mut out, len);
+                self.reply(out)
+            }
+            size if len > size => self.reply_error(libc::ERANGE),
+            _ => self.reply(value),
+        }
+    }
+
+    /// Send an error reply to the kernel with the error number of the `nix` crate.
+    ///
+    /// The unknown error number, which is not an error, is replied as `EIO`.
+    /// This method is available only if the feature `nix` is enabled.
+    #[cfg(feature = "nix")]
+    pub fn reply_errno(&self, errno: nix::errno::Errno) -> io::Result<()> {
+        self.reply_error(errno_to_code(errno))
+    }
+
+    fn send_reply<T>(&self, reply: Reply<T>) -> io::Result<()>
+    where
+        T: Bytes,
+    {
+        self.span.in_scope(|| {
+            tracing::debug!(
+                target: "polyfuse::request",
+                error = -reply.header.error,
+                len = reply.header.len,
+                "reply"
+            );
+        });
+        write_bytes(&*self.conn, reply)
+    }
+
+    /// Mark this request as answered, and return whether the reply should be sent.
+    fn begin_reply(&self) -> io::Result<bool> {
+        if self.replied.swap(true, Ordering::SeqCst) {
+            tracing::error!(
+                "the request has already been replied: unique={}",
+                self.unique()
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the request has already been replied",
+            ));
+        }
+        match self.inflight {
+            Some(ref inflight) => {
+                self.session.watchdog.remove(self.unique());
+                Ok(!inflight.replied.swap(true, Ordering::SeqCst))
+            }
+            None => Ok(true),
+        }
+    }
+}
+
+#[cfg(feature = "nix")]
+fn errno_to_code(errno: nix::errno::Errno) -> i32 {
+    match errno as i32 {
+        code if code > 0 => code,
+        _ => libc::EIO,
+    }
+}
+
+/// Find the payload of the request extension with the specified type.
+///
+/// The extensions are placed at the end of the request message and their
+/// total length is provided by `fuse_in_header::total_extlen` in 8-byte units.
+fn find_extension(arg: &[u8], total_extlen: u16, typ: u32) -> Option<&[u8]> {
+    find_extension_by(arg, total_extlen, |ext_type| ext_type == typ).map(|(_, ext)| ext)
+}
+
+/// Find the first request extension whose type satisfies the predicate,
+/// and return its type and payload.
+fn find_extension_by(
+    arg: &[u8],
+    total_extlen: u16,
+    pred: impl Fn(u32) -> bool,
+) -> Option<(u32, &[u8])> {
+    let extlen = total_extlen as usize * 8;
+    let mut exts = &arg[arg.len().checked_sub(extlen)?..];
+
+    while exts.len() >= mem::size_of::<fuse_ext_header>() {
+        let size = read_u32(&exts[0..4]) as usize;
+        let ext_type = read_u32(&exts[4..8]);
+        if size < mem::size_of::<fuse_ext_header>() || size > exts.len() {
+            return None;
+        }
+        if pred(ext_type) {
+            return Some((ext_type, &exts[mem::size_of::<fuse_ext_header>()..size]));
+        }
+        exts = &exts[size..];
+    }
+
+    None
+}
+
+fn decode_supp_groups(arg: &[u8], total_extlen: u16) -> Option<Vec<u32>> {
+    let ext = find_extension(arg, total_extlen, FUSE_EXT_GROUPS)?;
+    if ext.len() < mem::size_of::<fuse_supp_groups>() {
+        return None;
+    }
+    let (nr_groups, groups) = ext.split_at(mem::size_of::<fuse_supp_groups>());
+    let nr_groups = read_u32(nr_groups) as usize;
+
+    // The extension may not be aligned, so the group IDs are copied one by one.
+    Some(
+        groups
+            .chunks_exact(mem::size_of::<u32>())
+            .take(nr_groups)
+            .map(read_u32)
+            .collect(),
+    )
+}
+
+/// A security context of the file created by a request.
+///
+/// See `Request::security_contexts` for details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecurityContext<'a> {
+    name: &'a OsStr,
+    value: &'a [u8],
+}
+
+impl<'a> SecurityContext<'a> {
+    /// Return the name of the extended attribute that stores this context,
+    /// e.g. `security.selinux`.
+    pub fn name(&self) -> &'a OsStr {
+        self.name
+    }
+
+    /// Return the value of this context.
+    pub fn value(&self) -> &'a [u8] {
+        self.value
+    }
+}
+
+fn decode_security_contexts(arg: &[u8], total_extlen: u16) -> Option<Vec<SecurityContext<'_>>> {
+    // `fuse_secctx_header` doubles as the extension header, whose type is the number
+    // of the following contexts.  Each context is aligned to 8 bytes.
+    let (nr_secctx, mut ext) =
+        find_extension_by(arg, total_extlen, |typ| typ <= FUSE_MAX_NR_SECCTX)?;
+
+    let mut contexts = Vec::with_capacity(nr_secctx as usize);
+    for _ in 0..nr_secctx {
+        if ext.len() < mem::size_of::<fuse_secctx>() {
+            return None;
+        }
+        let size = read_u32(&ext[0..4]) as usize;
+        let rest = &ext[mem::size_of::<fuse_secctx>()..];
+        let name_len = rest.iter().position(|&b| b == b'\0')?;
+        let value = rest.get(name_len + 1..name_len + 1 + size)?;
+        contexts.push(SecurityContext {
+            name: OsStr::from_bytes(&rest[..name_len]),
+            value,
+        });
+
+        let len = mem::size_of::<fuse_secctx>() + name_len + 1 + size;
+        ext = ext.get((len + 7) & !7..).unwrap_or(&[]);
+    }
+    Some(contexts)
+}
+
+#[inline]
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_ne_bytes(bytes.try_into().expect("invalid slice length"))
+}
+
+/// The remaining part of request message.
+///
+/// The payload is borrowed from the buffer that the request has been received
+/// into, and no copy is made until it is read out.  Besides the `Read` and
+/// `BufRead` implementations, the payload can be passed directly to the
+/// vectored or positioned writes by `as_bytes` or `io_slices`.
+pub struct Data<'op> {
+    data: &'op [u8],
+}
+
+impl<'op> Data<'op> {
+    /// Return the remaining payload, without copying it.
+    #[inline]
+    pub fn as_bytes(&self) -> &'op [u8] {
+        self.data
+    }
+
+    /// Return the remaining payload split into the slices of the specified size,
+    /// e.g. the page size, for passing them to `writev(2)`.
+    ///
+    /// The last slice may be shorter than `size`.
+    ///
+    /// # Panics
+    /// It causes a panic if `size` is 0.
+    pub fn io_slices(&self, size: usize) -> Vec<IoSlice<'op>> {
+        self.data.chunks(size).map(IoSlice::new).collect()
+    }
+}
+
+impl fmt::Debug for Data<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Data").finish()
     }
 }
 
@@ -645,12 +2590,59 @@ impl<'op> BufRead for Data<'op> {
 
 // ==== Notifier ====
 
+/// The sender of notifications to the kernel.
+///
+/// A `Notifier` is obtained by `Session::notifier` and can be cloned and moved
+/// to other threads, independently of the loop receiving the requests.
+/// Each method constructs the corresponding notification message and sends it
+/// through the connection of the session:
+///
+/// * `inval_inode`, `inval_entry` and `delete` invalidate the kernel caches.
+/// * `store` and `retrieve` push and pull the data in the page cache.
+/// * `poll_wakeup` wakes up the waiters of a `poll` request.
+///
+/// # Example
+///
+/// ```no_run
+/// use polyfuse::{KernelConfig, Session};
+///
+/// # fn main() -> Result<(), polyfuse::Error> {
+/// let session = Session::mount("/mnt".into(), KernelConfig::default())?;
+/// let notifier = session.notifier();
+///
+/// std::thread::spawn(move || -> std::io::Result<()> {
+///     // Replace the cached content of the inode and its attributes.
+///     notifier.store(2, 0, &b"Hello, world!\n"[..])?;
+///     notifier.inval_inode(2, 0, 0)?;
+///
+///     // Drop the cached entry `foo` in the root directory.
+///     notifier.inval_entry(1, "foo")?;
+///
+///     // Read back the cached content.
+///     let data = notifier.retrieve(2, 0, 4096)?.wait()?;
+///     println!("{:?}", data);
+///     Ok(())
+/// });
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Clone)]
 pub struct Notifier {
     session: Arc<SessionInner>,
 }
 
 impl Notifier {
+    /// Create a batch of the notifications.
+    ///
+    /// See the documentation of `NotifyBatch` for details.
+    pub fn batch(&self) -> NotifyBatch {
+        NotifyBatch {
+            session: self.session.clone(),
+            buf: vec![],
+            count: 0,
+        }
+    }
+
     /// Notify the cache invalidation about an inode to the kernel.
     pub fn inval_inode(&self, ino: u64, off: i64, len: i64) -> io::Result<()> {
         let total_len = u32::try_from(
@@ -659,7 +2651,7 @@ impl Notifier {
         .unwrap();
 
         return write_bytes(
-            &self.session.conn,
+            &*self.session.conn,
             InvalInode {
                 header: fuse_out_header {
                     len: total_len,
@@ -706,7 +2698,7 @@ impl Notifier {
         .unwrap();
 
         return write_bytes(
-            &self.session.conn,
+            &*self.session.conn,
             InvalEntry {
                 header: fuse_out_header {
                     len: total_len,
@@ -716,7 +2708,7 @@ impl Notifier {
                 arg: fuse_notify_inval_entry_out {
                     parent,
                     namelen,
-                    padding: 0,
+                    flags: 0,
                 },
                 name,
             },
@@ -772,7 +2764,7 @@ impl Notifier {
         .expect("payload is too long");
 
         return write_bytes(
-            &self.session.conn,
+            &*self.session.conn,
             Delete {
                 header: fuse_out_header {
                     len: total_len,
@@ -819,6 +2811,9 @@ impl Notifier {
     }
 
     /// Push the data in an inode for updating the kernel cache.
+    ///
+    /// The data may consist of multiple segments, e.g. `&[IoSlice<'_>]`,
+    /// which are gathered into a single notification without copying.
     pub fn store<T>(&self, ino: u64, offset: u64, data: T) -> io::Result<()>
     where
         T: Bytes,
@@ -833,7 +2828,7 @@ impl Notifier {
         .expect("payload is too long");
 
         return write_bytes(
-            &self.session.conn,
+            &*self.session.conn,
             Store {
                 header: fuse_out_header {
                     len: total_len,
@@ -879,7 +2874,15 @@ impl Notifier {
     }
 
     /// Retrieve data in an inode from the kernel cache.
-    pub fn retrieve(&self, ino: u64, offset: u64, size: u32) -> io::Result<u64> {
+    ///
+    /// The returned `Retrieve` resolves to the cache data once the kernel
+    /// replies to this notification.  Note that the reply message is delivered
+    /// through `Session::next_request`, and hence the session must keep
+    /// receiving the requests until the retrieved data is obtained.
+    ///
+    /// The retrieved data may be shorter than `size` if the requested range
+    /// is not entirely in the kernel cache.
+    pub fn retrieve(&self, ino: u64, offset: u64, size: u32) -> io::Result<Retrieve> {
         let total_len = u32::try_from(
             mem::size_of::<fuse_out_header>() + mem::size_of::<fuse_notify_retrieve_out>(),
         )
@@ -888,9 +2891,11 @@ impl Notifier {
         // FIXME: choose appropriate memory ordering.
         let notify_unique = self.session.notify_unique.fetch_add(1, Ordering::SeqCst);
 
-        write_bytes(
-            &self.session.conn,
-            Retrieve {
+        let retrieve = self.session.retrieves.register(notify_unique);
+
+        if let Err(err) = write_bytes(
+            &*self.session.conn,
+            NotifyRetrieve {
                 header: fuse_out_header {
                     len: total_len,
                     error: fuse_notify_code::FUSE_NOTIFY_RETRIEVE as i32,
@@ -904,15 +2909,19 @@ impl Notifier {
                     padding: 0,
                 },
             },
-        )?;
+        ) {
+            // The kernel never replies to the notification that failed to send.
+            self.session.retrieves.remove(notify_unique);
+            return Err(err);
+        }
 
-        return Ok(notify_unique);
+        return Ok(retrieve);
 
-        struct Retrieve {
+        struct NotifyRetrieve {
             header: fuse_out_header,
             arg: fuse_notify_retrieve_out,
         }
-        impl Bytes for Retrieve {
+        impl Bytes for NotifyRetrieve {
             fn size(&self) -> usize {
                 self.header.len as usize
             }
@@ -929,6 +2938,9 @@ impl Notifier {
     }
 
     /// Send I/O readiness to the kernel.
+    ///
+    /// `kh` must be the poll handle returned by `op::Poll::kh` of the `poll`
+    /// request that asked for the notification.
     pub fn poll_wakeup(&self, kh: u64) -> io::Result<()> {
         let total_len = u32::try_from(
             mem::size_of::<fuse_out_header>() + mem::size_of::<fuse_notify_poll_wakeup_out>(),
@@ -936,7 +2948,7 @@ impl Notifier {
         .unwrap();
 
         return write_bytes(
-            &self.session.conn,
+            &*self.session.conn,
             PollWakeup {
                 header: fuse_out_header {
                     len: total_len,
@@ -968,6 +2980,613 @@ impl Notifier {
     }
 }
 
+// ==== NotifyBatch ====
+
+/// A batch of the notifications to be sent to the kernel at once.
+///
+/// The notifications are encoded into a single buffer when they are added,
+/// and sent in order by `flush`.  The FUSE device accepts exactly one message
+/// per `write(2)`, so the messages are not coalesced into a single syscall.
+/// Instead, the batch avoids the allocation and encoding overhead of each call
+/// to `Notifier`, and the buffer is reused for the subsequent notifications.
+///
+/// If a notification fails to be sent, `flush` returns the error and the
+/// remaining ones are kept in the batch.  Like `BufWriter`, the unsent
+/// notifications are flushed when the batch is dropped, ignoring any errors.
+pub struct NotifyBatch {
+    session: Arc<SessionInner>,
+    buf: Vec<u8>,
+    count: usize,
+}
+
+impl fmt::Debug for NotifyBatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotifyBatch")
+            .field("len", &self.count)
+            .finish()
+    }
+}
+
+impl Drop for NotifyBatch {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl NotifyBatch {
+    /// Add a cache invalidation about an inode.
+    ///
+    /// See also `Notifier::inval_inode`.
+    pub fn inval_inode(&mut self, ino: u64, off: i64, len: i64) -> &mut Self {
+        let arg = fuse_notify_inval_inode_out { ino, off, len };
+        self.push(
+            fuse_notify_code::FUSE_NOTIFY_INVAL_INODE,
+            arg.as_bytes(),
+            None,
+        )
+    }
+
+    /// Add an invalidation about a directory entry.
+    ///
+    /// See also `Notifier::inval_entry`.
+    pub fn inval_entry<T>(&mut self, parent: u64, name: T) -> &mut Self
+    where
+        T: AsRef<OsStr>,
+    {
+        let name = name.as_ref().as_bytes();
+        let arg = fuse_notify_inval_entry_out {
+            parent,
+            namelen: u32::try_from(name.len()).expect("provided name is too long"),
+            flags: 0,
+        };
+        self.push(
+            fuse_notify_code::FUSE_NOTIFY_INVAL_ENTRY,
+            arg.as_bytes(),
+            Some(name),
+        )
+    }
+
+    /// Add an invalidation about a deleted directory entry.
+    ///
+    /// See also `Notifier::delete`.
+    pub fn delete<T>(&mut self, parent: u64, child: u64, name: T) -> &mut Self
+    where
+        T: AsRef<OsStr>,
+    {
+        let name = name.as_ref().as_bytes();
+        let arg = fuse_notify_delete_out {
+            parent,
+            child,
+            namelen: u32::try_from(name.len()).expect("provided name is too long"),
+            padding: 0,
+        };
+        self.push(
+            fuse_notify_code::FUSE_NOTIFY_DELETE,
+            arg.as_bytes(),
+            Some(name),
+        )
+    }
+
+    /// Add a wakeup of the waiters of a `poll` request.
+    ///
+    /// See also `Notifier::poll_wakeup`.
+    pub fn poll_wakeup(&mut self, kh: u64) -> &mut Self {
+        let arg = fuse_notify_poll_wakeup_out { kh };
+        self.push(fuse_notify_code::FUSE_NOTIFY_POLL, arg.as_bytes(), None)
+    }
+
+    /// Return the number of the notifications not sent yet.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Return whether all of the notifications have been sent.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Send the notifications in the batch to the kernel, in the order they were added.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut sent = 0;
+        let res = loop {
+            let msg = &self.buf[sent..];
+            if msg.is_empty() {
+                break Ok(());
+            }
+            let len = read_u32(&msg[0..4]) as usize;
+            if let Err(err) = write_bytes(&*self.session.conn, &msg[..len]) {
+                break Err(err);
+            }
+            sent += len;
+            self.count -= 1;
+        };
+        self.buf.drain(..sent);
+        res
+    }
+
+    fn push(&mut self, code: fuse_notify_code, arg: &[u8], name: Option<&[u8]>) -> &mut Self {
+        let name_len = name.map_or(0, |name| name.len() + 1);
+        let header = fuse_out_header {
+            len: u32::try_from(mem::size_of::<fuse_out_header>() + arg.len() + name_len)
+                .expect("payload is too long"),
+            error: code as i32,
+            unique: 0,
+        };
+        self.buf.extend_from_slice(header.as_bytes());
+        self.buf.extend_from_slice(arg);
+        if let Some(name) = name {
+            self.buf.extend_from_slice(name);
+            self.buf.push(b'\0'); // null terminator
+        }
+        self.count += 1;
+        self
+    }
+}
+
+// ==== Retrieve ====
+
+/// The cache data to be retrieved from the kernel.
+///
+/// This type is returned from `Notifier::retrieve` and resolves to the retrieved
+/// data when the session receives the corresponding `NOTIFY_REPLY` message.
+/// It can be either awaited as a `Future` or waited synchronously by `wait`.
+///
+/// It resolves to an error if the reply message is malformed, or if the
+/// connection is closed before the reply is received.
+#[must_use]
+pub struct Retrieve {
+    unique: u64,
+    slot: Arc<RetrieveSlot>,
+}
+
+impl fmt::Debug for Retrieve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Retrieve")
+            .field("unique", &self.unique)
+            .finish()
+    }
+}
+
+impl Retrieve {
+    /// Return the unique ID of the notification message.
+    #[inline]
+    pub fn unique(&self) -> u64 {
+        self.unique
+    }
+
+    /// Block the current thread until the retrieved data is available.
+    pub fn wait(self) -> io::Result<Vec<u8>> {
+        let mut state = self.slot.state.lock().unwrap();
+        loop {
+            if let Some(data) = state.data.take() {
+                return data;
+            }
+            state = self.slot.cond.wait(state).unwrap();
+        }
+    }
+}
+
+impl Future for Retrieve {
+    type Output = io::Result<Vec<u8>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.slot.state.lock().unwrap();
+        match state.data.take() {
+            Some(data) => Poll::Ready(data),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct RetrieveSlot {
+    state: Mutex<RetrieveState>,
+    cond: Condvar,
+}
+
+impl RetrieveSlot {
+    fn resolve(&self, data: io::Result<Vec<u8>>) {
+        let mut state = self.state.lock().unwrap();
+        state.data = Some(data);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.cond.notify_all();
+    }
+}
+
+#[derive(Default)]
+struct RetrieveState {
+    data: Option<io::Result<Vec<u8>>>,
+    waker: Option<Waker>,
+}
+
+/// The table of pending `Retrieve`s, keyed by the unique ID of notifications.
+#[derive(Default)]
+struct Retrieves {
+    pending: Mutex<HashMap<u64, Arc<RetrieveSlot>>>,
+}
+
+impl Retrieves {
+    fn register(&self, unique: u64) -> Retrieve {
+        let slot = Arc::new(RetrieveSlot::default());
+        self.pending.lock().unwrap().insert(unique, slot.clone());
+        Retrieve { unique, slot }
+    }
+
+    fn remove(&self, unique: u64) {
+        self.pending.lock().unwrap().remove(&unique);
+    }
+
+    /// Complete the pending `Retrieve` with the argument of a `NOTIFY_REPLY` message.
+    ///
+    /// The return value indicates whether the message is consumed.
+    fn complete(&self, unique: u64, arg: &[u8]) -> bool {
+        let slot = match self.pending.lock().unwrap().remove(&unique) {
+            Some(slot) => slot,
+            None => return false,
+        };
+
+        let mut decoder = Decoder::new(arg);
+        let data = match decoder.fetch::<fuse_notify_retrieve_in>() {
+            Ok(retrieve_in) => {
+                let data = decoder.remaining();
+                Ok(data[..cmp::min(retrieve_in.size as usize, data.len())].to_vec())
+            }
+            Err(err) => {
+                tracing::error!("failed to decode fuse_notify_retrieve_in: {:?}", err);
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    DecodeError::new(err),
+                ))
+            }
+        };
+        slot.resolve(data);
+
+        true
+    }
+
+    /// Resolve all pending `Retrieve`s with an error.
+    fn abort_all(&self) {
+        for (_, slot) in self.pending.lock().unwrap().drain() {
+            slot.resolve(Err(io::Error::from_raw_os_error(libc::ENODEV)));
+        }
+    }
+}
+
+// ==== interrupts ====
+
+/// The state of a request that can be answered by either the filesystem or an interrupt.
+struct Inflight {
+    conn: Arc<Connection>,
+    replied: AtomicBool,
+    interrupted: AtomicBool,
+}
+
+/// The maximum number of the interrupts kept until their target requests are received.
+const MAX_PENDING_INTERRUPTS: usize = 64;
+
+/// The table of in-flight requests, keyed by their unique ID.
+#[derive(Default)]
+struct Interrupts {
+    enabled: AtomicBool,
+    state: Mutex<InterruptsState>,
+}
+
+#[derive(Default)]
+struct InterruptsState {
+    inflight: HashMap<u64, Arc<Inflight>>,
+    // The targets of the interrupts received before the target requests themselves.
+    pending: VecDeque<u64>,
+}
+
+impl Interrupts {
+    #[inline]
+    fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst)
+    }
+
+    fn register(&self, conn: &Arc<Connection>, unique: u64) -> Arc<Inflight> {
+        let inflight = Arc::new(Inflight {
+            conn: conn.clone(),
+            replied: AtomicBool::new(false),
+            interrupted: AtomicBool::new(false),
+        });
+
+        let mut state = self.state.lock().unwrap();
+        state.inflight.insert(unique, inflight.clone());
+        let pos = state.pending.iter().position(|&target| target == unique);
+        if let Some(pos) = pos {
+            state.pending.remove(pos);
+            drop(state);
+            tracing::debug!(unique, "the request has already been interrupted");
+            inflight.interrupted.store(true, Ordering::SeqCst);
+            inflight.replied.store(true, Ordering::SeqCst);
+            if let Err(err) = write_bytes(&**conn, Reply::new(unique, libc::ECANCELED, ())) {
+                tracing::debug!("failed to reply to the interrupted request: {}", err);
+            }
+        }
+
+        inflight
+    }
+
+    fn remove(&self, unique: u64) {
+        self.state.lock().unwrap().inflight.remove(&unique);
+    }
+
+    /// Mark all of the in-flight requests as interrupted, and answer them with
+    /// the specified error.
+    fn cancel_all(&self, code: Option<i32>) {
+        let inflight: Vec<_> = self
+            .state
+            .lock()
+            .unwrap()
+            .inflight
+            .iter()
+            .map(|(&unique, inflight)| (unique, inflight.clone()))
+            .collect();
+        for (unique, inflight) in inflight {
+            inflight.interrupted.store(true, Ordering::SeqCst);
+            let code = match code {
+                Some(code) => code,
+                None => continue,
+            };
+            if inflight.replied.swap(true, Ordering::SeqCst) {
+                continue;
+            }
+            if let Err(err) = write_bytes(&*inflight.conn, Reply::new(unique, code, ())) {
+                tracing::debug!("failed to reply to the cancelled request: {}", err);
+            }
+        }
+    }
+
+    /// Answer the target request of an `interrupt` request with `ECANCELED`.
+    fn interrupt(&self, arg: &[u8]) {
+        let target = match Decoder::new(arg).fetch::<fuse_interrupt_in>() {
+            Ok(arg) => arg.unique,
+            Err(err) => {
+                tracing::error!("failed to decode fuse_interrupt_in: {:?}", err);
+                return;
+            }
+        };
+
+        let inflight = {
+            let mut state = self.state.lock().unwrap();
+            match state.inflight.get(&target) {
+                Some(inflight) => inflight.clone(),
+                None => {
+                    // The target request has not been registered yet, e.g. it is being
+                    // received on another channel, so the interrupt is applied when it is.
+                    // The interrupts for the untracked or finished requests are left
+                    // until evicted, since they never match the later requests.
+                    if state.pending.len() >= MAX_PENDING_INTERRUPTS {
+                        state.pending.pop_front();
+                    }
+                    state.pending.push_back(target);
+                    return;
+                }
+            }
+        };
+
+        inflight.interrupted.store(true, Ordering::SeqCst);
+        if inflight.replied.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let res = write_bytes(&*inflight.conn, Reply::new(target, libc::ECANCELED, ()));
+        if let Err(err) = res {
+            // The target request may have already been finished by the kernel.
+            tracing::debug!("failed to reply to the interrupted request: {}", err);
+        }
+    }
+}
+
+// ==== handlers ====
+
+/// The number of the received requests whose handlers have not finished yet.
+#[derive(Default)]
+struct Handlers {
+    count: AtomicUsize,
+    waiters: AtomicUsize,
+    lock: Mutex<()>,
+    drained: Condvar,
+}
+
+impl Handlers {
+    fn enter(&self) {
+        self.count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn exit(&self) {
+        // The lock is taken only when someone is waiting for the handlers,
+        // so that the channels receiving requests in parallel do not contend on it.
+        if self.count.fetch_sub(1, Ordering::SeqCst) == 1 && self.waiters.load(Ordering::SeqCst) > 0
+        {
+            let _guard = self.lock.lock().unwrap();
+            self.drained.notify_all();
+        }
+    }
+
+    /// Wait until all of the handlers finish.
+    ///
+    /// Return `false` if the timeout elapses before that.
+    fn wait_all(&self, timeout: Option<Duration>) -> bool {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let mut guard = self.lock.lock().unwrap();
+        let mut drained = true;
+        while self.count.load(Ordering::SeqCst) > 0 {
+            guard = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        drained = false;
+                        break;
+                    }
+                    self.drained.wait_timeout(guard, deadline - now).unwrap().0
+                }
+                None => self.drained.wait(guard).unwrap(),
+            };
+        }
+        drop(guard);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        drained
+    }
+}
+
+// ==== watchdog ====
+
+/// The deadlines of the requests being processed by the filesystem.
+#[derive(Default)]
+struct Watchdog {
+    enabled: AtomicBool,
+    shared: Arc<WatchdogShared>,
+}
+
+#[derive(Default)]
+struct WatchdogShared {
+    state: Mutex<WatchdogState>,
+    cond: Condvar,
+}
+
+#[derive(Default)]
+struct WatchdogState {
+    timeout: Option<Duration>,
+    error: Option<i32>,
+    running: bool,
+    shutdown: bool,
+    pending: HashMap<u64, Pending>,
+}
+
+struct Pending {
+    deadline: Instant,
+    opcode: u32,
+    inflight: Arc<Inflight>,
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.shutdown = true;
+        state.pending.clear();
+        self.shared.cond.notify_all();
+    }
+}
+
+impl Watchdog {
+    #[inline]
+    fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.timeout = timeout;
+        self.enabled.store(timeout.is_some(), Ordering::SeqCst);
+        if timeout.is_none() {
+            state.pending.clear();
+        } else if !state.running {
+            // The thread only holds the shared state, so it never keeps the session alive.
+            let shared = self.shared.clone();
+            thread::Builder::new()
+                .name("polyfuse-watchdog".into())
+                .spawn(move || shared.run())
+                .expect("failed to spawn the watchdog thread");
+            state.running = true;
+        }
+        self.shared.cond.notify_all();
+    }
+
+    fn set_error(&self, code: Option<i32>) {
+        self.shared.state.lock().unwrap().error = code;
+    }
+
+    fn register(&self, header: &fuse_in_header, inflight: &Arc<Inflight>) {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(timeout) = state.timeout {
+            state.pending.insert(
+                header.unique,
+                Pending {
+                    deadline: Instant::now() + timeout,
+                    opcode: header.opcode,
+                    inflight: inflight.clone(),
+                },
+            );
+            self.shared.cond.notify_all();
+        }
+    }
+
+    fn remove(&self, unique: u64) {
+        if self.enabled() {
+            self.shared.state.lock().unwrap().pending.remove(&unique);
+        }
+    }
+}
+
+impl WatchdogShared {
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        while !state.shutdown {
+            let now = Instant::now();
+            let expired: Vec<u64> = state
+                .pending
+                .iter()
+                .filter(|(_, pending)| pending.deadline <= now)
+                .map(|(&unique, _)| unique)
+                .collect();
+            if !expired.is_empty() {
+                let error = state.error;
+                let expired: Vec<_> = expired
+                    .into_iter()
+                    .filter_map(|unique| Some((unique, state.pending.remove(&unique)?)))
+                    .collect();
+                drop(state);
+                for (unique, pending) in expired {
+                    self.expire(unique, pending, error);
+                }
+                state = self.state.lock().unwrap();
+                continue;
+            }
+
+            let next_deadline = state.pending.values().map(|pending| pending.deadline).min();
+            state = match next_deadline {
+                Some(deadline) => {
+                    self.cond
+                        .wait_timeout(state, deadline.saturating_duration_since(now))
+                        .unwrap()
+                        .0
+                }
+                None => self.cond.wait(state).unwrap(),
+            };
+        }
+    }
+
+    fn expire(&self, unique: u64, pending: Pending, error: Option<i32>) {
+        tracing::warn!(
+            "the request has not been replied within the deadline: unique={}, opcode={}",
+            unique,
+            pending.opcode
+        );
+
+        if let Some(code) = error {
+            if pending.inflight.replied.swap(true, Ordering::SeqCst) {
+                return;
+            }
+            let res = write_bytes(&*pending.inflight.conn, Reply::new(unique, code, ()));
+            if let Err(err) = res {
+                tracing::debug!("failed to reply to the expired request: {}", err);
+            }
+        }
+    }
+}
+
 // ==== utils ====
 
 struct Reply<T> {
@@ -1013,6 +3632,10 @@ where
     }
 }
 
+/// Write the chunks provided by `bytes` to the writer.
+///
+/// If the chunks do not match the `size` and `count` reported by `bytes`,
+/// nothing is written and an error with `io::ErrorKind::InvalidData` is returned.
 #[inline]
 fn write_bytes<W, T>(mut writer: W, bytes: T) -> io::Result<()>
 where
@@ -1022,19 +3645,19 @@ where
     let size = bytes.size();
     let count = bytes.count();
 
-    let written;
-
     macro_rules! small_write {
         ($n:expr) => {{
             let mut vec: [MaybeUninit<IoSlice<'_>>; $n] =
                 unsafe { MaybeUninit::uninit().assume_init() };
-            bytes.fill_bytes(&mut FillWriteBytes {
-                vec: &mut vec[..],
-                offset: 0,
-            });
+            let mut fill = FillWriteBytes::new(&mut vec[..]);
+            bytes.fill_bytes(&mut fill);
+            fill.validate(size, count)?;
             let vec = unsafe { slice_assume_init_ref(&vec[..]) };
 
-            written = writer.write_vectored(vec)?;
+            let written = writer.write_vectored(vec)?;
+            if written < size {
+                write_remaining(&mut writer, vec, written)?;
+            }
         }};
     }
 
@@ -1055,36 +3678,85 @@ where
                     vec.as_mut_ptr().cast(), //
                     count,
                 );
-                bytes.fill_bytes(&mut FillWriteBytes {
-                    vec: dst,
-                    offset: 0,
-                });
+                let mut fill = FillWriteBytes::new(dst);
+                bytes.fill_bytes(&mut fill);
+                fill.validate(size, count)?;
                 vec.set_len(count);
             }
 
-            written = writer.write_vectored(&*vec)?;
+            let written = writer.write_vectored(&*vec)?;
+            if written < size {
+                write_remaining(&mut writer, &vec, written)?;
+            }
         }
     }
 
-    if written < size {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "written data is too short",
-        ));
-    }
+    Ok(())
+}
 
+/// Write the rest of the chunks partially written by `write_vectored`.
+///
+/// The FUSE device always consumes a whole message by a single `write`,
+/// but the other writers may accept it incrementally.
+fn write_remaining<W>(mut writer: W, chunks: &[IoSlice<'_>], mut written: usize) -> io::Result<()>
+where
+    W: io::Write,
+{
+    for chunk in chunks {
+        if written >= chunk.len() {
+            written -= chunk.len();
+            continue;
+        }
+        writer.write_all(&chunk[written..])?;
+        written = 0;
+    }
     Ok(())
 }
 
 struct FillWriteBytes<'a, 'vec> {
     vec: &'vec mut [MaybeUninit<IoSlice<'a>>],
     offset: usize,
+    len: usize,
+}
+
+impl<'a, 'vec> FillWriteBytes<'a, 'vec> {
+    fn new(vec: &'vec mut [MaybeUninit<IoSlice<'a>>]) -> Self {
+        Self {
+            vec,
+            offset: 0,
+            len: 0,
+        }
+    }
+
+    /// Check that the provided chunks match the `size` and `count` reported by `Bytes`.
+    ///
+    /// This also guarantees that all elements of `vec` are initialized.
+    fn validate(&self, size: usize, count: usize) -> io::Result<()> {
+        if self.offset != count || self.len != size {
+            tracing::error!(
+                "mismatched Bytes: size = {}, count = {}, but provided {} bytes in {} chunks",
+                size,
+                count,
+                self.len,
+                self.offset
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the provided chunks do not match the reported size or count",
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl<'a, 'vec> FillBytes<'a> for FillWriteBytes<'a, 'vec> {
     fn put(&mut self, chunk: &'a [u8]) {
-        self.vec[self.offset] = MaybeUninit::new(IoSlice::new(chunk));
+        // The excess chunks are counted but never written, and are reported by `validate`.
+        if let Some(slot) = self.vec.get_mut(self.offset) {
+            *slot = MaybeUninit::new(IoSlice::new(chunk));
+        }
         self.offset += 1;
+        self.len += chunk.len();
     }
 }
 
@@ -1114,8 +3786,10 @@ const fn default_init_out() -> fuse_init_out {
         max_write: DEFAULT_MAX_WRITE,
         time_gran: 1,
         max_pages: 0,
-        padding: 0,
-        unused: [0; 8],
+        map_alignment: 0,
+        flags2: 0,
+        max_stack_depth: 0,
+        unused: [0; 6],
     }
 }
 
@@ -1124,6 +3798,70 @@ mod tests {
     use super::*;
     use std::mem;
 
+    /// Create a session connected to a socket that plays the role of the kernel.
+    fn session_pair() -> (Connection, Session) {
+        let (kernel, conn) = conn_pair();
+        (kernel, Session::new(conn, default_init_out(), 0))
+    }
+
+    /// Make the blocking reads from the connection fail after the timeout.
+    fn set_read_timeout(conn: &Connection, timeout: Duration) {
+        let tv = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        let res = unsafe {
+            libc::setsockopt(
+                conn.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &tv as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(res, 0, "setsockopt: {}", io::Error::last_os_error());
+    }
+
+    /// Create a pair of the connections, the kernel side and the session side.
+    fn conn_pair() -> (Connection, Connection) {
+        let mut fds = [0; 2];
+        let res =
+            unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) };
+        assert_eq!(res, 0, "socketpair: {}", io::Error::last_os_error());
+        (
+            Connection::from_raw_fd(fds[1]),
+            Connection::from_raw_fd(fds[0]),
+        )
+    }
+
+    /// Send a `getattr` request of the root inode from the kernel side.
+    fn send_getattr(mut kernel: &Connection, unique: u64) {
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_getattr_in>()) as u32,
+            opcode: fuse_opcode::FUSE_GETATTR as u32,
+            unique,
+            nodeid: 1,
+            ..Default::default()
+        };
+        let written = kernel
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(fuse_getattr_in::default().as_bytes()),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+    }
+
+    /// Receive an error reply on the kernel side, and return its unique ID and error number.
+    fn recv_reply(mut kernel: &Connection) -> (u64, i32) {
+        let mut buf = [0u8; 64];
+        let len = kernel.read(&mut buf[..]).unwrap();
+        assert_eq!(len, mem::size_of::<fuse_out_header>());
+        let error = i32::from_ne_bytes(buf[4..8].try_into().unwrap());
+        let unique = u64::from_ne_bytes(buf[8..16].try_into().unwrap());
+        (unique, -error)
+    }
+
     #[test]
     fn init_default() {
         let input_len = mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_init_in>();
@@ -1135,6 +3873,7 @@ mod tests {
             uid: 100,
             gid: 100,
             pid: 12,
+            total_extlen: 0,
             padding: 0,
         };
         let init_in = fuse_init_in {
@@ -1145,6 +3884,7 @@ mod tests {
                 | FUSE_MAX_PAGES
                 | FUSE_NO_OPEN_SUPPORT
                 | FUSE_NO_OPENDIR_SUPPORT,
+            ..Default::default()
         };
 
         let mut input = Vec::with_capacity(input_len);
@@ -1155,7 +3895,8 @@ mod tests {
         let mut output = Vec::<u8>::new();
 
         let mut init_out = default_init_out();
-        init_session(&mut init_out, &input[..], &mut output).expect("initialization failed");
+        let readonly_flags = init_session(&mut init_out, &input[..], &mut output, |_, _| Ok(()))
+            .expect("initialization failed");
 
         let expected_max_pages = (DEFAULT_MAX_WRITE / (pagesize() as u32)) as u16;
 
@@ -1167,8 +3908,10 @@ mod tests {
         assert_eq!(init_out.max_write, DEFAULT_MAX_WRITE);
         assert_eq!(init_out.max_pages, expected_max_pages);
         assert_eq!(init_out.time_gran, 1);
-        assert!(init_out.flags & FUSE_NO_OPEN_SUPPORT != 0);
-        assert!(init_out.flags & FUSE_NO_OPENDIR_SUPPORT != 0);
+        assert!(readonly_flags & FUSE_NO_OPEN_SUPPORT != 0);
+        assert!(readonly_flags & FUSE_NO_OPENDIR_SUPPORT != 0);
+        assert!(init_out.flags & FUSE_NO_OPEN_SUPPORT == 0);
+        assert!(init_out.flags & FUSE_NO_OPENDIR_SUPPORT == 0);
 
         let output_len = mem::size_of::<fuse_out_header>() + mem::size_of::<fuse_init_out>();
         let out_header = fuse_out_header {
@@ -1186,8 +3929,10 @@ mod tests {
             max_write: DEFAULT_MAX_WRITE,
             time_gran: 1,
             max_pages: expected_max_pages,
-            padding: 0,
-            unused: [0; 8],
+            map_alignment: 0,
+            flags2: 0,
+            max_stack_depth: 0,
+            unused: [0; 6],
         };
 
         let mut expected = Vec::with_capacity(output_len);
@@ -1220,58 +3965,2314 @@ mod tests {
         );
     }
 
-    #[inline]
-    fn bytes(bytes: &[u8]) -> &[u8] {
-        bytes
-    }
-    macro_rules! b {
-        ($($b:expr),*$(,)?) => ( *bytes(&[$($b),*]) );
+    /// Create a session initialized by an INIT request with the specified parameters.
+    fn init_pair(init_in: &fuse_init_in, mut init_out: fuse_init_out) -> (Connection, Session) {
+        let (kernel, conn) = conn_pair();
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_init_in>()) as u32,
+            opcode: fuse_opcode::FUSE_INIT as u32,
+            unique: 2,
+            ..Default::default()
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(init_in.as_bytes()),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+
+        let readonly_flags = init_session(&mut init_out, &conn, &conn, |_, _| Ok(()))
+            .expect("initialization failed");
+        (kernel, Session::new(conn, init_out, readonly_flags))
+    }
+
+    fn run_init(init_in: &[u8], init_out: &mut fuse_init_out) -> Vec<u8> {
+        let in_header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + init_in.len()) as u32,
+            opcode: fuse_opcode::FUSE_INIT as u32,
+            unique: 2,
+            ..Default::default()
+        };
+        let mut input = vec![];
+        input.extend_from_slice(in_header.as_bytes());
+        input.extend_from_slice(init_in);
+
+        let mut output = Vec::<u8>::new();
+        init_session(init_out, &input[..], &mut output, |_, _| Ok(()))
+            .expect("initialization failed");
+        output
+    }
+
+    #[test]
+    fn init_flags2() {
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 38,
+            max_readahead: 40,
+            flags: INIT_FLAGS_MASK | FUSE_INIT_EXT,
+            flags2: ((FUSE_SECURITY_CTX | FUSE_CREATE_SUPP_GROUP) >> 32) as u32,
+            ..Default::default()
+        };
+
+        let mut init_out = default_init_out();
+        init_out.flags2 = ((FUSE_HAS_INODE_DAX | FUSE_CREATE_SUPP_GROUP) >> 32) as u32;
+        let output = run_init(init_in.as_bytes(), &mut init_out);
+
+        assert_eq!(init_out.minor, 38);
+        assert!(init_out.flags & FUSE_INIT_EXT != 0);
+        assert_eq!(init_out.flags2, (FUSE_CREATE_SUPP_GROUP >> 32) as u32);
+
+        let output = &output[mem::size_of::<fuse_out_header>()..];
+        assert_eq!(
+            output[12..16],
+            (FUSE_INIT_EXT | DEFAULT_INIT_FLAGS | FUSE_BIG_WRITES).to_ne_bytes(),
+            "init_out.flags"
+        );
+        assert_eq!(
+            output[32..36],
+            init_out.flags2.to_ne_bytes(),
+            "init_out.flags2"
+        );
+    }
+
+    #[test]
+    fn init_explicit_inval_data() {
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 38,
+            max_readahead: 40,
+            flags: INIT_FLAGS_MASK,
+            ..Default::default()
+        };
+
+        let mut config = KernelConfig::default();
+        config.explicit_inval_data(true);
+        let mut init_out = config.init_out;
+        run_init(init_in.as_bytes(), &mut init_out);
+        assert!(init_out.flags & FUSE_EXPLICIT_INVAL_DATA != 0);
+        assert!(init_out.flags & FUSE_AUTO_INVAL_DATA == 0);
+
+        // The capability is not granted by the kernel.
+        let init_in = fuse_init_in {
+            flags: INIT_FLAGS_MASK & !FUSE_EXPLICIT_INVAL_DATA,
+            ..init_in
+        };
+        let mut init_out = config.init_out;
+        run_init(init_in.as_bytes(), &mut init_out);
+        assert!(init_out.flags & FUSE_EXPLICIT_INVAL_DATA == 0);
+    }
+
+    #[test]
+    fn init_cache_symlinks() {
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 38,
+            max_readahead: 40,
+            flags: INIT_FLAGS_MASK,
+            ..Default::default()
+        };
+
+        let mut config = KernelConfig::default();
+        config.cache_symlinks(true);
+        let mut init_out = config.init_out;
+        let output = run_init(init_in.as_bytes(), &mut init_out);
+
+        let output = &output[mem::size_of::<fuse_out_header>()..];
+        assert_eq!(
+            output[12..16],
+            (DEFAULT_INIT_FLAGS | FUSE_CACHE_SYMLINKS | FUSE_BIG_WRITES).to_ne_bytes(),
+            "init_out.flags"
+        );
+    }
+
+    #[test]
+    fn init_submounts() {
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 38,
+            max_readahead: 40,
+            flags: INIT_FLAGS_MASK,
+            ..Default::default()
+        };
+
+        let mut config = KernelConfig::default();
+        config.submounts(true);
+        let mut init_out = config.init_out;
+        run_init(init_in.as_bytes(), &mut init_out);
+        assert!(init_out.flags & FUSE_SUBMOUNTS != 0);
+
+        // The kernel prior to ABI 7.32 does not know the flag.
+        let init_in = fuse_init_in {
+            minor: 31,
+            flags: INIT_FLAGS_MASK & !FUSE_SUBMOUNTS,
+            ..init_in
+        };
+        let mut init_out = config.init_out;
+        run_init(init_in.as_bytes(), &mut init_out);
+        assert!(init_out.flags & FUSE_SUBMOUNTS == 0);
+    }
+
+    #[test]
+    fn init_no_default_flags() {
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 38,
+            max_readahead: 40,
+            flags: INIT_FLAGS_MASK,
+            ..Default::default()
+        };
+
+        let mut config = KernelConfig::default();
+        config.no_default_flags().parallel_dirops(true);
+        let mut init_out = config.init_out;
+        let output = run_init(init_in.as_bytes(), &mut init_out);
+
+        let output = &output[mem::size_of::<fuse_out_header>()..];
+        assert_eq!(
+            output[12..16],
+            (FUSE_PARALLEL_DIROPS | FUSE_BIG_WRITES).to_ne_bytes(),
+            "init_out.flags"
+        );
+    }
+
+    #[test]
+    fn config_default_permissions() {
+        let mut config = KernelConfig::default();
+        assert!(!config.mountopts.default_permissions());
+
+        config.mount_option("ro, default_permissions");
+        assert!(config.mountopts.default_permissions());
+
+        config.default_permissions(true);
+        config.default_permissions(false);
+        assert!(!config.mountopts.default_permissions());
+        assert_eq!(config.mountopts.options, ["ro"]);
+    }
+
+    #[test]
+    fn config_read_only() {
+        let mut config = KernelConfig::default();
+        assert!(!config.mountopts.read_only());
+
+        config.mount_option("rw,default_permissions");
+        config.read_only(true);
+        assert!(config.mountopts.read_only());
+        assert_eq!(config.mountopts.options, ["default_permissions", "ro"]);
+
+        config.mount_option("rw");
+        assert!(!config.mountopts.read_only());
+
+        config.read_only(false);
+        assert_eq!(config.mountopts.options, ["default_permissions"]);
+    }
+
+    #[test]
+    fn reject_writes() {
+        let (kernel, session) = session_pair();
+        session.set_reject_writes(true);
+
+        let write_in = fuse_write_in {
+            fh: 1,
+            size: 3,
+            ..Default::default()
+        };
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_write_in>() + 3) as u32,
+            opcode: fuse_opcode::FUSE_WRITE as u32,
+            unique: 2,
+            nodeid: 2,
+            ..Default::default()
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(write_in.as_bytes()),
+                IoSlice::new(b"foo"),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_getattr_in>()) as u32,
+            opcode: fuse_opcode::FUSE_GETATTR as u32,
+            unique: 4,
+            nodeid: 2,
+            ..Default::default()
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(fuse_getattr_in::default().as_bytes()),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+
+        // The write request is answered by the session and never returned.
+        let req = session.next_request().unwrap().expect("no request");
+        assert_eq!(req.unique(), 4);
+        assert!(matches!(req.operation().unwrap(), Operation::Getattr(..)));
+
+        let mut buf = [0u8; 64];
+        let len = (&kernel).read(&mut buf[..]).unwrap();
+        assert_eq!(len, mem::size_of::<fuse_out_header>());
+        assert_eq!(
+            i32::from_ne_bytes(buf[4..8].try_into().unwrap()),
+            -libc::EROFS
+        );
+        assert_eq!(u64::from_ne_bytes(buf[8..16].try_into().unwrap()), 2);
+    }
+
+    #[test]
+    fn init_max_background() {
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 38,
+            max_readahead: 40,
+            flags: INIT_FLAGS_MASK,
+            ..Default::default()
+        };
+
+        let mut config = KernelConfig::default();
+        config.max_background(32).congestion_threshold(0);
+        let mut init_out = config.init_out;
+        let output = run_init(init_in.as_bytes(), &mut init_out);
+
+        let output = &output[mem::size_of::<fuse_out_header>()..];
+        assert_eq!(
+            output[16..18],
+            32u16.to_ne_bytes(),
+            "init_out.max_background"
+        );
+        assert_eq!(
+            output[18..20],
+            24u16.to_ne_bytes(),
+            "init_out.congestion_threshold"
+        );
+
+        let (kernel, conn) = conn_pair();
+        drop(kernel);
+        let session = Session::new(conn, init_out, 0);
+        assert_eq!(session.max_background(), 32);
+        assert_eq!(session.congestion_threshold(), 24);
+        // The test connection is not mounted.
+        assert!(session.applied_max_background().is_err());
+    }
+
+    #[test]
+    fn init_setxattr_ext() {
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 38,
+            max_readahead: 40,
+            flags: INIT_FLAGS_MASK,
+            ..Default::default()
+        };
+
+        let mut config = KernelConfig::default();
+        config.setxattr_ext(true);
+        let mut init_out = config.init_out;
+        run_init(init_in.as_bytes(), &mut init_out);
+        assert!(init_out.flags & FUSE_SETXATTR_EXT != 0);
+
+        // The kernel prior to ABI 7.33 sends the legacy arguments.
+        let init_in = fuse_init_in {
+            minor: 32,
+            flags: INIT_FLAGS_MASK & !FUSE_SETXATTR_EXT,
+            ..init_in
+        };
+        let mut init_out = config.init_out;
+        run_init(init_in.as_bytes(), &mut init_out);
+        assert!(init_out.flags & FUSE_SETXATTR_EXT == 0);
+    }
+
+    #[test]
+    fn init_max_readahead() {
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 38,
+            max_readahead: 128 * 1024,
+            flags: INIT_FLAGS_MASK,
+            ..Default::default()
+        };
+
+        // The kernel's value is smaller.
+        let mut init_out = default_init_out();
+        run_init(init_in.as_bytes(), &mut init_out);
+        assert_eq!(init_out.max_readahead, 128 * 1024);
+
+        // The filesystem's value is smaller.
+        let mut config = KernelConfig::default();
+        config.max_readahead(4096);
+        let mut init_out = config.init_out;
+        run_init(init_in.as_bytes(), &mut init_out);
+        assert_eq!(init_out.max_readahead, 4096);
+    }
+
+    #[test]
+    fn init_protocol_minor() {
+        let init_in = |minor: u32| fuse_init_in {
+            major: 7,
+            minor,
+            max_readahead: 40,
+            flags: INIT_FLAGS_MASK,
+            ..Default::default()
+        };
+
+        // The cap is lower than the kernel's version.
+        let mut config = KernelConfig::default();
+        config.protocol_minor(31);
+        let mut init_out = config.init_out;
+        let output = run_init(init_in(38).as_bytes(), &mut init_out);
+        assert_eq!(init_out.minor, 31);
+        assert_eq!(
+            output[mem::size_of::<fuse_out_header>() + 4..][..4],
+            31u32.to_ne_bytes(),
+            "init_out.minor"
+        );
+
+        // The kernel's version is lower than the cap.
+        let mut init_out = config.init_out;
+        run_init(init_in(28).as_bytes(), &mut init_out);
+        assert_eq!(init_out.minor, 28);
+
+        // The cap is never raised above the version supported by polyfuse.
+        config.protocol_minor(u32::MAX);
+        assert_eq!(config.init_out.minor, FUSE_KERNEL_MINOR_VERSION);
+    }
+
+    #[test]
+    #[should_panic]
+    fn protocol_minor_too_old() {
+        KernelConfig::default().protocol_minor(MINIMUM_SUPPORTED_MINOR_VERSION - 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_max_readahead() {
+        KernelConfig::default().max_readahead(0);
+    }
+
+    #[test]
+    fn init_passthrough() {
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 40,
+            max_readahead: 40,
+            flags: INIT_FLAGS_MASK | FUSE_INIT_EXT,
+            flags2: (FUSE_PASSTHROUGH >> 32) as u32,
+            ..Default::default()
+        };
+
+        let mut config = KernelConfig::default();
+        config.passthrough(true);
+        let mut init_out = config.init_out;
+        run_init(init_in.as_bytes(), &mut init_out);
+        assert_eq!(init_out.flags2, (FUSE_PASSTHROUGH >> 32) as u32);
+        assert_eq!(init_out.max_stack_depth, 1);
+
+        // The capability is not granted by the kernel.
+        let init_in = fuse_init_in {
+            minor: 38,
+            flags2: 0,
+            ..init_in
+        };
+        let mut init_out = config.init_out;
+        run_init(init_in.as_bytes(), &mut init_out);
+        assert_eq!(init_out.flags2, 0);
+        assert_eq!(init_out.max_stack_depth, 0);
+    }
+
+    #[test]
+    fn init_rejected_by_hook() {
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 38,
+            max_readahead: 40,
+            flags: INIT_FLAGS_MASK & !FUSE_WRITEBACK_CACHE,
+            ..Default::default()
+        };
+        let in_header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_init_in>()) as u32,
+            opcode: fuse_opcode::FUSE_INIT as u32,
+            unique: 2,
+            ..Default::default()
+        };
+        let mut input = vec![];
+        input.extend_from_slice(in_header.as_bytes());
+        input.extend_from_slice(init_in.as_bytes());
+
+        // The filesystem depends on the writeback caching.
+        let hook = || {
+            init_hook(|init, config| {
+                if init.flags() & FUSE_WRITEBACK_CACHE as u64 == 0 {
+                    return Err(libc::ENOTSUP);
+                }
+                config.writeback_cache(true);
+                Ok(())
+            })
+        };
+
+        let mut init_out = default_init_out();
+        let mut output = Vec::<u8>::new();
+        let err = init_session(&mut init_out, &input[..], &mut output, hook()).unwrap_err();
+        assert!(matches!(err, Error::Io(ref err) if err.raw_os_error() == Some(libc::ENOTSUP)));
+        assert_eq!(output.len(), mem::size_of::<fuse_out_header>());
+        assert_eq!(
+            output[4..8],
+            (-libc::ENOTSUP).to_ne_bytes(),
+            "out_header.error"
+        );
+
+        // The kernel supports the writeback caching.
+        let init_in = fuse_init_in {
+            flags: INIT_FLAGS_MASK,
+            ..init_in
+        };
+        input.truncate(mem::size_of::<fuse_in_header>());
+        input.extend_from_slice(init_in.as_bytes());
+
+        let mut init_out = default_init_out();
+        init_session(&mut init_out, &input[..], &mut Vec::<u8>::new(), hook()).unwrap();
+        assert!(init_out.flags & FUSE_WRITEBACK_CACHE != 0);
+    }
+
+    #[test]
+    fn session_from_raw_fd() {
+        let mut fds = [0; 2];
+        let res =
+            unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) };
+        assert_eq!(res, 0, "socketpair: {}", io::Error::last_os_error());
+        let kernel = Connection::from_raw_fd(fds[1]);
+
+        // The INIT request sent by the kernel after the helper has mounted the filesystem.
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 38,
+            max_readahead: 40,
+            flags: INIT_FLAGS_MASK,
+            ..Default::default()
+        };
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_init_in>()) as u32,
+            opcode: fuse_opcode::FUSE_INIT as u32,
+            unique: 2,
+            ..Default::default()
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(init_in.as_bytes()),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+
+        let mut config = KernelConfig::default();
+        config.max_write(MIN_MAX_WRITE);
+        let session = unsafe { Session::from_raw_fd(fds[0], config) }.unwrap();
+        assert_eq!(session.as_raw_fd(), fds[0]);
+        assert_eq!(session.max_write(), MIN_MAX_WRITE);
+        assert_eq!(session.max_readahead(), 40);
+
+        let mut reply = [0u8; 128];
+        let len = (&kernel).read(&mut reply[..]).unwrap();
+        assert_eq!(
+            len,
+            mem::size_of::<fuse_out_header>() + mem::size_of::<fuse_init_out>()
+        );
+        assert_eq!(reply[8..16], 2u64.to_ne_bytes(), "out_header.unique");
+
+        // The device is closed along with the session, without unmounting.
+        drop(session);
+        assert_eq!((&kernel).read(&mut reply[..]).unwrap(), 0);
+    }
+
+    #[test]
+    fn init_no_open_support() {
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 38,
+            max_readahead: 40,
+            flags: INIT_FLAGS_MASK | FUSE_NO_OPEN_SUPPORT | FUSE_NO_OPENDIR_SUPPORT,
+            ..Default::default()
+        };
+
+        let mut init_out = default_init_out();
+        let output = run_init(init_in.as_bytes(), &mut init_out);
+
+        // The read-only flags are not sent back to the kernel,
+        let output = &output[mem::size_of::<fuse_out_header>()..];
+        assert_eq!(
+            output[12..16],
+            (DEFAULT_INIT_FLAGS | FUSE_BIG_WRITES).to_ne_bytes(),
+            "init_out.flags"
+        );
+
+        // but kept in the session.
+        let (_kernel, session) = init_pair(&init_in, default_init_out());
+        assert!(session.no_open_support());
+        assert!(session.no_opendir_support());
+    }
+
+    #[test]
+    fn zero_message_open() {
+        let run = |init_flags: u32| {
+            let (kernel, conn) = conn_pair();
+
+            let session = Session::new(conn, default_init_out(), init_flags);
+            session.set_zero_message_open(true);
+
+            let send = |opcode: fuse_opcode, unique: u64| {
+                let header = fuse_in_header {
+                    len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_open_in>()) as u32,
+                    opcode: opcode as u32,
+                    unique,
+                    nodeid: 2,
+                    ..Default::default()
+                };
+                let written = (&kernel)
+                    .write_vectored(&[
+                        IoSlice::new(header.as_bytes()),
+                        IoSlice::new(fuse_open_in::default().as_bytes()),
+                    ])
+                    .unwrap();
+                assert_eq!(written, header.len as usize);
+            };
+            send(fuse_opcode::FUSE_OPEN, 2);
+            send(fuse_opcode::FUSE_OPENDIR, 4);
+            send(fuse_opcode::FUSE_FLUSH, 6);
+
+            let mut returned = vec![];
+            loop {
+                let req = session.next_request().unwrap().expect("no request");
+                returned.push(req.unique());
+                if req.unique() == 6 {
+                    break;
+                }
+            }
+
+            kernel.set_nonblocking(true).unwrap();
+            let mut replies = vec![];
+            let mut buf = [0u8; 64];
+            while let Ok(len) = (&kernel).read(&mut buf[..]) {
+                assert_eq!(len, mem::size_of::<fuse_out_header>());
+                let error = i32::from_ne_bytes(buf[4..8].try_into().unwrap());
+                let unique = u64::from_ne_bytes(buf[8..16].try_into().unwrap());
+                replies.push((unique, -error));
+            }
+            (returned, replies)
+        };
+
+        // No open request is returned to the filesystem for replying a handle.
+        let (returned, replies) = run(FUSE_NO_OPEN_SUPPORT | FUSE_NO_OPENDIR_SUPPORT);
+        assert_eq!(returned, [6]);
+        assert_eq!(replies, [(2, libc::ENOSYS), (4, libc::ENOSYS)]);
+
+        let (returned, replies) = run(FUSE_NO_OPEN_SUPPORT);
+        assert_eq!(returned, [4, 6]);
+        assert_eq!(replies, [(2, libc::ENOSYS)]);
+
+        // The kernel does not support the zero-message opens.
+        let (returned, replies) = run(0);
+        assert_eq!(returned, [2, 4, 6]);
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn init_without_flags2() {
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 31,
+            max_readahead: 40,
+            flags: INIT_FLAGS_MASK,
+            flags2: (FUSE_CREATE_SUPP_GROUP >> 32) as u32, // must be ignored
+            ..Default::default()
+        };
+
+        let mut init_out = default_init_out();
+        init_out.flags2 = (FUSE_CREATE_SUPP_GROUP >> 32) as u32;
+        run_init(init_in.as_bytes(), &mut init_out);
+        assert!(init_out.flags & FUSE_INIT_EXT == 0);
+        assert_eq!(init_out.flags2, 0);
+
+        // The kernels prior to 7.36 send the shorter `fuse_init_in`.
+        let mut init_out = default_init_out();
+        init_out.flags2 = (FUSE_CREATE_SUPP_GROUP >> 32) as u32;
+        run_init(&init_in.as_bytes()[..COMPAT_INIT_IN_SIZE], &mut init_out);
+        assert_eq!(init_out.minor, 31);
+        assert_eq!(init_out.max_readahead, 40);
+        assert!(init_out.flags & FUSE_INIT_EXT == 0);
+        assert_eq!(init_out.flags2, 0);
+    }
+
+    #[test]
+    fn init_malformed() {
+        let in_header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + 8) as u32,
+            opcode: fuse_opcode::FUSE_INIT as u32,
+            unique: 2,
+            ..Default::default()
+        };
+        let mut input = vec![];
+        input.extend_from_slice(in_header.as_bytes());
+        // ABI 7.6 or later must send `flags` and `max_readahead`.
+        input.extend_from_slice(&7u32.to_ne_bytes());
+        input.extend_from_slice(&31u32.to_ne_bytes());
+
+        let mut init_out = default_init_out();
+        let err = init_session(&mut init_out, &input[..], &mut Vec::<u8>::new(), |_, _| {
+            Ok(())
+        })
+        .unwrap_err();
+        assert!(matches!(err, Error::Decode(..)), "{:?}", err);
+
+        let mut init_out = default_init_out();
+        let err = init_session(
+            &mut init_out,
+            &input[..10],
+            &mut Vec::<u8>::new(),
+            |_, _| Ok(()),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::Decode(..)), "{:?}", err);
+    }
+
+    #[test]
+    fn init_operations_before_init() {
+        let (kernel, conn) = conn_pair();
+
+        let in_header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_getattr_in>()) as u32,
+            opcode: fuse_opcode::FUSE_GETATTR as u32,
+            unique: 2,
+            nodeid: 1,
+            ..Default::default()
+        };
+        for _ in 0..16 {
+            let written = (&kernel)
+                .write_vectored(&[
+                    IoSlice::new(in_header.as_bytes()),
+                    IoSlice::new(fuse_getattr_in::default().as_bytes()),
+                ])
+                .unwrap();
+            assert_eq!(written, in_header.len as usize);
+        }
+
+        let err = init_session(&mut default_init_out(), &conn, &conn, |_, _| Ok(())).unwrap_err();
+        match err {
+            Error::OperationBeforeInit { opcode, count } => {
+                assert_eq!(opcode, fuse_opcode::FUSE_GETATTR as u32);
+                assert_eq!(count, 10);
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+
+        // Each operation is answered with EIO.
+        for _ in 0..10 {
+            let mut buf = [0u8; 64];
+            let len = (&kernel).read(&mut buf[..]).unwrap();
+            assert_eq!(len, mem::size_of::<fuse_out_header>());
+            assert_eq!(
+                i32::from_ne_bytes(buf[4..8].try_into().unwrap()),
+                -libc::EIO
+            );
+        }
+    }
+
+    #[test]
+    fn init_version_only() {
+        let init_in = |major: u32, minor: u32| {
+            let mut init_in = vec![];
+            init_in.extend_from_slice(&major.to_ne_bytes());
+            init_in.extend_from_slice(&minor.to_ne_bytes());
+            assert_eq!(init_in.len(), COMPAT_5_INIT_IN_SIZE);
+            init_in
+        };
+        let run = |init_in: &[u8]| {
+            let in_header = fuse_in_header {
+                len: (mem::size_of::<fuse_in_header>() + init_in.len()) as u32,
+                opcode: fuse_opcode::FUSE_INIT as u32,
+                unique: 2,
+                ..Default::default()
+            };
+            let mut input = vec![];
+            input.extend_from_slice(in_header.as_bytes());
+            input.extend_from_slice(init_in);
+            let mut output = Vec::<u8>::new();
+            let res = init_session(&mut default_init_out(), &input[..], &mut output, |_, _| {
+                Ok(())
+            });
+            (res, output)
+        };
+
+        // The kernels prior to ABI 7.6 are rejected by the version, not as malformed.
+        let (res, output) = run(&init_in(7, 5));
+        let err = res.unwrap_err();
+        assert!(
+            matches!(err, Error::UnsupportedVersion { major: 7, minor: 5 }),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            output[4..8],
+            (-libc::EPROTO).to_ne_bytes(),
+            "out_header.error"
+        );
+
+        // The newer major version is answered with the supported version,
+        // and the negotiation continues with the second INIT request.
+        let (res, output) = run(&init_in(8, 0));
+        assert!(
+            matches!(res, Err(Error::Decode(..))),
+            "EOF after the first reply"
+        );
+        let output = &output[mem::size_of::<fuse_out_header>()..];
+        assert_eq!(
+            output[0..4],
+            FUSE_KERNEL_VERSION.to_ne_bytes(),
+            "init_out.major"
+        );
+    }
+
+    #[test]
+    fn init_unsupported_version() {
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 22,
+            ..Default::default()
+        };
+
+        let mut init_out = default_init_out();
+        let mut output = Vec::<u8>::new();
+        let mut input = vec![];
+        input.extend_from_slice(
+            fuse_in_header {
+                len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_init_in>()) as u32,
+                opcode: fuse_opcode::FUSE_INIT as u32,
+                unique: 2,
+                ..Default::default()
+            }
+            .as_bytes(),
+        );
+        input.extend_from_slice(init_in.as_bytes());
+
+        let err = init_session(&mut init_out, &input[..], &mut output, |_, _| Ok(())).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::UnsupportedVersion {
+                    major: 7,
+                    minor: 22
+                }
+            ),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            output[4..8],
+            (-libc::EPROTO).to_ne_bytes(),
+            "out_header.error"
+        );
+    }
+
+    #[test]
+    fn init_cuse() {
+        let init_in = cuse_init_in {
+            major: 7,
+            minor: 31,
+            unused: 0,
+            flags: CUSE_UNRESTRICTED_IOCTL,
+        };
+        let in_header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<cuse_init_in>()) as u32,
+            opcode: fuse_opcode::CUSE_INIT as u32,
+            unique: 2,
+            ..Default::default()
+        };
+        let mut input = vec![];
+        input.extend_from_slice(in_header.as_bytes());
+        input.extend_from_slice(init_in.as_bytes());
+
+        let mut config = CuseConfig::new("polyfuse");
+        config.dev_major(240).dev_minor(1).unrestricted_ioctl(true);
+        let devinfo = config.devinfo();
+        let mut init_out = config.init_out;
+
+        let mut output = Vec::<u8>::new();
+        init_cuse_session(&mut init_out, &devinfo, &input[..], &mut output)
+            .expect("initialization failed");
+
+        assert_eq!(init_out.minor, 31);
+        assert_eq!(init_out.flags, CUSE_UNRESTRICTED_IOCTL);
+
+        let output_len = mem::size_of::<fuse_out_header>()
+            + mem::size_of::<cuse_init_out>()
+            + b"DEVNAME=polyfuse\0".len();
+        assert_eq!(output.len(), output_len);
+        assert_eq!(
+            output[0..4],
+            (output_len as u32).to_ne_bytes(),
+            "out_header.len"
+        );
+        assert_eq!(output[8..16], 2u64.to_ne_bytes(), "out_header.unique");
+
+        let output = &output[mem::size_of::<fuse_out_header>()..];
+        assert_eq!(output[0..4], 7u32.to_ne_bytes(), "init_out.major");
+        assert_eq!(output[4..8], 31u32.to_ne_bytes(), "init_out.minor");
+        assert_eq!(output[24..28], 240u32.to_ne_bytes(), "init_out.dev_major");
+        assert_eq!(output[28..32], 1u32.to_ne_bytes(), "init_out.dev_minor");
+        assert_eq!(
+            output[mem::size_of::<cuse_init_out>()..],
+            *b"DEVNAME=polyfuse\0",
+            "devinfo"
+        );
+    }
+
+    #[test]
+    fn supp_groups_extension() {
+        let mut arg = vec![0u8; 0];
+        arg.extend_from_slice(fuse_mkdir_in::default().as_bytes());
+        arg.extend_from_slice(b"dir\0");
+        let offset = arg.len();
+        arg.extend_from_slice(
+            fuse_ext_header {
+                size: 16,
+                typ: FUSE_EXT_GROUPS,
+            }
+            .as_bytes(),
+        );
+        arg.extend_from_slice(&1u32.to_ne_bytes());
+        arg.extend_from_slice(&1000u32.to_ne_bytes());
+        let total_extlen = ((arg.len() - offset) / 8) as u16;
+
+        assert_eq!(decode_supp_groups(&arg[..], total_extlen), Some(vec![1000]));
+        assert_eq!(decode_supp_groups(&arg[..], 0), None);
+    }
+
+    #[test]
+    fn security_context_extension() {
+        let mut arg = vec![0u8; 0];
+        arg.extend_from_slice(fuse_create_in::default().as_bytes());
+        arg.extend_from_slice(b"file\0");
+        while arg.len() % 8 != 0 {
+            arg.push(0);
+        }
+        let offset = arg.len();
+
+        let name = b"security.selinux\0";
+        let value = b"unconfined_u:object_r:user_home_t:s0\0";
+        let len = mem::size_of::<fuse_secctx>() + name.len() + value.len();
+        let padded_len = (len + 7) & !7;
+        arg.extend_from_slice(
+            fuse_secctx_header {
+                size: (mem::size_of::<fuse_secctx_header>() + padded_len) as u32,
+                nr_secctx: 1,
+            }
+            .as_bytes(),
+        );
+        arg.extend_from_slice(
+            fuse_secctx {
+                size: value.len() as u32,
+                padding: 0,
+            }
+            .as_bytes(),
+        );
+        arg.extend_from_slice(name);
+        arg.extend_from_slice(value);
+        arg.resize(arg.len() + padded_len - len, 0);
+
+        // The supplementary group extension follows.
+        arg.extend_from_slice(
+            fuse_ext_header {
+                size: 16,
+                typ: FUSE_EXT_GROUPS,
+            }
+            .as_bytes(),
+        );
+        arg.extend_from_slice(&1u32.to_ne_bytes());
+        arg.extend_from_slice(&1000u32.to_ne_bytes());
+        let total_extlen = ((arg.len() - offset) / 8) as u16;
+
+        let contexts = decode_security_contexts(&arg[..], total_extlen).unwrap();
+        assert_eq!(contexts.len(), 1);
+        assert_eq!(contexts[0].name(), "security.selinux");
+        assert_eq!(contexts[0].value(), &value[..]);
+        assert_eq!(decode_supp_groups(&arg[..], total_extlen), Some(vec![1000]));
+        assert_eq!(decode_security_contexts(&arg[..], 0), None);
+
+        // The create request is decoded regardless of the extensions.
+        let header = fuse_in_header {
+            opcode: fuse_opcode::FUSE_CREATE as u32,
+            total_extlen,
+            ..Default::default()
+        };
+        match Operation::decode(&header, &arg[..], (), FUSE_INIT_EXT).unwrap() {
+            Operation::Create(op) => assert_eq!(op.name(), "file"),
+            _ => panic!("incorrect operation is returned"),
+        }
+
+        // No LSM provides the context.
+        let mut arg = vec![0u8; 0];
+        arg.extend_from_slice(fuse_mkdir_in::default().as_bytes());
+        arg.extend_from_slice(b"dir\0\0\0\0\0");
+        arg.extend_from_slice(
+            fuse_secctx_header {
+                size: 8,
+                nr_secctx: 0,
+            }
+            .as_bytes(),
+        );
+        assert_eq!(decode_security_contexts(&arg[..], 1), Some(vec![]));
+    }
+
+    #[inline]
+    fn bytes(bytes: &[u8]) -> &[u8] {
+        bytes
+    }
+    macro_rules! b {
+        ($($b:expr),*$(,)?) => ( *bytes(&[$($b),*]) );
+    }
+
+    #[test]
+    fn send_msg_empty() {
+        let mut buf = vec![0u8; 0];
+        write_bytes(&mut buf, Reply::new(42, -4, &[])).unwrap();
+        assert_eq!(buf[0..4], b![0x10, 0x00, 0x00, 0x00], "header.len");
+        assert_eq!(buf[4..8], b![0x04, 0x00, 0x00, 0x00], "header.error");
+        assert_eq!(
+            buf[8..16],
+            b![0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            "header.unique"
+        );
+    }
+
+    #[test]
+    fn send_msg_single_data() {
+        let mut buf = vec![0u8; 0];
+        write_bytes(&mut buf, Reply::new(42, 0, "hello")).unwrap();
+        assert_eq!(buf[0..4], b![0x15, 0x00, 0x00, 0x00], "header.len");
+        assert_eq!(buf[4..8], b![0x00, 0x00, 0x00, 0x00], "header.error");
+        assert_eq!(
+            buf[8..16],
+            b![0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            "header.unique"
+        );
+        assert_eq!(buf[16..], b![0x68, 0x65, 0x6c, 0x6c, 0x6f], "payload");
+    }
+
+    #[test]
+    fn send_msg_chunked_data() {
+        let payload: &[&[u8]] = &[
+            "hello, ".as_ref(), //
+            "this ".as_ref(),
+            "is a ".as_ref(),
+            "message.".as_ref(),
+        ];
+        let mut buf = vec![0u8; 0];
+        write_bytes(&mut buf, Reply::new(26, 0, payload)).unwrap();
+        assert_eq!(buf[0..4], b![0x29, 0x00, 0x00, 0x00], "header.len");
+        assert_eq!(buf[4..8], b![0x00, 0x00, 0x00, 0x00], "header.error");
+        assert_eq!(
+            buf[8..16],
+            b![0x1a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            "header.unique"
+        );
+        assert_eq!(buf[16..], *b"hello, this is a message.", "payload");
+    }
+
+    #[test]
+    fn read_remaining_pieces() {
+        let in_header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + 8) as u32,
+            opcode: fuse_opcode::FUSE_GETATTR as u32,
+            unique: 1,
+            ..Default::default()
+        };
+        let mut input = vec![];
+        input.extend_from_slice(in_header.as_bytes());
+        input.extend_from_slice(&42u64.to_ne_bytes());
+
+        // Only a part of the header is received by the first read.
+        let mut header = fuse_in_header::default();
+        header.as_bytes_mut()[..4].copy_from_slice(&input[..4]);
+        let mut arg = vec![0u8; 64];
+        let len = read_remaining(&input[4..], &mut header, &mut arg, 4).unwrap();
+
+        assert_eq!(header.unique, 1);
+        assert_eq!(arg[..len], 42u64.to_ne_bytes());
+    }
+
+    #[test]
+    fn device_short_read() {
+        let (kernel, session) = session_pair();
+
+        // The message is shorter than its header declares.
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_getattr_in>()) as u32,
+            opcode: fuse_opcode::FUSE_GETATTR as u32,
+            unique: 2,
+            nodeid: 1,
+            ..Default::default()
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(&fuse_getattr_in::default().as_bytes()[..8]),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize - 8);
+        match session.next_request() {
+            Err(Error::Decode(..)) => (),
+            Err(err) => panic!("unexpected error: {:?}", err),
+            Ok(..) => panic!("the short message is accepted"),
+        }
+
+        // The next message is not merged into the short one.
+        let header = fuse_in_header {
+            unique: 4,
+            ..header
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(fuse_getattr_in::default().as_bytes()),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+        let req = session.next_request().unwrap().expect("no request");
+        assert_eq!(req.unique(), 4);
+        assert!(matches!(req.operation(), Ok(Operation::Getattr(..))));
+    }
+
+    #[test]
+    fn stream_pieces() {
+        let mut fds = [0; 2];
+        let res =
+            unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) };
+        assert_eq!(res, 0, "socketpair: {}", io::Error::last_os_error());
+        let kernel = Connection::from_raw_fd(fds[1]);
+        let session = Session::new(Connection::from_raw_fd(fds[0]), default_init_out(), 0);
+        assert!(session.inner.stream);
+
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_getattr_in>()) as u32,
+            opcode: fuse_opcode::FUSE_GETATTR as u32,
+            unique: 2,
+            nodeid: 1,
+            ..Default::default()
+        };
+        let mut message = header.as_bytes().to_vec();
+        message.extend_from_slice(fuse_getattr_in::default().as_bytes());
+        message.extend_from_slice(
+            fuse_in_header {
+                unique: 4,
+                ..header
+            }
+            .as_bytes(),
+        );
+        message.extend_from_slice(fuse_getattr_in::default().as_bytes());
+
+        // The messages arrive in pieces that cross their boundaries.
+        let writer = thread::spawn(move || {
+            for piece in message.chunks(24) {
+                (&kernel).write_all(piece).unwrap();
+                thread::sleep(Duration::from_millis(1));
+            }
+            kernel
+        });
+        for &unique in &[2, 4] {
+            let req = session.next_request().unwrap().expect("no request");
+            assert_eq!(req.unique(), unique);
+            assert!(matches!(req.operation(), Ok(Operation::Getattr(..))));
+        }
+
+        // The end of the stream closes the session.
+        drop(writer.join().unwrap());
+        assert!(session.next_request().unwrap().is_none());
+    }
+
+    #[test]
+    fn read_remaining_truncated() {
+        let in_header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + 8) as u32,
+            opcode: fuse_opcode::FUSE_GETATTR as u32,
+            unique: 1,
+            ..Default::default()
+        };
+
+        // The connection is closed in the middle of the header.
+        let mut header = fuse_in_header::default();
+        let mut arg = vec![0u8; 64];
+        let err = read_remaining(&in_header.as_bytes()[..10], &mut header, &mut arg, 0)
+            .expect_err("should be failed");
+        assert!(matches!(err, Error::Decode(..)));
+
+        // The message is longer than the buffer.
+        let mut header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + 128) as u32,
+            ..in_header
+        };
+        let mut arg = vec![0u8; 64];
+        let err = read_remaining(
+            &[][..],
+            &mut header,
+            &mut arg,
+            mem::size_of::<fuse_in_header>(),
+        )
+        .expect_err("should be failed");
+        assert!(matches!(err, Error::Decode(..)));
+
+        // The length is shorter than the header.
+        let mut header = fuse_in_header {
+            len: 8,
+            ..in_header
+        };
+        let mut arg = vec![0u8; 64];
+        let err = read_remaining(
+            &[][..],
+            &mut header,
+            &mut arg,
+            mem::size_of::<fuse_in_header>(),
+        )
+        .expect_err("should be failed");
+        assert!(matches!(err, Error::Decode(..)));
     }
 
     #[test]
-    fn send_msg_empty() {
-        let mut buf = vec![0u8; 0];
-        write_bytes(&mut buf, Reply::new(42, -4, &[])).unwrap();
-        assert_eq!(buf[0..4], b![0x10, 0x00, 0x00, 0x00], "header.len");
-        assert_eq!(buf[4..8], b![0x04, 0x00, 0x00, 0x00], "header.error");
+    fn write_bytes_incrementally() {
+        /// A writer accepting at most 5 bytes per call.
+        struct Incremental(Vec<u8>);
+
+        impl io::Write for Incremental {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let n = cmp::min(buf.len(), 5);
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let chunks: [&[u8]; 3] = [b"hello, ", b"world", b"!"];
+        let mut writer = Incremental(vec![]);
+        write_bytes(&mut writer, Reply::new(42, 0, &chunks[..])).unwrap();
+
+        let output = writer.0;
+        let header_len = mem::size_of::<fuse_out_header>();
+        assert_eq!(output.len(), header_len + 13);
         assert_eq!(
-            buf[8..16],
-            b![0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
-            "header.unique"
+            output[0..4],
+            (header_len as u32 + 13).to_ne_bytes(),
+            "header.len"
         );
+        assert_eq!(output[8..16], 42u64.to_ne_bytes(), "header.unique");
+        assert_eq!(output[header_len..], b"hello, world!"[..]);
     }
 
     #[test]
-    fn send_msg_single_data() {
-        let mut buf = vec![0u8; 0];
-        write_bytes(&mut buf, Reply::new(42, 0, "hello")).unwrap();
-        assert_eq!(buf[0..4], b![0x15, 0x00, 0x00, 0x00], "header.len");
-        assert_eq!(buf[4..8], b![0x00, 0x00, 0x00, 0x00], "header.error");
+    fn write_bytes_zero_copy() {
+        /// A writer recording the location of the chunks passed to `write_vectored`.
+        #[derive(Default)]
+        struct Record(Vec<(*const u8, usize)>);
+
+        impl io::Write for Record {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.write_vectored(&[IoSlice::new(buf)])
+            }
+
+            fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+                self.0
+                    .extend(bufs.iter().map(|buf| (buf.as_ptr(), buf.len())));
+                Ok(bufs.iter().map(|buf| buf.len()).sum())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // The data is passed to the writer as is, without being copied into another buffer.
+        let data = vec![0x5au8; 1024 * 1024];
+        let mut out = crate::reply::ReadOut::new();
+        out.push(&data[..512]).push(&data[512..]);
+
+        let mut writer = Record::default();
+        write_bytes(&mut writer, Reply::new(7, 0, out)).unwrap();
+        assert_eq!(writer.0.len(), 3);
+        assert_eq!(writer.0[0].1, mem::size_of::<fuse_out_header>());
+        assert_eq!(writer.0[1], (data.as_ptr(), 512));
+        assert_eq!(writer.0[2], (data[512..].as_ptr(), data.len() - 512));
+    }
+
+    #[test]
+    fn reply_read_chunks() {
+        let mut out = crate::reply::ReadOut::new();
+        out.push(b"foo").push(b"").push(b"bar").push(b"baz");
+        assert_eq!(out.len(), 9);
+
+        let mut output = Vec::<u8>::new();
+        write_bytes(&mut output, Reply::new(7, 0, out)).unwrap();
+
+        let header_len = mem::size_of::<fuse_out_header>();
+        assert_eq!(output.len(), header_len + 9);
         assert_eq!(
-            buf[8..16],
-            b![0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
-            "header.unique"
+            output[0..4],
+            (header_len as u32 + 9).to_ne_bytes(),
+            "header.len"
         );
-        assert_eq!(buf[16..], b![0x68, 0x65, 0x6c, 0x6c, 0x6f], "payload");
+        assert_eq!(output[header_len..], b"foobarbaz"[..]);
     }
 
     #[test]
-    fn send_msg_chunked_data() {
-        let payload: &[&[u8]] = &[
-            "hello, ".as_ref(), //
-            "this ".as_ref(),
-            "is a ".as_ref(),
-            "message.".as_ref(),
-        ];
-        let mut buf = vec![0u8; 0];
-        write_bytes(&mut buf, Reply::new(26, 0, payload)).unwrap();
-        assert_eq!(buf[0..4], b![0x29, 0x00, 0x00, 0x00], "header.len");
-        assert_eq!(buf[4..8], b![0x00, 0x00, 0x00, 0x00], "header.error");
+    fn request_data() {
+        let (kernel, session) = session_pair();
+
+        let payload = b"hello, world";
+        let write_in = fuse_write_in {
+            fh: 1,
+            size: payload.len() as u32,
+            ..Default::default()
+        };
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>()
+                + mem::size_of::<fuse_write_in>()
+                + payload.len()) as u32,
+            opcode: fuse_opcode::FUSE_WRITE as u32,
+            unique: 1,
+            nodeid: 2,
+            ..Default::default()
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(write_in.as_bytes()),
+                IoSlice::new(payload),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+
+        let req = session.next_request().unwrap().expect("no request");
+
+        let mut data = vec![];
+        req.data().read_to_end(&mut data).unwrap();
+        assert_eq!(data, payload);
+
+        // The payload refers to the receive buffer directly.
+        let data = req.data();
+        assert_eq!(data.as_bytes(), payload);
+        assert!(std::ptr::eq(
+            data.as_bytes(),
+            &req.arg[mem::size_of::<fuse_write_in>()..]
+        ));
+        let slices = data.io_slices(5);
+        let slices: Vec<&[u8]> = slices.iter().map(|s| &**s).collect();
+        assert_eq!(slices, [&b"hello"[..], b", wor", b"ld"]);
+
+        match req.operation().unwrap() {
+            Operation::Write(op, mut data) => {
+                assert_eq!(op.size() as usize, payload.len());
+                let mut buf = vec![];
+                data.read_to_end(&mut buf).unwrap();
+                assert_eq!(buf, payload);
+            }
+            _ => panic!("incorrect operation is returned"),
+        }
+    }
+
+    #[test]
+    fn poll_schedule_notify() {
+        let (kernel, session) = session_pair();
+
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_poll_in>()) as u32,
+            opcode: fuse_opcode::FUSE_POLL as u32,
+            unique: 2,
+            nodeid: 2,
+            ..Default::default()
+        };
+        let arg = fuse_poll_in {
+            fh: 1,
+            kh: 0xdead_beef,
+            flags: FUSE_POLL_SCHEDULE_NOTIFY,
+            events: libc::POLLIN as u32,
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(arg.as_bytes()),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+
+        // The file is not ready yet, so reply no events and remember the poll handle.
+        let req = session.next_request().unwrap().expect("no request");
+        let kh = match req.operation().unwrap() {
+            Operation::Poll(op) => {
+                assert!(op.schedule_notify());
+                op.kh().expect("no poll handle")
+            }
+            _ => panic!("incorrect operation is returned"),
+        };
+        req.reply(crate::reply::PollOut::default()).unwrap();
+
+        let header_len = mem::size_of::<fuse_out_header>();
+        let mut buf = [0u8; 64];
+        let len = (&kernel).read(&mut buf[..]).unwrap();
+        assert_eq!(len, header_len + mem::size_of::<fuse_poll_out>());
+        assert_eq!(buf[8..16], 2u64.to_ne_bytes(), "unique");
         assert_eq!(
-            buf[8..16],
-            b![0x1a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
-            "header.unique"
+            buf[header_len..header_len + 4],
+            0u32.to_ne_bytes(),
+            "revents"
         );
-        assert_eq!(buf[16..], *b"hello, this is a message.", "payload");
+
+        // The wakeup carries the same poll handle.
+        session.notifier().poll_wakeup(kh).unwrap();
+        let len = (&kernel).read(&mut buf[..]).unwrap();
+        assert_eq!(
+            len,
+            header_len + mem::size_of::<fuse_notify_poll_wakeup_out>()
+        );
+        assert_eq!(
+            buf[4..8],
+            (fuse_notify_code::FUSE_NOTIFY_POLL as i32).to_ne_bytes()
+        );
+        assert_eq!(buf[8..16], 0u64.to_ne_bytes(), "unique");
+        assert_eq!(
+            buf[header_len..header_len + 8],
+            0xdead_beefu64.to_ne_bytes()
+        );
+    }
+
+    #[test]
+    fn remount_on_abort() {
+        let mut config = KernelConfig::default();
+        config.mount_option("ro").max_write(MIN_MAX_WRITE);
+        let remount = Remount::new("/tmp/mnt".into(), config);
+        assert_eq!(remount.mountpoint(), Path::new("/tmp/mnt"));
+        assert_eq!(remount.mount_count(), 0);
+
+        // The kept configuration is used as is for each mount.
+        let copied = remount.config.clone();
+        assert_eq!(copied.mountopts.options, ["ro"]);
+        assert_eq!(copied.init_out.max_write, MIN_MAX_WRITE);
+
+        let session = |aborted: bool| {
+            let (kernel, session) = session_pair();
+            drop(kernel);
+            session.inner.aborted.store(aborted, Ordering::SeqCst);
+            session.inner.disconnected(&session.inner.conn);
+            session
+        };
+        assert!(remount.should_remount(&session(true)));
+        assert!(!remount.should_remount(&session(false)));
+    }
+
+    #[test]
+    fn notify_batch() {
+        let (kernel, session) = session_pair();
+        let mut batch = session.notifier().batch();
+
+        const N: u64 = 100;
+        for i in 0..N {
+            batch.inval_entry(1, format!("file{}", i));
+        }
+        batch.inval_inode(1, 0, -1).delete(1, 2, "foo");
+        assert_eq!(batch.len(), N as usize + 2);
+
+        // Nothing is sent until flushed.
+        kernel.set_nonblocking(true).unwrap();
+        let err = (&kernel).read(&mut [0u8; 64][..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        batch.flush().unwrap();
+        assert!(batch.is_empty());
+
+        let header_len = mem::size_of::<fuse_out_header>();
+        let recv = || {
+            let mut buf = vec![0u8; 256];
+            let len = (&kernel).read(&mut buf[..]).unwrap();
+            buf.truncate(len);
+            assert_eq!(buf[0..4], (len as u32).to_ne_bytes(), "len");
+            let code = i32::from_ne_bytes(buf[4..8].try_into().unwrap());
+            (code, buf.split_off(header_len))
+        };
+        for i in 0..N {
+            let (code, arg) = recv();
+            assert_eq!(code, fuse_notify_code::FUSE_NOTIFY_INVAL_ENTRY as i32);
+            let name = format!("file{}\0", i);
+            let arg_len = mem::size_of::<fuse_notify_inval_entry_out>();
+            assert_eq!(arg[0..8], 1u64.to_ne_bytes(), "parent");
+            assert_eq!(arg[8..12], (name.len() as u32 - 1).to_ne_bytes(), "namelen");
+            assert_eq!(arg[arg_len..], *name.as_bytes());
+        }
+        let (code, arg) = recv();
+        assert_eq!(code, fuse_notify_code::FUSE_NOTIFY_INVAL_INODE as i32);
+        assert_eq!(arg[16..24], (-1i64).to_ne_bytes(), "len");
+        let (code, arg) = recv();
+        assert_eq!(code, fuse_notify_code::FUSE_NOTIFY_DELETE as i32);
+        assert_eq!(arg[mem::size_of::<fuse_notify_delete_out>()..], *b"foo\0");
+
+        // The unsent notifications are flushed on drop.
+        batch.poll_wakeup(42);
+        drop(batch);
+        let (code, arg) = recv();
+        assert_eq!(code, fuse_notify_code::FUSE_NOTIFY_POLL as i32);
+        assert_eq!(arg[..], 42u64.to_ne_bytes());
+    }
+
+    #[test]
+    fn store_io_slices() {
+        let (kernel, session) = session_pair();
+        let notifier = session.notifier();
+
+        let chunks: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i; 100]).collect();
+        let slices: Vec<_> = chunks.iter().map(|chunk| IoSlice::new(chunk)).collect();
+        notifier.store(42, 4096, &slices[..]).unwrap();
+
+        let mut buf = [0u8; 1024];
+        let len = (&kernel).read(&mut buf[..]).unwrap();
+        let header_len = mem::size_of::<fuse_out_header>();
+        let arg_len = mem::size_of::<fuse_notify_store_out>();
+        assert_eq!(len, header_len + arg_len + 400);
+        assert_eq!(buf[0..4], (len as u32).to_ne_bytes(), "len");
+        assert_eq!(
+            buf[4..8],
+            (fuse_notify_code::FUSE_NOTIFY_STORE as i32).to_ne_bytes(),
+            "error"
+        );
+        let out = &buf[header_len..len];
+        assert_eq!(out[0..8], 42u64.to_ne_bytes(), "nodeid");
+        assert_eq!(out[8..16], 4096u64.to_ne_bytes(), "offset");
+        assert_eq!(out[16..20], 400u32.to_ne_bytes(), "size");
+        assert_eq!(out[arg_len..], chunks.concat()[..]);
+    }
+
+    #[test]
+    fn retrieve_end_to_end() {
+        let (kernel, session) = session_pair();
+        let notifier = session.notifier();
+
+        let retrieve = notifier.retrieve(42, 0, 8).unwrap();
+
+        // The notification sent to the kernel.
+        let mut buf = [0u8; 64];
+        let len = (&kernel).read(&mut buf[..]).unwrap();
+        assert_eq!(
+            len,
+            mem::size_of::<fuse_out_header>() + mem::size_of::<fuse_notify_retrieve_out>()
+        );
+        let out = &buf[mem::size_of::<fuse_out_header>()..len];
+        assert_eq!(out[0..8], retrieve.unique().to_ne_bytes(), "notify_unique");
+        assert_eq!(out[8..16], 42u64.to_ne_bytes(), "nodeid");
+        assert_eq!(out[24..28], 8u32.to_ne_bytes(), "size");
+
+        // The kernel replies with less data than requested.
+        let data = b"abcd";
+        let reply_in = fuse_notify_retrieve_in {
+            offset: 0,
+            size: data.len() as u32,
+            ..Default::default()
+        };
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>()
+                + mem::size_of::<fuse_notify_retrieve_in>()
+                + data.len()) as u32,
+            opcode: fuse_opcode::FUSE_NOTIFY_REPLY as u32,
+            unique: retrieve.unique(),
+            nodeid: 42,
+            ..Default::default()
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(reply_in.as_bytes()),
+                IoSlice::new(data),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+
+        // An ordinary request follows it.
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_getattr_in>()) as u32,
+            opcode: fuse_opcode::FUSE_GETATTR as u32,
+            unique: 100,
+            nodeid: 1,
+            ..Default::default()
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(fuse_getattr_in::default().as_bytes()),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+
+        // The NOTIFY_REPLY is consumed by the session and never surfaces as a request.
+        let req = session.next_request().unwrap().expect("no request");
+        assert_eq!(req.unique(), 100);
+
+        assert_eq!(retrieve.wait().unwrap(), data);
+    }
+
+    #[test]
+    fn auto_interrupt() {
+        let (kernel, session) = session_pair();
+        session.set_auto_interrupt(true);
+
+        let send_interrupt = |target: u64| {
+            let header = fuse_in_header {
+                len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_interrupt_in>())
+                    as u32,
+                opcode: fuse_opcode::FUSE_INTERRUPT as u32,
+                unique: target | 1,
+                ..Default::default()
+            };
+            let arg = fuse_interrupt_in { unique: target };
+            let written = (&kernel)
+                .write_vectored(&[
+                    IoSlice::new(header.as_bytes()),
+                    IoSlice::new(arg.as_bytes()),
+                ])
+                .unwrap();
+            assert_eq!(written, header.len as usize);
+        };
+
+        send_getattr(&kernel, 2);
+        let req = session.next_request().unwrap().expect("no request");
+        assert_eq!(req.unique(), 2);
+        assert!(!req.interrupted());
+
+        // The interrupt is consumed by the session and never surfaces as a request.
+        send_interrupt(2);
+        send_getattr(&kernel, 4);
+        let req2 = session.next_request().unwrap().expect("no request");
+        assert_eq!(req2.unique(), 4);
+        assert_eq!(recv_reply(&kernel), (2, libc::ECANCELED));
+        assert!(req.interrupted());
+
+        // The reply from the filesystem after the interrupt is discarded.
+        req.reply_error(libc::EIO).unwrap();
+        drop(req);
+        req2.reply_error(libc::ENOENT).unwrap();
+        assert_eq!(recv_reply(&kernel), (4, libc::ENOENT));
+
+        // The interrupt for a finished request is not answered.
+        drop(req2);
+        send_interrupt(4);
+
+        // The interrupt received before its target is applied when the target arrives.
+        send_interrupt(6);
+        send_getattr(&kernel, 6);
+        let req3 = session.next_request().unwrap().expect("no request");
+        assert_eq!(req3.unique(), 6);
+        assert!(req3.interrupted());
+        assert_eq!(recv_reply(&kernel), (6, libc::ECANCELED));
+        kernel.set_nonblocking(true).unwrap();
+        let err = (&kernel).read(&mut [0u8; 64][..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(session.inner.interrupts.state.lock().unwrap().pending, [4]);
+    }
+
+    #[test]
+    fn reply_timeout() {
+        let (kernel, session) = session_pair();
+        session.set_reply_timeout(Some(Duration::from_millis(50)));
+        session.set_reply_timeout_error(Some(libc::EIO));
+
+        // The request replied in time is no longer watched.
+        send_getattr(&kernel, 2);
+        let req = session.next_request().unwrap().expect("no request");
+        req.reply_error(libc::ENOENT).unwrap();
+        assert_eq!(recv_reply(&kernel), (2, libc::ENOENT));
+
+        // A deliberately slow handler.  The reply from the watchdog is waited
+        // for with a generous timeout rather than racing against the deadline.
+        send_getattr(&kernel, 4);
+        let req = session.next_request().unwrap().expect("no request");
+        set_read_timeout(&kernel, Duration::from_secs(10));
+        assert_eq!(recv_reply(&kernel), (4, libc::EIO));
+
+        // The late reply from the filesystem is discarded.
+        req.reply_error(libc::ENOENT).unwrap();
+        drop(req);
+        kernel.set_nonblocking(true).unwrap();
+        let err = (&kernel).read(&mut [0u8; 64][..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(session
+            .inner
+            .watchdog
+            .shared
+            .state
+            .lock()
+            .unwrap()
+            .pending
+            .is_empty());
+    }
+
+    #[test]
+    fn shutdown_inflight() {
+        let (kernel, session) = session_pair();
+
+        // The handlers wait until they are signaled.  The request received
+        // before enabling `auto_interrupt` is not tracked by the session.
+        let mut handlers = vec![];
+        for unique in &[6, 2, 4] {
+            send_getattr(&kernel, *unique);
+            let req = session.next_request().unwrap().expect("no request");
+            handlers.push(thread::spawn(move || {
+                while !req.interrupted() {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                req.reply_error(libc::ENOENT).unwrap();
+            }));
+            session.set_auto_interrupt(true);
+        }
+        send_getattr(&kernel, 8);
+        let req = session.next_request().unwrap().expect("no request");
+        req.reply_error(libc::ENOENT).unwrap();
+        assert_eq!(recv_reply(&kernel), (8, libc::ENOENT));
+        drop(req);
+
+        session.shutdown(Some(libc::EIO));
+        for handler in handlers {
+            handler.join().unwrap();
+        }
+
+        // The untracked request is replied by its handler.
+        let mut replies: Vec<_> = (0..3).map(|_| recv_reply(&kernel)).collect();
+        replies.sort_unstable();
+        assert_eq!(replies, [(2, libc::EIO), (4, libc::EIO), (6, libc::ENOENT)]);
+
+        // The other replies from the handlers are discarded, and no request is accepted.
+        send_getattr(&kernel, 10);
+        assert!(session.next_request().unwrap().is_none());
+        kernel.set_nonblocking(true).unwrap();
+        let err = (&kernel).read(&mut [0u8; 64][..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn shutdown_blocked() {
+        let (kernel, session) = session_pair();
+        let session = Arc::new(session);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let receiver = thread::spawn({
+            let session = session.clone();
+            move || {
+                tx.send(unsafe { libc::syscall(libc::SYS_gettid) }).unwrap();
+                session.next_request().unwrap().map(|req| req.unique())
+            }
+        });
+
+        // Wait until the receiver is blocked in `read`.
+        let tid = rx.recv().unwrap();
+        let stat = format!("/proc/self/task/{}/stat", tid);
+        loop {
+            let stat = std::fs::read_to_string(&stat).unwrap();
+            let state = stat[stat.rfind(')').unwrap() + 2..].chars().next();
+            if state == Some('S') {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // The request read after the shutdown is answered by the session.
+        session.shutdown(Some(libc::EIO));
+        send_getattr(&kernel, 2);
+        assert_eq!(receiver.join().unwrap(), None);
+        assert_eq!(recv_reply(&kernel), (2, libc::EIO));
+    }
+
+    #[test]
+    fn write_mismatched_bytes() {
+        struct Mismatched {
+            size: usize,
+            count: usize,
+            chunks: &'static [&'static [u8]],
+        }
+
+        impl Bytes for Mismatched {
+            fn size(&self) -> usize {
+                self.size
+            }
+
+            fn count(&self) -> usize {
+                self.count
+            }
+
+            fn fill_bytes<'a>(&'a self, dst: &mut dyn FillBytes<'a>) {
+                for chunk in self.chunks {
+                    dst.put(chunk);
+                }
+            }
+        }
+
+        let write = |bytes: Mismatched| {
+            let mut output = vec![];
+            let res = write_bytes(&mut output, bytes);
+            (res, output)
+        };
+
+        // Well-formed, in both of the small and large paths.
+        let (res, output) = write(Mismatched {
+            size: 6,
+            count: 2,
+            chunks: &[b"foo", b"bar"],
+        });
+        assert!(res.is_ok());
+        assert_eq!(output, b"foobar");
+        let (res, output) = write(Mismatched {
+            size: 5,
+            count: 5,
+            chunks: &[b"a", b"b", b"c", b"d", b"e"],
+        });
+        assert!(res.is_ok());
+        assert_eq!(output, b"abcde");
+
+        // The reported size is larger than the actual one.
+        let (res, output) = write(Mismatched {
+            size: 10,
+            count: 2,
+            chunks: &[b"foo", b"bar"],
+        });
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(output.is_empty());
+
+        // Fewer or more chunks than reported.
+        const CHUNKS: &[&[u8]] = &[b"fo", b"ob", b"ar"];
+        for &count in &[2, 4, 6] {
+            let (res, output) = write(Mismatched {
+                size: 6,
+                count,
+                chunks: CHUNKS,
+            });
+            assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+            assert!(output.is_empty());
+        }
+    }
+
+    #[cfg(feature = "nix")]
+    #[test]
+    fn reply_nix_errno() {
+        use nix::errno::Errno;
+
+        assert_eq!(errno_to_code(Errno::ENOENT), libc::ENOENT);
+        assert_eq!(errno_to_code(Errno::EACCES), libc::EACCES);
+        assert_eq!(errno_to_code(Errno::ENOSYS), libc::ENOSYS);
+        assert_eq!(errno_to_code(Errno::UnknownErrno), libc::EIO);
+
+        let (kernel, session) = session_pair();
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_getattr_in>()) as u32,
+            opcode: fuse_opcode::FUSE_GETATTR as u32,
+            unique: 2,
+            nodeid: 1,
+            ..Default::default()
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(fuse_getattr_in::default().as_bytes()),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+
+        let req = session.next_request().unwrap().expect("no request");
+        req.reply_errno(Errno::EEXIST).unwrap();
+
+        let mut buf = [0u8; 64];
+        let len = (&kernel).read(&mut buf[..]).unwrap();
+        assert_eq!(len, mem::size_of::<fuse_out_header>());
+        assert_eq!(buf[4..8], (-libc::EEXIST).to_ne_bytes(), "error");
+    }
+
+    #[test]
+    fn reply_twice() {
+        let (kernel, session) = session_pair();
+
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_getattr_in>()) as u32,
+            opcode: fuse_opcode::FUSE_GETATTR as u32,
+            unique: 2,
+            nodeid: 1,
+            ..Default::default()
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(fuse_getattr_in::default().as_bytes()),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+
+        let req = session.next_request().unwrap().expect("no request");
+        req.reply_error(libc::ENOENT).unwrap();
+
+        let err = req.reply(()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = req.reply_error(libc::EIO).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // Only the first reply reaches the kernel.
+        drop(session);
+        drop(req);
+        let mut buf = [0u8; 64];
+        let len = (&kernel).read(&mut buf[..]).unwrap();
+        assert_eq!(len, mem::size_of::<fuse_out_header>());
+        assert_eq!(buf[4..8], (-libc::ENOENT).to_ne_bytes());
+        assert_eq!((&kernel).read(&mut buf[..]).unwrap(), 0);
+    }
+
+    #[test]
+    fn compact_buffers() {
+        let (kernel, session) = session_pair();
+
+        // A tiny request holds the whole buffer by default,
+        send_getattr(&kernel, 2);
+        let req = session.next_request().unwrap().expect("no request");
+        assert!(req.arg.capacity() >= session.buffer_size() - mem::size_of::<fuse_in_header>());
+
+        // but only the required length when enabled.
+        session.set_compact_buffers(true);
+        let reqs: Vec<_> = (0..16)
+            .map(|i| {
+                send_getattr(&kernel, 4 + i * 2);
+                session.next_request().unwrap().expect("no request")
+            })
+            .collect();
+        for req in &reqs {
+            assert_eq!(req.arg.capacity(), mem::size_of::<fuse_getattr_in>());
+            assert!(matches!(req.operation().unwrap(), Operation::Getattr(..)));
+        }
+    }
+
+    #[test]
+    fn custom_buffer_size() {
+        let (kernel, conn) = conn_pair();
+
+        let mut init_out = default_init_out();
+        init_out.max_write = MIN_MAX_WRITE;
+        let session = Session::new(conn, init_out, 0);
+        let default_size = session.buffer_size();
+        assert_eq!(default_size, BUFFER_HEADER_SIZE + MIN_MAX_WRITE as usize);
+
+        let err = session.set_buffer_size(default_size - 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(session.buffer_size(), default_size);
+
+        session.set_buffer_size(default_size * 4).unwrap();
+        assert_eq!(session.buffer_size(), default_size * 4);
+
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_getattr_in>()) as u32,
+            opcode: fuse_opcode::FUSE_GETATTR as u32,
+            unique: 2,
+            nodeid: 1,
+            ..Default::default()
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(fuse_getattr_in::default().as_bytes()),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+
+        let req = session.next_request().unwrap().expect("no request");
+        assert_eq!(
+            req.arg.capacity(),
+            default_size * 4 - mem::size_of::<fuse_in_header>()
+        );
+        assert!(matches!(req.operation().unwrap(), Operation::Getattr(..)));
+    }
+
+    #[test]
+    fn join_handlers() {
+        let (kernel, session) = session_pair();
+        assert!(session.join_handlers(Some(Duration::from_millis(0))));
+
+        let finished = Arc::new(AtomicUsize::new(0));
+        let mut handlers = vec![];
+        for unique in &[2, 4, 6] {
+            send_getattr(&kernel, *unique);
+            let req = session.next_request().unwrap().expect("no request");
+            let finished = finished.clone();
+            handlers.push(thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                req.reply_error(libc::ENOENT).unwrap();
+                finished.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+
+        // The requests are counted without being tracked individually.
+        assert!(session
+            .inner
+            .interrupts
+            .state
+            .lock()
+            .unwrap()
+            .inflight
+            .is_empty());
+
+        assert!(!session.join_handlers(Some(Duration::from_millis(10))));
+        assert!(session.join_handlers(None));
+        assert_eq!(finished.load(Ordering::SeqCst), 3);
+
+        for handler in handlers {
+            handler.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn reply_cached_payload() {
+        let (kernel, session) = session_pair();
+
+        let mut attr_out = crate::reply::AttrOut::default();
+        attr_out.attr().ino(1);
+        attr_out.attr().mode(libc::S_IFDIR | 0o755);
+        attr_out.ttl(Duration::from_secs(60));
+        let cached: std::sync::Arc<[u8]> = crate::bytes::encode(&attr_out).into();
+        assert_eq!(cached.len(), mem::size_of::<fuse_attr_out>());
+
+        for &unique in &[2, 4] {
+            let header = fuse_in_header {
+                len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_getattr_in>()) as u32,
+                opcode: fuse_opcode::FUSE_GETATTR as u32,
+                unique,
+                nodeid: 1,
+                ..Default::default()
+            };
+            let written = (&kernel)
+                .write_vectored(&[
+                    IoSlice::new(header.as_bytes()),
+                    IoSlice::new(fuse_getattr_in::default().as_bytes()),
+                ])
+                .unwrap();
+            assert_eq!(written, header.len as usize);
+
+            let req = session.next_request().unwrap().expect("no request");
+            req.reply(cached.clone()).unwrap();
+
+            let mut buf = [0u8; 256];
+            let len = (&kernel).read(&mut buf[..]).unwrap();
+            let out_len = mem::size_of::<fuse_out_header>() + cached.len();
+            assert_eq!(len, out_len);
+            assert_eq!(
+                u32::from_ne_bytes(buf[0..4].try_into().unwrap()) as usize,
+                out_len
+            );
+            assert_eq!(i32::from_ne_bytes(buf[4..8].try_into().unwrap()), 0);
+            assert_eq!(u64::from_ne_bytes(buf[8..16].try_into().unwrap()), unique);
+            assert_eq!(&buf[mem::size_of::<fuse_out_header>()..len], &cached[..]);
+        }
+    }
+
+    #[test]
+    fn reply_xattr_size() {
+        let (kernel, session) = session_pair();
+
+        let value = b"user.value";
+        let reply = |unique: u64, size: u32| {
+            let name = b"user.foo\0";
+            let header = fuse_in_header {
+                len: (mem::size_of::<fuse_in_header>()
+                    + mem::size_of::<fuse_getxattr_in>()
+                    + name.len()) as u32,
+                opcode: fuse_opcode::FUSE_GETXATTR as u32,
+                unique,
+                nodeid: 1,
+                ..Default::default()
+            };
+            let arg = fuse_getxattr_in { size, padding: 0 };
+            let written = (&kernel)
+                .write_vectored(&[
+                    IoSlice::new(header.as_bytes()),
+                    IoSlice::new(arg.as_bytes()),
+                    IoSlice::new(&name[..]),
+                ])
+                .unwrap();
+            assert_eq!(written, header.len as usize);
+
+            let req = session.next_request().unwrap().expect("no request");
+            let size = match req.operation().unwrap() {
+                Operation::Getxattr(op) => op.size(),
+                _ => panic!("incorrect operation is returned"),
+            };
+            req.reply_xattr(size, &value[..]).unwrap();
+
+            let mut buf = [0u8; 256];
+            let len = (&kernel).read(&mut buf[..]).unwrap();
+            assert_eq!(u64::from_ne_bytes(buf[8..16].try_into().unwrap()), unique);
+            (
+                -i32::from_ne_bytes(buf[4..8].try_into().unwrap()),
+                buf[mem::size_of::<fuse_out_header>()..len].to_vec(),
+            )
+        };
+
+        // The size query is replied with the length of the value.
+        let (error, payload) = reply(2, 0);
+        assert_eq!(error, 0);
+        assert_eq!(payload.len(), mem::size_of::<fuse_getxattr_out>());
+        assert_eq!(payload[0..4], (value.len() as u32).to_ne_bytes());
+
+        // The value fits in the buffer.
+        let (error, payload) = reply(4, value.len() as u32);
+        assert_eq!(error, 0);
+        assert_eq!(payload, &value[..]);
+
+        // The buffer is too small for the value.
+        let (error, payload) = reply(6, value.len() as u32 - 1);
+        assert_eq!(error, libc::ERANGE);
+        assert!(payload.is_empty());
+    }
+
+    #[test]
+    fn header_len_too_small() {
+        let (kernel, session) = session_pair();
+
+        // The header declares a truncated argument of `getattr`.
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + 8) as u32,
+            opcode: fuse_opcode::FUSE_GETATTR as u32,
+            unique: 2,
+            nodeid: 1,
+            ..Default::default()
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(&fuse_getattr_in::default().as_bytes()[..8]),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+
+        let req = session.next_request().unwrap().expect("no request");
+        let err = req.operation().unwrap_err();
+        assert_eq!(err.opcode(), Some(fuse_opcode::FUSE_GETATTR as u32));
+        assert!(
+            err.to_string().contains("expected 16 bytes but got 8"),
+            "{}",
+            err
+        );
+        drop(req);
+
+        // The bytes beyond the declared length are never taken as the argument.
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(fuse_getattr_in::default().as_bytes()),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize + 8);
+
+        match session.next_request() {
+            Err(Error::Decode(..)) => (),
+            Err(err) => panic!("unexpected error: {:?}", err),
+            Ok(..) => panic!("the oversized message is accepted"),
+        }
+    }
+
+    #[test]
+    fn session_capabilities() {
+        let init_in = fuse_init_in {
+            major: 7,
+            minor: 38,
+            max_readahead: 40,
+            flags: INIT_FLAGS_MASK
+                | FUSE_INIT_EXT
+                | FUSE_SPLICE_READ
+                | FUSE_IOCTL_DIR
+                | FUSE_NO_OPEN_SUPPORT,
+            flags2: (FUSE_PASSTHROUGH >> 32) as u32,
+            ..Default::default()
+        };
+        let mut init_out = default_init_out();
+        init_out.flags = FUSE_ASYNC_READ | FUSE_WRITEBACK_CACHE;
+        init_out.flags2 = (FUSE_PASSTHROUGH >> 32) as u32;
+        let (_kernel, session) = init_pair(&init_in, init_out);
+
+        // The read-only flags offered by the kernel are not reported as negotiated.
+        let caps: Vec<_> = session.capabilities().collect();
+        assert_eq!(
+            caps,
+            [
+                ("FUSE_ASYNC_READ", FUSE_ASYNC_READ as u64),
+                ("FUSE_BIG_WRITES", FUSE_BIG_WRITES as u64),
+                ("FUSE_WRITEBACK_CACHE", FUSE_WRITEBACK_CACHE as u64),
+                ("FUSE_INIT_EXT", FUSE_INIT_EXT as u64),
+                ("FUSE_PASSTHROUGH", FUSE_PASSTHROUGH),
+            ]
+        );
+        assert_eq!(
+            caps.iter().fold(0, |acc, &(_, flag)| acc | flag),
+            session.flags()
+        );
+
+        // Every flag in the mask has a name.
+        let flags = INIT_FLAGS_MASK as u64 | (INIT_FLAGS2_MASK as u64) << 32;
+        for i in 0..64 {
+            let flag = 1u64 << i;
+            if flags & flag != 0 {
+                assert!(INIT_FLAG_NAMES.iter().any(|&(_, f)| f == flag));
+            }
+        }
+    }
+
+    #[test]
+    fn protocol_minor_negotiated() {
+        let (_kernel, conn) = conn_pair();
+
+        let mut init_out = default_init_out();
+        init_out.minor = 26;
+        let session = Session::new(conn, init_out, 0);
+        assert_eq!(session.protocol_minor(), 26);
+    }
+
+    #[test]
+    fn buffer_size_max_pages() {
+        let (kernel, conn) = conn_pair();
+
+        // `max_pages` pages are larger than `max_write`.
+        let mut init_out = default_init_out();
+        init_out.flags |= FUSE_MAX_PAGES;
+        init_out.max_write = MIN_MAX_WRITE + 1;
+        init_out.max_pages = 32;
+        let max_payload = init_out.max_pages as usize * pagesize();
+        let session = Session::new(conn, init_out, 0);
+        assert_eq!(session.buffer_size(), BUFFER_HEADER_SIZE + max_payload);
+
+        let err = session.set_buffer_size(BUFFER_HEADER_SIZE + MIN_MAX_WRITE as usize + 1);
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        // A write filling all of the pages is received without truncation.
+        let data = vec![0xa5u8; max_payload];
+        let write_in = fuse_write_in {
+            fh: 1,
+            size: max_payload as u32,
+            ..Default::default()
+        };
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_write_in>() + max_payload)
+                as u32,
+            opcode: fuse_opcode::FUSE_WRITE as u32,
+            unique: 2,
+            nodeid: 2,
+            ..Default::default()
+        };
+        let written = (&kernel)
+            .write_vectored(&[
+                IoSlice::new(header.as_bytes()),
+                IoSlice::new(write_in.as_bytes()),
+                IoSlice::new(&data[..]),
+            ])
+            .unwrap();
+        assert_eq!(written, header.len as usize);
+
+        let req = session.next_request().unwrap().expect("no request");
+        match req.operation().unwrap() {
+            Operation::Write(op, mut data) => {
+                assert_eq!(op.size() as usize, max_payload);
+                let mut received = vec![];
+                data.read_to_end(&mut received).unwrap();
+                assert_eq!(received.len(), max_payload);
+                assert!(received.iter().all(|&b| b == 0xa5));
+            }
+            _ => panic!("incorrect operation is returned"),
+        }
+    }
+
+    #[test]
+    fn compact_forgets() {
+        let (kernel, session) = session_pair();
+
+        let forgets: Vec<_> = (0..64)
+            .map(|i| fuse_forget_one {
+                nodeid: 2 + i,
+                nlookup: 1,
+            })
+            .collect();
+        let arg = fuse_batch_forget_in {
+            count: forgets.len() as u32,
+            ..Default::default()
+        };
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>()
+                + mem::size_of::<fuse_batch_forget_in>()
+                + forgets.as_bytes().len()) as u32,
+            opcode: fuse_opcode::FUSE_BATCH_FORGET as u32,
+            unique: 0,
+            ..Default::default()
+        };
+
+        // The buffers of forget requests are compacted even if disabled.
+        let reqs: Vec<_> = (0..16)
+            .map(|_| {
+                let written = (&kernel)
+                    .write_vectored(&[
+                        IoSlice::new(header.as_bytes()),
+                        IoSlice::new(arg.as_bytes()),
+                        IoSlice::new(forgets.as_bytes()),
+                    ])
+                    .unwrap();
+                assert_eq!(written, header.len as usize);
+                session.next_request().unwrap().expect("no request")
+            })
+            .collect();
+        for req in &reqs {
+            assert_eq!(
+                req.arg.capacity(),
+                header.len as usize - mem::size_of::<fuse_in_header>()
+            );
+            match req.operation().unwrap() {
+                Operation::Forget(forgets) => assert_eq!(forgets.as_ref().len(), 64),
+                _ => panic!("incorrect operation is returned"),
+            }
+        }
+    }
+
+    #[test]
+    fn exit_reason() {
+        let (_kernel, session) = session_pair();
+        assert_eq!(session.exit_reason(), None);
+        assert!(
+            session.abort().is_err(),
+            "the mock connection is not mounted"
+        );
+
+        // The device returns ENODEV once the connection is closed.
+        // The mock connection has no mountpoint to be checked.
+        session.inner.disconnected(&session.inner.conn);
+        assert_eq!(session.exit_reason(), Some(ExitReason::Closed));
+
+        // The reason is recorded only for the first time.
+        session.inner.aborted.store(true, Ordering::SeqCst);
+        session.inner.disconnected(&session.inner.conn);
+        assert_eq!(session.exit_reason(), Some(ExitReason::Closed));
+
+        // The connection aborted by `Session::abort`.
+        session.inner.exit_reason.lock().unwrap().take();
+        session.inner.disconnected(&session.inner.conn);
+        assert_eq!(session.exit_reason(), Some(ExitReason::Aborted));
+    }
+
+    #[test]
+    fn retrieve_malformed_and_aborted() {
+        let retrieves = Retrieves::default();
+
+        let malformed = retrieves.register(1);
+        let aborted = retrieves.register(2);
+
+        assert!(retrieves.complete(1, &[0u8; 4]));
+        assert!(!retrieves.complete(3, &[]), "unknown unique ID");
+        retrieves.abort_all();
+
+        let err = malformed.wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = aborted.wait().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENODEV));
     }
 }
@@ -8,7 +8,7 @@
 #![deny(clippy::unimplemented, clippy::todo)]
 
 use polyfuse::{
-    reply::{AttrOut, EntryOut, FileAttr, ReaddirOut},
+    reply::{AttrOut, EntryOut, ReaddirOut},
     KernelConfig, Notifier, Operation, Request, Session,
 };
 
@@ -145,8 +145,7 @@ impl Heartbeat {
     }
 
     fn handle_request(&self, req: &Request) -> Result<()> {
-        let span = tracing::debug_span!("handle_request", unique = req.unique());
-        let _enter = span.enter();
+        let _enter = req.span().enter();
 
         let op = req.operation()?;
         tracing::debug!(?op);
@@ -157,9 +156,7 @@ impl Heartbeat {
                     let mut current = self.current.lock().unwrap();
 
                     if op.name().as_bytes() == current.filename.as_bytes() {
-                        let mut out = EntryOut::default();
-                        out.ino(self.file_attr.st_ino);
-                        fill_attr(out.attr(), &self.file_attr);
+                        let mut out = EntryOut::from_stat(self.file_attr.st_ino, &self.file_attr);
                         out.ttl_entry(self.ttl);
                         out.ttl_attr(self.ttl);
 
@@ -190,7 +187,7 @@ impl Heartbeat {
                 };
 
                 let mut out = AttrOut::default();
-                fill_attr(out.attr(), attr);
+                out.attr().stat(attr);
                 out.ttl(self.ttl);
 
                 req.reply(out)?;
@@ -207,7 +204,7 @@ impl Heartbeat {
                     if op.offset() == 0 {
                         let current = self.current.lock().unwrap();
 
-                        let mut out = ReaddirOut::new(op.size() as usize);
+                        let mut out = ReaddirOut::for_op(&op);
                         out.entry(current.filename.as_ref(), FILE_INO, 0, 1);
                         req.reply(out)?;
                     } else {
@@ -223,18 +220,3 @@ impl Heartbeat {
         Ok(())
     }
 }
-
-fn fill_attr(attr: &mut FileAttr, st: &libc::stat) {
-    attr.ino(st.st_ino);
-    attr.size(st.st_size as u64);
-    attr.mode(st.st_mode);
-    attr.nlink(st.st_nlink as u32);
-    attr.uid(st.st_uid);
-    attr.gid(st.st_gid);
-    attr.rdev(st.st_rdev as u32);
-    attr.blksize(st.st_blksize as u32);
-    attr.blocks(st.st_blocks as u64);
-    attr.atime(Duration::new(st.st_atime as u64, st.st_atime_nsec as u32));
-    attr.mtime(Duration::new(st.st_mtime as u64, st.st_mtime_nsec as u32));
-    attr.ctime(Duration::new(st.st_ctime as u64, st.st_ctime_nsec as u32));
-}
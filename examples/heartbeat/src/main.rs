@@ -8,18 +8,16 @@
 #![deny(clippy::unimplemented)]
 
 use polyfuse::{
-    reply::{AttrOut, FileAttr, OpenOut},
+    reply::{AttrOut, OpenOut},
     KernelConfig, Notifier, Operation, Session,
 };
 
 use anyhow::{anyhow, ensure, Context as _, Result};
 use chrono::Local;
 use std::{
-    collections::HashMap,
-    io::{self, prelude::*},
-    mem,
+    io, mem,
     path::PathBuf,
-    sync::{mpsc, Arc, Mutex},
+    sync::{Arc, Mutex},
     time::Duration,
 };
 
@@ -78,8 +76,7 @@ fn main() -> Result<()> {
         let heartbeat = heartbeat.clone();
 
         std::thread::spawn(move || -> Result<()> {
-            let span = tracing::debug_span!("handle request", unique = req.unique());
-            let _enter = span.enter();
+            let _enter = req.span().enter();
 
             let op = req.operation()?;
             tracing::debug!(?op);
@@ -89,7 +86,7 @@ fn main() -> Result<()> {
                     ROOT_INO => {
                         let inner = heartbeat.inner.lock().unwrap();
                         let mut out = AttrOut::default();
-                        fill_attr(out.attr(), &inner.attr);
+                        out.attr().stat(&inner.attr);
                         req.reply(out)?;
                     }
                     _ => req.reply_error(libc::ENOENT)?,
@@ -118,15 +115,6 @@ fn main() -> Result<()> {
                     }
                     _ => req.reply_error(libc::ENOENT)?,
                 },
-                Operation::NotifyReply(op, mut data) => {
-                    let mut retrieves = heartbeat.retrieves.lock().unwrap();
-                    if let Some(tx) = retrieves.remove(&op.unique()) {
-                        let mut buf = vec![0u8; op.size() as usize];
-                        data.read_exact(&mut buf)?;
-                        tx.send(buf).unwrap();
-                    }
-                }
-
                 _ => req.reply_error(libc::ENOSYS)?,
             }
 
@@ -145,7 +133,6 @@ enum NotifyKind {
 
 struct Heartbeat {
     inner: Mutex<Inner>,
-    retrieves: Mutex<HashMap<u64, mpsc::Sender<Vec<u8>>>>,
 }
 
 struct Inner {
@@ -164,7 +151,6 @@ impl Heartbeat {
 
         Self {
             inner: Mutex::new(Inner { content, attr }),
-            retrieves: Mutex::default(),
         }
     }
 
@@ -185,13 +171,7 @@ impl Heartbeat {
         // To check if the cache is updated correctly, pull the
         // content from the kernel using notify_retrieve.
         tracing::info!("send notify_retrieve");
-        let data = {
-            // FIXME: choose appropriate atomic ordering.
-            let unique = notifier.retrieve(ROOT_INO, 0, 1024)?;
-            let (tx, rx) = mpsc::channel();
-            self.retrieves.lock().unwrap().insert(unique, tx);
-            rx.recv().unwrap()
-        };
+        let data = notifier.retrieve(ROOT_INO, 0, 1024)?.wait()?;
         tracing::info!("--> content={:?}", data);
 
         if data[..content.len()] != *content.as_bytes() {
@@ -207,18 +187,3 @@ impl Heartbeat {
         Ok(())
     }
 }
-
-fn fill_attr(attr: &mut FileAttr, st: &libc::stat) {
-    attr.ino(st.st_ino);
-    attr.size(st.st_size as u64);
-    attr.mode(st.st_mode);
-    attr.nlink(st.st_nlink as u32);
-    attr.uid(st.st_uid);
-    attr.gid(st.st_gid);
-    attr.rdev(st.st_rdev as u32);
-    attr.blksize(st.st_blksize as u32);
-    attr.blocks(st.st_blocks as u64);
-    attr.atime(Duration::new(st.st_atime as u64, st.st_atime_nsec as u32));
-    attr.mtime(Duration::new(st.st_mtime as u64, st.st_mtime_nsec as u32));
-    attr.ctime(Duration::new(st.st_ctime as u64, st.st_ctime_nsec as u32));
-}
@@ -143,27 +143,16 @@ impl Hello {
         req.reply(data)
     }
 
-    fn dir_entries(&self) -> impl Iterator<Item = (u64, &DirEntry)> + '_ {
-        self.entries.iter().enumerate().map(|(i, ent)| {
-            let offset = (i + 1) as u64;
-            (offset, ent)
-        })
-    }
-
     fn readdir(&self, req: &Request, op: op::Readdir<'_>) -> io::Result<()> {
         if op.ino() != ROOT_INO {
             return req.reply_error(libc::ENOTDIR);
         }
 
-        let mut out = ReaddirOut::new(op.size() as usize);
+        let mut out = ReaddirOut::for_op(&op);
+        out.resume_from(op.offset());
 
-        for (i, entry) in self.dir_entries().skip(op.offset() as usize) {
-            let full = out.entry(
-                entry.name.as_ref(), //
-                entry.ino,
-                entry.typ,
-                i + 1,
-            );
+        for entry in &self.entries {
+            let full = out.push(entry.name.as_ref(), entry.ino, entry.typ);
             if full {
                 break;
             }
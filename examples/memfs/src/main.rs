@@ -3,7 +3,7 @@
 
 use polyfuse::{
     op,
-    reply::{AttrOut, EntryOut, FileAttr, OpenOut, ReaddirOut, WriteOut, XattrOut},
+    reply::{AttrOut, EntryOut, OpenOut, ReaddirOut, WriteOut},
     KernelConfig, Operation, Request, Session,
 };
 
@@ -36,8 +36,7 @@ fn main() -> Result<()> {
     let mut fs = MemFS::new();
 
     while let Some(req) = session.next_request()? {
-        let span = tracing::debug_span!("handle_request", unique = req.unique());
-        let _enter = span.enter();
+        let _enter = req.span().enter();
 
         fs.handle_request(&req)?;
     }
@@ -312,9 +311,7 @@ impl MemFS {
             .unwrap_or_else(|| unreachable!());
         child.refcount += 1;
 
-        let mut out = EntryOut::default();
-        out.ino(child_ino);
-        fill_attr(out.attr(), &child.attr);
+        let mut out = EntryOut::from_stat(child_ino, &child.attr);
         out.ttl_entry(self.ttl);
 
         req.reply(out)
@@ -340,7 +337,7 @@ impl MemFS {
         };
 
         let mut out = AttrOut::default();
-        fill_attr(out.attr(), &inode.attr);
+        out.attr().stat(&inode.attr);
         out.ttl(self.ttl);
 
         req.reply(out)
@@ -352,12 +349,22 @@ impl MemFS {
             None => return req.reply_error(libc::ENOENT),
         };
 
-        fn to_duration(t: op::SetAttrTime) -> Duration {
-            match t {
-                op::SetAttrTime::Timespec(ts) => ts,
-                _ => SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap(),
+        fn to_timespec(t: op::SetAttrTime) -> (i64, i64) {
+            let (ts, before_epoch) = match t {
+                op::SetAttrTime::Timespec(ts) => (ts, false),
+                op::SetAttrTime::BeforeEpoch(ts) => (ts, true),
+                _ => (
+                    SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap(),
+                    false,
+                ),
+            };
+            let (secs, nsecs) = (ts.as_secs() as i64, ts.subsec_nanos() as i64);
+            match (before_epoch, nsecs) {
+                (false, _) => (secs, nsecs),
+                (true, 0) => (-secs, 0),
+                (true, nsecs) => (-secs - 1, 1_000_000_000 - nsecs),
             }
         }
 
@@ -374,14 +381,14 @@ impl MemFS {
             inode.attr.st_size = size as libc::off_t;
         }
         if let Some(atime) = op.atime() {
-            let atime = to_duration(atime);
-            inode.attr.st_atime = atime.as_secs() as i64;
-            inode.attr.st_atime_nsec = atime.subsec_nanos() as u64 as i64;
+            let (secs, nsecs) = to_timespec(atime);
+            inode.attr.st_atime = secs;
+            inode.attr.st_atime_nsec = nsecs;
         }
         if let Some(mtime) = op.mtime() {
-            let mtime = to_duration(mtime);
-            inode.attr.st_mtime = mtime.as_secs() as i64;
-            inode.attr.st_mtime_nsec = mtime.subsec_nanos() as u64 as i64;
+            let (secs, nsecs) = to_timespec(mtime);
+            inode.attr.st_mtime = secs;
+            inode.attr.st_mtime_nsec = nsecs;
         }
         if let Some(ctime) = op.ctime() {
             inode.attr.st_ctime = ctime.as_secs() as i64;
@@ -389,7 +396,7 @@ impl MemFS {
         }
 
         let mut out = AttrOut::default();
-        fill_attr(out.attr(), &inode.attr);
+        out.attr().stat(&inode.attr);
         out.ttl(self.ttl);
 
         req.reply(out)
@@ -443,7 +450,7 @@ impl MemFS {
             None => return req.reply_error(libc::EINVAL),
         };
 
-        let mut out = ReaddirOut::new(op.size() as usize);
+        let mut out = ReaddirOut::for_op(&op);
 
         for entry in dir.entries.iter().skip(op.offset() as usize) {
             if out.entry(&entry.name, entry.ino, entry.typ, entry.off) {
@@ -536,9 +543,7 @@ impl MemFS {
         let inode_entry = self.inodes.vacant_entry().expect("inode number conflict");
         let inode = f(&inode_entry);
 
-        let mut out = EntryOut::default();
-        out.ino(inode_entry.ino());
-        fill_attr(out.attr(), &inode.attr);
+        let mut out = EntryOut::from_stat(inode_entry.ino(), &inode.attr);
         out.ttl_entry(self.ttl);
         req.reply(out)?;
 
@@ -574,9 +579,7 @@ impl MemFS {
             }
         }
 
-        let mut out = EntryOut::default();
-        out.ino(op.ino());
-        fill_attr(out.attr(), &inode.attr);
+        let mut out = EntryOut::from_stat(op.ino(), &inode.attr);
         out.ttl_entry(self.ttl);
 
         req.reply(out)
@@ -694,19 +697,7 @@ impl MemFS {
             None => return req.reply_error(libc::ENODATA),
         };
 
-        match op.size() {
-            0 => {
-                let mut out = XattrOut::default();
-                out.size(value.len() as u32);
-                req.reply(out)
-            }
-            size => {
-                if value.len() as u32 > size {
-                    return req.reply_error(libc::ERANGE);
-                }
-                req.reply(value)
-            }
-        }
+        req.reply_xattr(op.size(), value)
     }
 
     fn do_setxattr(&self, req: &Request, op: op::Setxattr<'_>) -> io::Result<()> {
@@ -750,30 +741,12 @@ impl MemFS {
             None => return req.reply_error(libc::ENOENT),
         };
 
-        match op.size() {
-            0 => {
-                let total_len = inode.xattrs.keys().map(|name| name.len() as u32 + 1).sum();
-                let mut out = XattrOut::default();
-                out.size(total_len);
-                req.reply(out)
-            }
-
-            size => {
-                let mut total_len = 0;
-                let names = inode.xattrs.keys().fold(OsString::new(), |mut acc, name| {
-                    acc.push(name);
-                    acc.push("\0");
-                    total_len += name.len() as u32 + 1;
-                    acc
-                });
-
-                if total_len > size {
-                    return req.reply_error(libc::ERANGE);
-                }
-
-                req.reply(names)
-            }
-        }
+        let names = inode.xattrs.keys().fold(OsString::new(), |mut acc, name| {
+            acc.push(name);
+            acc.push("\0");
+            acc
+        });
+        req.reply_xattr(op.size(), names)
     }
 
     fn do_removexattr(&self, req: &Request, op: op::Removexattr<'_>) -> io::Result<()> {
@@ -841,18 +814,3 @@ impl MemFS {
         req.reply(out)
     }
 }
-
-fn fill_attr(attr: &mut FileAttr, st: &libc::stat) {
-    attr.ino(st.st_ino);
-    attr.size(st.st_size as u64);
-    attr.mode(st.st_mode);
-    attr.nlink(st.st_nlink as u32);
-    attr.uid(st.st_uid);
-    attr.gid(st.st_gid);
-    attr.rdev(st.st_rdev as u32);
-    attr.blksize(st.st_blksize as u32);
-    attr.blocks(st.st_blocks as u64);
-    attr.atime(Duration::new(st.st_atime as u64, st.st_atime_nsec as u32));
-    attr.mtime(Duration::new(st.st_mtime as u64, st.st_mtime_nsec as u32));
-    attr.ctime(Duration::new(st.st_ctime as u64, st.st_ctime_nsec as u32));
-}
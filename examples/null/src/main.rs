@@ -0,0 +1,123 @@
+use polyfuse::{
+    op,
+    reply::{AttrOut, OpenOut, WriteOut},
+    KernelConfig, Operation, Request, Session,
+};
+
+use anyhow::{ensure, Context as _, Result};
+use std::{io, path::PathBuf, thread, time::Duration};
+
+const FILE_SIZE: u64 = 1 << 40;
+
+fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let mut args = pico_args::Arguments::from_env();
+
+    let workers: usize = args.opt_value_from_str("--workers")?.unwrap_or(0);
+
+    let mountpoint: PathBuf = args.free_from_str()?.context("missing mountpoint")?;
+    ensure!(mountpoint.is_file(), "mountpoint must be a regular file");
+
+    let session = Session::mount(mountpoint, KernelConfig::default())?;
+
+    // The written data never exceeds the negotiated `max_write`,
+    // so the scratch buffer of each worker can be sized up front.
+    let max_write = session.max_write() as usize;
+
+    // Spawn the additional workers, each of which reads the requests
+    // from its own channel cloned from the session.
+    let mut handles = Vec::with_capacity(workers);
+    for _ in 0..workers {
+        let channel = session.clone_channel()?;
+        handles.push(thread::spawn(move || -> Result<()> {
+            let mut buf = vec![0u8; max_write];
+            while let Some(req) = channel.next_request()? {
+                handle(&req, &mut buf)?;
+            }
+            Ok(())
+        }));
+    }
+
+    let mut buf = vec![0u8; max_write];
+    while let Some(req) = session.next_request()? {
+        handle(&req, &mut buf)?;
+    }
+
+    for handle in handles {
+        handle.join().expect("worker thread panicked")?;
+    }
+
+    Ok(())
+}
+
+fn handle(req: &Request, buf: &mut [u8]) -> Result<()> {
+    match req.operation()? {
+        Operation::Getattr(op) => getattr(req, op)?,
+        Operation::Open(op) => open(req, op)?,
+        Operation::Read(op) => read(req, op)?,
+        Operation::Write(op, data) => write(req, op, data, buf)?,
+        _ => req.reply_error(libc::ENOSYS)?,
+    }
+    Ok(())
+}
+
+fn getattr(req: &Request, op: op::Getattr<'_>) -> io::Result<()> {
+    if op.ino() != 1 {
+        return req.reply_error(libc::ENOENT);
+    }
+
+    let mut out = AttrOut::default();
+    out.attr().ino(1);
+    out.attr().mode(libc::S_IFREG | 0o666);
+    out.attr().size(FILE_SIZE);
+    out.attr().nlink(1);
+    out.attr().uid(unsafe { libc::getuid() });
+    out.attr().gid(unsafe { libc::getgid() });
+    out.ttl(Duration::from_secs(1));
+
+    req.reply(out)
+}
+
+fn open(req: &Request, op: op::Open<'_>) -> io::Result<()> {
+    if op.ino() != 1 {
+        return req.reply_error(libc::ENOENT);
+    }
+
+    // Bypass the page cache so that every read reaches the filesystem.
+    let mut out = OpenOut::default();
+    out.direct_io(true);
+
+    req.reply(out)
+}
+
+fn read(req: &Request, op: op::Read<'_>) -> io::Result<()> {
+    if op.ino() != 1 {
+        return req.reply_error(libc::ENOENT);
+    }
+
+    let remaining = FILE_SIZE.saturating_sub(op.offset());
+    let size = std::cmp::min(op.size() as u64, remaining) as usize;
+
+    req.reply(vec![0u8; size])
+}
+
+fn write(
+    req: &Request,
+    op: op::Write<'_>,
+    mut data: impl io::Read,
+    buf: &mut [u8],
+) -> io::Result<()> {
+    if op.ino() != 1 {
+        return req.reply_error(libc::ENOENT);
+    }
+
+    // Discard the written data, as `/dev/null` does.
+    let size = op.size() as usize;
+    data.read_exact(&mut buf[..size])?;
+
+    let mut out = WriteOut::default();
+    out.size(op.size());
+
+    req.reply(out)
+}
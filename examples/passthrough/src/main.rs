@@ -8,7 +8,7 @@ use polyfuse::{
     reply::{
         AttrOut, EntryOut, FileAttr, OpenOut, ReaddirOut, Statfs, StatfsOut, WriteOut, XattrOut,
     },
-    KernelConfig, Operation, Session,
+    Data, KernelConfig, Operation, Session,
 };
 
 use anyhow::{ensure, Context as _, Result};
@@ -20,7 +20,7 @@ use std::{
     ffi::{OsStr, OsString},
     fmt::Debug,
     fs::{File, OpenOptions},
-    io::{self, prelude::*, BufRead},
+    io::{self, prelude::*},
     os::unix::prelude::*,
     path::PathBuf,
     sync::{Arc, Mutex},
@@ -62,11 +62,19 @@ fn main() -> Result<()> {
     let fs = Arc::new(Passthrough::new(source, timeout)?);
 
     while let Some(req) = session.next_request()? {
+        // The forget requests are processed inline, since they are never replied
+        // and are too frequent to spawn a thread for each.
+        if let Operation::Forget(forgets) = req.operation()? {
+            for forget in forgets.as_ref() {
+                fs.forget_one(forget.ino(), forget.nlookup());
+            }
+            continue;
+        }
+
         let fs = fs.clone();
 
         std::thread::spawn(move || -> Result<()> {
-            let span = tracing::debug_span!("handle_request", unique = req.unique());
-            let _enter = span.enter();
+            let _enter = req.span().enter();
 
             let op = req.operation()?;
             tracing::debug!(?op);
@@ -89,11 +97,6 @@ fn main() -> Result<()> {
 
             match op {
                 Operation::Lookup(op) => try_reply!(fs.do_lookup(op.parent(), op.name())),
-                Operation::Forget(forgets) => {
-                    for forget in forgets.as_ref() {
-                        fs.forget_one(forget.ino(), forget.nlookup());
-                    }
-                }
                 Operation::Getattr(op) => try_reply!(fs.do_getattr(&op)),
                 Operation::Setattr(op) => try_reply!(fs.do_setattr(&op)),
                 Operation::Readlink(op) => try_reply!(fs.do_readlink(&op)),
@@ -132,6 +135,7 @@ fn main() -> Result<()> {
                 Operation::Fsyncdir(op) => try_reply!(fs.do_fsyncdir(&op)),
                 Operation::Releasedir(op) => try_reply!(fs.do_releasedir(&op)),
 
+                Operation::Create(op) => try_reply!(fs.do_create(&op)),
                 Operation::Open(op) => try_reply!(fs.do_open(&op)),
                 Operation::Read(op) => try_reply!(fs.do_read(&op)),
                 Operation::Write(op, data) => try_reply!(fs.do_write(&op, data)),
@@ -336,6 +340,16 @@ impl Passthrough {
                     tv_sec: ts.as_secs() as i64,
                     tv_nsec: ts.subsec_nanos() as u64 as i64,
                 },
+                Some(op::SetAttrTime::BeforeEpoch(ts)) if ts.subsec_nanos() == 0 => {
+                    libc::timespec {
+                        tv_sec: -(ts.as_secs() as i64),
+                        tv_nsec: 0,
+                    }
+                }
+                Some(op::SetAttrTime::BeforeEpoch(ts)) => libc::timespec {
+                    tv_sec: -(ts.as_secs() as i64) - 1,
+                    tv_nsec: 1_000_000_000 - ts.subsec_nanos() as i64,
+                },
                 _ => libc::timespec {
                     tv_sec: 0,
                     tv_nsec: libc::UTIME_OMIT,
@@ -516,7 +530,7 @@ impl Passthrough {
         let read_dir = &mut *read_dir.lock().unwrap();
         read_dir.seek(op.offset());
 
-        let mut out = ReaddirOut::new(op.size() as usize);
+        let mut out = ReaddirOut::for_op(op);
         for entry in read_dir {
             let entry = entry?;
             if out.entry(&entry.name, entry.ino, entry.typ, entry.off) {
@@ -545,27 +559,35 @@ impl Passthrough {
         Ok(())
     }
 
+    fn do_create(&self, op: &op::Create<'_>) -> io::Result<(EntryOut, OpenOut)> {
+        let file = {
+            let inodes = self.inodes.lock().unwrap();
+            let parent = inodes.get(op.parent()).ok_or_else(no_entry)?;
+            let parent = parent.lock().unwrap();
+
+            open_options(op.open_flags())
+                .create(true)
+                .mode(op.mode() & !libc::S_IFMT)
+                .open(parent.fd.procname().join(op.name()))?
+        };
+
+        // The lookup count of the created inode is incremented here as well as `lookup`,
+        // and will be decremented by the subsequent `forget`.
+        let entry = self.do_lookup(op.parent(), op.name())?;
+        let fh = self.opened_files.insert(Mutex::new(file));
+
+        let mut out = OpenOut::default();
+        out.fh(fh);
+
+        Ok((entry, out))
+    }
+
     fn do_open(&self, op: &op::Open<'_>) -> io::Result<OpenOut> {
         let inodes = self.inodes.lock().unwrap();
         let inode = inodes.get(op.ino()).ok_or_else(no_entry)?;
         let inode = inode.lock().unwrap();
 
-        let mut options = OpenOptions::new();
-        match (op.flags() & 0x03) as i32 {
-            libc::O_RDONLY => {
-                options.read(true);
-            }
-            libc::O_WRONLY => {
-                options.write(true);
-            }
-            libc::O_RDWR => {
-                options.read(true).write(true);
-            }
-            _ => (),
-        }
-        options.custom_flags(op.flags() as i32 & !libc::O_NOFOLLOW);
-
-        let file = options.open(&inode.fd.procname())?;
+        let file = open_options(op.flags()).open(&inode.fd.procname())?;
         let fh = self.opened_files.insert(Mutex::new(file));
 
         let mut out = OpenOut::default();
@@ -587,29 +609,14 @@ impl Passthrough {
         Ok(buf)
     }
 
-    fn do_write<T>(&self, op: &op::Write<'_>, mut data: T) -> io::Result<WriteOut>
-    where
-        T: BufRead + Unpin,
-    {
+    fn do_write(&self, op: &op::Write<'_>, data: Data<'_>) -> io::Result<WriteOut> {
         let file = self.opened_files.get(op.fh()).ok_or_else(no_entry)?;
-        let mut file = file.lock().unwrap();
-        let file = &mut *file;
-
-        file.seek(io::SeekFrom::Start(op.offset()))?;
-
-        // At here, the data is transferred via the temporary buffer due to
-        // the incompatibility between the I/O abstraction in `futures` and
-        // `tokio`.
-        //
-        // In order to efficiently transfer the large files, both of zero
-        // copying support in `polyfuse` and resolution of impedance mismatch
-        // between `futures::io` and `tokio::io` are required.
-        let mut buf = Vec::with_capacity(op.size() as usize);
-        data.read_to_end(&mut buf)?;
+        let file = file.lock().unwrap();
 
-        let mut buf = &buf[..];
-        let mut buf = (&mut buf).take(op.size() as u64);
-        let written = std::io::copy(&mut buf, &mut *file)?;
+        // The payload is written from the receive buffer without the intermediate copy.
+        let data = data.as_bytes();
+        let data = &data[..std::cmp::min(data.len(), op.size() as usize)];
+        let written = file.write_at(data, op.offset())?;
 
         let mut out = WriteOut::default();
         out.size(written as u32);
@@ -785,9 +792,9 @@ fn fill_attr(attr: &mut FileAttr, st: &libc::stat) {
     attr.rdev(st.st_rdev as u32);
     attr.blksize(st.st_blksize as u32);
     attr.blocks(st.st_blocks as u64);
-    attr.atime(Duration::new(st.st_atime as u64, st.st_atime_nsec as u32));
-    attr.mtime(Duration::new(st.st_mtime as u64, st.st_mtime_nsec as u32));
-    attr.ctime(Duration::new(st.st_ctime as u64, st.st_ctime_nsec as u32));
+    attr.atime_timespec(st.st_atime, st.st_atime_nsec as u32);
+    attr.mtime_timespec(st.st_mtime, st.st_mtime_nsec as u32);
+    attr.ctime_timespec(st.st_ctime, st.st_ctime_nsec as u32);
 }
 
 fn fill_statfs(statfs: &mut Statfs, st: &libc::statvfs) {
@@ -885,6 +892,24 @@ impl VacantEntry<'_> {
     }
 }
 
+fn open_options(flags: u32) -> OpenOptions {
+    let mut options = OpenOptions::new();
+    match (flags & 0x03) as i32 {
+        libc::O_RDONLY => {
+            options.read(true);
+        }
+        libc::O_WRONLY => {
+            options.write(true);
+        }
+        libc::O_RDWR => {
+            options.read(true).write(true);
+        }
+        _ => (),
+    }
+    options.custom_flags(flags as i32 & !libc::O_NOFOLLOW);
+    options
+}
+
 #[inline]
 fn no_entry() -> io::Error {
     io::Error::from_raw_os_error(libc::ENOENT)
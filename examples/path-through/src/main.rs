@@ -26,7 +26,6 @@ use std::{
     io::{self, prelude::*, BufRead},
     os::unix::prelude::*,
     path::{Path, PathBuf},
-    time::Duration,
 };
 
 fn main() -> Result<()> {
@@ -289,7 +288,7 @@ impl PathThrough {
 
         let dir = Slab::get_mut(&mut self.dirs, op.fh() as usize).ok_or_else(invalid_handle)?;
 
-        let mut out = ReaddirOut::new(op.size() as usize);
+        let mut out = ReaddirOut::for_op(op);
         let mut at_least_one_entry = false;
 
         if let Some(entry) = dir.last_entry.take() {
@@ -479,18 +478,9 @@ fn fill_attr(metadata: &Metadata, attr: &mut FileAttr) {
     attr.rdev(metadata.rdev() as u32);
     attr.blksize(metadata.blksize() as u32);
     attr.blocks(metadata.blocks());
-    attr.atime(Duration::new(
-        metadata.atime() as u64,
-        metadata.atime_nsec() as u32,
-    ));
-    attr.mtime(Duration::new(
-        metadata.mtime() as u64,
-        metadata.mtime_nsec() as u32,
-    ));
-    attr.ctime(Duration::new(
-        metadata.ctime() as u64,
-        metadata.ctime_nsec() as u32,
-    ));
+    attr.atime_timespec(metadata.atime(), metadata.atime_nsec() as u32);
+    attr.mtime_timespec(metadata.mtime(), metadata.mtime_nsec() as u32);
+    attr.ctime_timespec(metadata.ctime(), metadata.ctime_nsec() as u32);
 }
 
 // ==== utils ====
@@ -162,7 +162,7 @@ impl Hello {
             return req.reply_error(libc::ENOTDIR);
         }
 
-        let mut out = ReaddirOut::new(op.size() as usize);
+        let mut out = ReaddirOut::for_op(&op);
 
         for (i, entry) in self.dir_entries().skip(op.offset() as usize) {
             let full = out.entry(
@@ -201,12 +201,12 @@ impl AsyncSession {
         use futures::{future::poll_fn, ready, task::Poll};
 
         poll_fn(|cx| loop {
-            match self.inner.get_ref().next_request() {
-                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+            match self.inner.get_ref().try_next_request()? {
+                Poll::Pending => {
                     ready!(self.inner.poll_readable(cx))?;
                     continue;
                 }
-                res => return Poll::Ready(res),
+                Poll::Ready(req) => return Poll::Ready(Ok(req)),
             }
         })
         .await
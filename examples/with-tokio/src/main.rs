@@ -166,7 +166,7 @@ impl Hello {
             return req.reply_error(libc::ENOTDIR);
         }
 
-        let mut out = ReaddirOut::new(op.size() as usize);
+        let mut out = ReaddirOut::for_op(&op);
 
         for (i, entry) in self.dir_entries().skip(op.offset() as usize) {
             let full = out.entry(
@@ -207,14 +207,14 @@ impl AsyncSession {
 
         poll_fn(|cx| {
             let mut guard = ready!(self.inner.poll_read_ready(cx))?;
-            match self.inner.get_ref().next_request() {
-                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+            match self.inner.get_ref().try_next_request()? {
+                Poll::Pending => {
                     guard.clear_ready();
                     Poll::Pending
                 }
-                res => {
+                Poll::Ready(req) => {
                     guard.retain_ready();
-                    Poll::Ready(res)
+                    Poll::Ready(Ok(req))
                 }
             }
         })